@@ -0,0 +1,307 @@
+use crossbeam_channel::Receiver;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::ratelimit;
+use crate::scanner::{
+    Alerts, CancelToken, Checkpoint, ExportFormat, PortScanner, PortState, ScanConfig, ScanDiff,
+    ScanError, ScanEvent, ScanReport,
+};
+
+// Headless runs from the command line, for work that shouldn't depend on the GUI
+// staying open.
+
+const USAGE: &str = "\
+Usage: DeepNet [--resume <checkpoint>] [--rate <pps>] [--bandwidth <bytes/s>]
+       DeepNet --schedule <report> --every <hours> [--notify] [--webhook <url>]
+               [--rate <pps>] [--bandwidth <bytes/s>]
+
+With no arguments the GUI starts.
+
+  --resume <checkpoint>  Carry on with the scan saved in a checkpoint file,
+                         printing results as they come. Ctrl-C stops it and
+                         saves the checkpoint for another go.
+  --schedule <report>    Run the scan a JSON export was made from over and
+                         over, comparing each run with the one before and
+                         alerting when hosts or open ports change. The export
+                         is the first run to compare with, and is replaced by
+                         each new run's results.
+  --every <hours>        How often to start a scheduled run, e.g. 24 or 0.5
+  --notify               Alert with a desktop notification as well as a line
+                         on stderr
+  --webhook <url>        Alert by POSTing the changes as JSON to an http:// URL
+  --rate <pps>           Send at most this many packets a second, 0 for no
+                         limit. Without it the limit the scan was saved with
+                         applies.
+  --bandwidth <bytes/s>  Send at most this many bytes a second, 0 for no limit";
+
+// How often to print a progress line while a phase is under way
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn interrupted(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+// --rate and --bandwidth, overriding the limits saved with the scan.
+#[derive(Debug, Default)]
+struct Limits {
+    packets_per_second: Option<u32>,
+    bytes_per_second: Option<u64>,
+}
+
+impl Limits {
+    // Takes the limit options out of `args`, which may come anywhere.
+    fn parse(args: &[String]) -> Option<(Self, Vec<String>)> {
+        let mut limits = Limits::default();
+        let mut rest = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--rate" => limits.packets_per_second = Some(args.next()?.parse().ok()?),
+                "--bandwidth" => limits.bytes_per_second = Some(args.next()?.parse().ok()?),
+                _ => rest.push(arg.clone()),
+            }
+        }
+        Some((limits, rest))
+    }
+
+    // Records the limits in the scan's settings, so they're saved with its
+    // checkpoints and reports, and puts them in force.
+    fn apply(&self, config: &mut ScanConfig) {
+        if let Some(rate) = self.packets_per_second {
+            config.rate_limit.packets_per_second = (rate > 0).then_some(rate);
+        }
+        if let Some(rate) = self.bytes_per_second {
+            config.rate_limit.bytes_per_second = (rate > 0).then_some(rate);
+        }
+        ratelimit::set_limit(config.rate_limit);
+    }
+}
+
+// Returns the exit code.
+pub fn run(args: &[String]) -> i32 {
+    let Some((limits, args)) = Limits::parse(args) else {
+        eprintln!("{}", USAGE);
+        return 2;
+    };
+    match args.as_slice() {
+        [flag, path] if flag == "--resume" => resume(Path::new(path), &limits),
+        [flag, path, options @ ..] if flag == "--schedule" => match schedule_options(options) {
+            Some((interval, alerts)) => schedule(Path::new(path), interval, &alerts, &limits),
+            None => {
+                eprintln!("{}", USAGE);
+                2
+            }
+        },
+        [flag] if flag == "--help" || flag == "-h" => {
+            println!("{}", USAGE);
+            0
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            2
+        }
+    }
+}
+
+fn resume(path: &Path, limits: &Limits) -> i32 {
+    let mut checkpoint = match Checkpoint::load(path) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+    limits.apply(&mut checkpoint.config);
+    let mut scanner = match PortScanner::from_checkpoint(path, checkpoint) {
+        Ok(scanner) => scanner,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return 1;
+        }
+    };
+
+    let cancel = CancelToken::new();
+    cancel_on_interrupt(&cancel);
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let scan = thread::spawn(move || scanner.scan(tx, &cancel));
+    watch(rx, print_event);
+
+    match scan.join().unwrap() {
+        Ok(()) => 0,
+        Err(ScanError::Cancelled) => {
+            eprintln!("Scan stopped, carry on with --resume {}", path.display());
+            130
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            1
+        }
+    }
+}
+
+// "--every <hours>" and the alerts to raise, in any order.
+fn schedule_options(options: &[String]) -> Option<(Duration, Alerts)> {
+    let mut interval = None;
+    let mut alerts = Alerts {
+        log: true,
+        ..Default::default()
+    };
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--every" => {
+                let hours = options.next()?.parse::<f64>().ok().filter(|&hours| hours > 0.0)?;
+                interval = Some(Duration::try_from_secs_f64(hours * 3600.0).ok()?);
+            }
+            "--notify" => alerts.desktop = true,
+            "--webhook" => alerts.webhook = Some(options.next()?.clone()),
+            _ => return None,
+        }
+    }
+    Some((interval?, alerts))
+}
+
+// Runs until interrupted. A run that fails is reported and tried again next
+// time round; only finished runs are compared and saved.
+fn schedule(path: &Path, interval: Duration, alerts: &Alerts, limits: &Limits) -> i32 {
+    let mut previous = match ScanReport::load(path) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    let mut config = previous.config.clone();
+    // Every run starts afresh rather than resuming the last
+    config.checkpoint = None;
+    limits.apply(&mut config);
+
+    let cancel = CancelToken::new();
+    cancel_on_interrupt(&cancel);
+    loop {
+        let started = Instant::now();
+        eprintln!("Scanning {}", config.target);
+        match scan_report(&config, &cancel) {
+            Ok(report) => {
+                let diff = ScanDiff::new(&previous, &report);
+                if diff.exposure_changed() {
+                    alerts.fire(&diff);
+                    print!("{}", diff);
+                } else {
+                    eprintln!("No change in exposure");
+                }
+                if let Err(e) = report.save(path, ExportFormat::Json) {
+                    eprintln!("Error saving {}: {}", path.display(), e);
+                }
+                previous = report;
+            }
+            Err(ScanError::Cancelled) => break,
+            Err(e) => eprintln!("Scan failed: {}", e),
+        }
+
+        let next = started + interval;
+        while !cancel.is_cancelled() && Instant::now() < next {
+            thread::sleep(Duration::from_millis(200));
+        }
+        if cancel.is_cancelled() {
+            break;
+        }
+    }
+    eprintln!("Schedule stopped");
+    130
+}
+
+// One scheduled run, collected into a report.
+fn scan_report(config: &ScanConfig, cancel: &CancelToken) -> Result<ScanReport, ScanError> {
+    let mut scanner = PortScanner::new(config)?;
+    let mut report = ScanReport::new(config);
+    let (tx, rx) = crossbeam_channel::unbounded();
+    thread::scope(|scope| {
+        let scan = scope.spawn(|| scanner.scan(tx, cancel));
+        watch(rx, |event| report.record(&event));
+        scan.join().unwrap()
+    })?;
+    report.finished = Some(SystemTime::now());
+    Ok(report)
+}
+
+// Hand each event to `each` as it comes, printing a progress line every so
+// often and one as each phase ends.
+fn watch<F: FnMut(ScanEvent)>(rx: Receiver<ScanEvent>, mut each: F) {
+    let mut last_progress = Instant::now();
+    let mut finished = None;
+    for event in rx {
+        if let ScanEvent::Progress(progress) = &event {
+            if progress.done >= progress.total {
+                if finished != Some(progress.phase) {
+                    eprintln!("{}", progress);
+                }
+                finished = Some(progress.phase);
+            } else if last_progress.elapsed() >= PROGRESS_INTERVAL {
+                eprintln!("{}", progress);
+                last_progress = Instant::now();
+            }
+            continue;
+        }
+        each(event);
+    }
+}
+
+// Ctrl-C stops the scan the way the GUI's stop button does, rather than killing
+// the process before it can save.
+fn cancel_on_interrupt(cancel: &CancelToken) {
+    unsafe {
+        libc::signal(libc::SIGINT, interrupted as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+    let cancel = cancel.clone();
+    thread::spawn(move || {
+        while !INTERRUPTED.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        cancel.cancel();
+    });
+}
+
+// Live hosts, open ports and the services on them.
+fn print_event(event: ScanEvent) {
+    match event {
+        ScanEvent::Host(host) if host.up => {
+            let mut line = format!("Host {}", host.address);
+            if let Some(hostname) = &host.hostname {
+                line += &format!(" ({})", hostname);
+            }
+            line += &format!(" is up: {}", host.reason);
+            if let Some(os) = &host.os {
+                line += &format!(", OS {} ({}%)", os.name, os.confidence);
+            }
+            println!("{}", line);
+        }
+        ScanEvent::Port(result) if result.state == PortState::Open => {
+            println!(
+                "{}\t{}/{}\t{}\t{}\t{}",
+                result.host, result.port, result.protocol, result.state, result.service, result.reason
+            );
+        }
+        ScanEvent::Service(result) => {
+            let details: Vec<&str> = [&result.product, &result.version, &result.info]
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect();
+            println!(
+                "{}\t{}/{}\t{}\t{}",
+                result.host,
+                result.port,
+                result.protocol,
+                result.service,
+                details.join(" ")
+            );
+        }
+        _ => {}
+    }
+}
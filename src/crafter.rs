@@ -1,35 +1,44 @@
-use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::ipv4::{Ipv4Packet, MutableIpv4Packet};
-use pnet::packet::tcp::{TcpPacket, MutableTcpPacket, TcpFlags};
-use pnet::packet::udp::{UdpPacket, MutableUdpPacket};
-use pnet::packet::icmp::{IcmpPacket, MutableIcmpPacket, IcmpTypes, echo_request::MutableEchoRequestPacket};
-use pnet::packet::ethernet::{EthernetPacket, MutableEthernetPacket};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::MutableIpv4Packet;
+use pnet::packet::ipv6::MutableIpv6Packet;
+use pnet::packet::tcp::{MutableTcpPacket, TcpFlags};
+use pnet::packet::udp::MutableUdpPacket;
+use pnet::packet::icmp::{self, IcmpPacket, IcmpTypes, echo_request::MutableEchoRequestPacket};
+use pnet::packet::icmpv6::{self, Icmpv6Types, MutableIcmpv6Packet};
+use pnet::packet::ethernet::{EtherTypes, MutableEthernetPacket};
+use pnet::packet::util;
 use pnet::packet::Packet;
-use std::net::Ipv4Addr;
+use pnet_datalink::NetworkInterface;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
+use super::oui;
+use super::ratelimit;
 use super::widgets::{packet_crafter::Protocol};
 
+const ETHERNET_HEADER_LEN: usize = 14;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+
 pub struct PacketCrafter {
-    source_ip: Ipv4Addr,
-    dest_ip: Ipv4Addr,
+    source_ip: IpAddr,
+    dest_ip: IpAddr,
     source_port: u16,
     dest_port: u16,
     protocol: Protocol,
     payload: String,
     count: u32,
-    delay: u32,
 }
 
 impl PacketCrafter {
     pub fn new(
-        source_ip: Ipv4Addr,
-        dest_ip: Ipv4Addr,
+        source_ip: IpAddr,
+        dest_ip: IpAddr,
         source_port: u16,
         dest_port: u16,
         protocol: Protocol,
         payload: String,
         count: u32,
-        delay: u32,
     ) -> Self {
         Self {
             source_ip,
@@ -39,112 +48,177 @@ impl PacketCrafter {
             protocol,
             payload,
             count,
-            delay,
         }
     }
     
-    pub fn craft_and_send(&mut self) {
-        let interfaces = pnet_datalink::interfaces();
-        let interface = interfaces.iter()
-            .find(|iface| iface.is_up() && !iface.is_loopback() && !iface.ips.is_empty())
-            .expect("No suitable interface found");
+    // The interface that owns the source address, else the first one that's up.
+    // Either way a real network card, whose MAC is registered to a vendor, wins
+    // over virtual ones like bridges and tunnels.
+    pub fn pick_interface(&self) -> io::Result<NetworkInterface> {
+        pnet_datalink::interfaces()
+            .into_iter()
+            .filter(|iface| iface.is_up() && !iface.is_loopback() && !iface.ips.is_empty())
+            .min_by_key(|iface| {
+                let owns_source = iface.ips.iter().any(|network| network.ip() == self.source_ip);
+                let registered = iface.mac.and_then(oui::vendor).is_some();
+                (!owns_source, !registered)
+            })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No suitable interface found"))
+    }
+    
+    // Blocks while the rate limit holds packets back, so run it off the UI
+    // thread. `sent` hears how many packets have gone out after each one.
+    pub fn craft_and_send<F: FnMut(u32)>(&mut self, mut sent: F) -> io::Result<()> {
+        let interface = self.pick_interface()?;
+        let mac = interface.mac.unwrap_or_default();
 
-        let (mut tx, _) = match pnet_datalink::channel(&interface, Default::default()) {
-            Ok(pnet_datalink::Channel::Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => panic!("Unsupported channel type"),
-            Err(e) => panic!("Error creating channel: {}", e),
+        let (mut tx, _) = match pnet_datalink::channel(&interface, Default::default())? {
+            pnet_datalink::Channel::Ethernet(tx, rx) => (tx, rx),
+            _ => return Err(io::Error::other("Unsupported channel type")),
         };
         
         for i in 0..self.count {
-            let mut ethernet_buffer = [0u8; 42];
-            let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap();
-            
-            // Build Ethernet frame
-            ethernet_packet.set_destination(interface.mac.unwrap());
-            ethernet_packet.set_source(interface.mac.unwrap());
-            ethernet_packet.set_ethertype(pnet::packet::ethernet::EtherTypes::Ipv4);
-            
-            // Build IP packet
-            let mut ip_buffer = [0u8; 20];
-            let mut ip_packet = MutableIpv4Packet::new(&mut ip_buffer).unwrap();
-            
-            ip_packet.set_version(4);
-            ip_packet.set_header_length(5);
-            ip_packet.set_total_length(20);
-            ip_packet.set_ttl(64);
-            ip_packet.set_source(self.source_ip);
-            ip_packet.set_destination(self.dest_ip);
-            
-            // Build transport layer packet based on protocol
-            match self.protocol {
-                Protocol::Tcp => {
-                    ip_packet.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
-                    ip_packet.set_total_length(40);
-                    
-                    let mut tcp_buffer = [0u8; 20];
-                    let mut tcp_packet = MutableTcpPacket::new(&mut tcp_buffer).unwrap();
-                    
-                    tcp_packet.set_source(self.source_port);
-                    tcp_packet.set_destination(self.dest_port);
-                    tcp_packet.set_flags(TcpFlags::SYN);
-                    tcp_packet.set_sequence(12345);
-                    tcp_packet.set_window(64240);
-                    
-                    // Calculate checksums
-                    tcp_packet.set_checksum(0);
-                    let checksum = pnet::packet::tcp::ipv4_checksum(
-                        &tcp_packet.to_immutable(),
-                        &self.source_ip,
-                        &self.dest_ip,
-                    );
-                    tcp_packet.set_checksum(checksum);
-                    
-                    ip_packet.set_payload(tcp_packet.packet());
+            let (ethertype, ip_packet) = match (self.source_ip, self.dest_ip) {
+                (IpAddr::V4(source), IpAddr::V4(destination)) => {
+                    (EtherTypes::Ipv4, self.build_ipv4(source, destination))
                 }
-                Protocol::Udp => {
-                    ip_packet.set_next_level_protocol(IpNextHeaderProtocols::Udp);
-                    ip_packet.set_total_length(28);
-                    
-                    let mut udp_buffer = [0u8; 8];
-                    let mut udp_packet = MutableUdpPacket::new(&mut udp_buffer).unwrap();
-                    
-                    udp_packet.set_source(self.source_port);
-                    udp_packet.set_destination(self.dest_port);
-                    udp_packet.set_length(8);
-                    udp_packet.set_checksum(0);
-                    
-                    ip_packet.set_payload(udp_packet.packet());
+                (IpAddr::V6(source), IpAddr::V6(destination)) => {
+                    (EtherTypes::Ipv6, self.build_ipv6(source, destination))
                 }
-                Protocol::Icmp => {
-                    ip_packet.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
-                    ip_packet.set_total_length(28);
-                    
-                    let mut icmp_buffer = [0u8; 8];
-                    let mut icmp_packet = MutableEchoRequestPacket::new(&mut icmp_buffer).unwrap();
-                    
-                    icmp_packet.set_icmp_type(IcmpTypes::EchoRequest);
-                    icmp_packet.set_identifier(1234);
-                    icmp_packet.set_sequence_number(1);
-                    
-                    ip_packet.set_payload(icmp_packet.packet());
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "Source and destination must both be IPv4 or both be IPv6",
+                    ));
                 }
-                Protocol::Raw => {
-                    ip_packet.set_next_level_protocol(IpNextHeaderProtocols::Tcp);
-                    ip_packet.set_total_length(20 + self.payload.len() as u16);
-                    ip_packet.set_payload(self.payload.as_bytes());
-                }
-            }
-            
-            // Set IP checksum
-            ip_packet.set_checksum(0);
-            let checksum = pnet::packet::ipv4::checksum(&ip_packet.to_immutable());
-            ip_packet.set_checksum(checksum);
+            };
             
-            // Combine packets
-            ethernet_packet.set_payload(ip_packet.packet());
+            // Build Ethernet frame
+            let mut ethernet_buffer = vec![0u8; ETHERNET_HEADER_LEN + ip_packet.len()];
+            let mut ethernet_packet = MutableEthernetPacket::new(&mut ethernet_buffer).unwrap();
+            ethernet_packet.set_destination(mac);
+            ethernet_packet.set_source(mac);
+            ethernet_packet.set_ethertype(ethertype);
+            ethernet_packet.set_payload(&ip_packet);
             
             // Send packet
-            tx.send_to(ethernet_packet.packet(), None).unwrap().unwrap();
+            ratelimit::acquire(ethernet_packet.packet().len());
+            tx.send_to(ethernet_packet.packet(), None)
+                .unwrap_or_else(|| Err(io::Error::other("Failed to send packet")))?;
+            sent(i + 1);
+        }
+        
+        Ok(())
+    }
+    
+    fn build_ipv4(&self, source: Ipv4Addr, destination: Ipv4Addr) -> Vec<u8> {
+        let (protocol, transport) = match self.protocol {
+            Protocol::Icmp => {
+                let mut icmp_buffer = [0u8; 8];
+                let mut icmp_packet = MutableEchoRequestPacket::new(&mut icmp_buffer).unwrap();
+                
+                icmp_packet.set_icmp_type(IcmpTypes::EchoRequest);
+                icmp_packet.set_identifier(1234);
+                icmp_packet.set_sequence_number(1);
+                let checksum = icmp::checksum(&IcmpPacket::new(icmp_packet.packet()).unwrap());
+                icmp_packet.set_checksum(checksum);
+                
+                (IpNextHeaderProtocols::Icmp, icmp_buffer.to_vec())
+            }
+            _ => self.build_transport(|packet, skipword, protocol| {
+                util::ipv4_checksum(packet, skipword, &[], &source, &destination, protocol)
+            }),
+        };
+        
+        let mut ip_buffer = vec![0u8; IPV4_HEADER_LEN + transport.len()];
+        let mut ip_packet = MutableIpv4Packet::new(&mut ip_buffer).unwrap();
+        
+        ip_packet.set_version(4);
+        ip_packet.set_header_length(5);
+        ip_packet.set_total_length((IPV4_HEADER_LEN + transport.len()) as u16);
+        ip_packet.set_ttl(64);
+        ip_packet.set_next_level_protocol(protocol);
+        ip_packet.set_source(source);
+        ip_packet.set_destination(destination);
+        ip_packet.set_payload(&transport);
+        
+        // Set IP checksum
+        let checksum = pnet::packet::ipv4::checksum(&ip_packet.to_immutable());
+        ip_packet.set_checksum(checksum);
+        
+        ip_buffer
+    }
+    
+    fn build_ipv6(&self, source: Ipv6Addr, destination: Ipv6Addr) -> Vec<u8> {
+        let (protocol, transport) = match self.protocol {
+            Protocol::Icmp => {
+                let mut icmp_buffer = [0u8; 8];
+                let mut icmp_packet = MutableIcmpv6Packet::new(&mut icmp_buffer).unwrap();
+                
+                icmp_packet.set_icmpv6_type(Icmpv6Types::EchoRequest);
+                // Identifier and sequence number, as for ICMP
+                icmp_packet.set_payload(&[0x04, 0xd2, 0x00, 0x01]);
+                let checksum = icmpv6::checksum(&icmp_packet.to_immutable(), &source, &destination);
+                icmp_packet.set_checksum(checksum);
+                
+                (IpNextHeaderProtocols::Icmpv6, icmp_buffer.to_vec())
+            }
+            _ => self.build_transport(|packet, skipword, protocol| {
+                util::ipv6_checksum(packet, skipword, &[], &source, &destination, protocol)
+            }),
+        };
+        
+        let mut ip_buffer = vec![0u8; IPV6_HEADER_LEN + transport.len()];
+        let mut ip_packet = MutableIpv6Packet::new(&mut ip_buffer).unwrap();
+        
+        ip_packet.set_version(6);
+        ip_packet.set_payload_length(transport.len() as u16);
+        ip_packet.set_next_header(protocol);
+        ip_packet.set_hop_limit(64);
+        ip_packet.set_source(source);
+        ip_packet.set_destination(destination);
+        ip_packet.set_payload(&transport);
+        
+        ip_buffer
+    }
+    
+    // The TCP, UDP or raw payload. `checksum` covers the pseudo-header of whichever
+    // IP version is being built and skips the given 16-bit word.
+    fn build_transport<F>(&self, checksum: F) -> (IpNextHeaderProtocol, Vec<u8>)
+    where
+        F: Fn(&[u8], usize, IpNextHeaderProtocol) -> u16,
+    {
+        match self.protocol {
+            Protocol::Tcp => {
+                let mut tcp_buffer = [0u8; 20];
+                let mut tcp_packet = MutableTcpPacket::new(&mut tcp_buffer).unwrap();
+                
+                tcp_packet.set_source(self.source_port);
+                tcp_packet.set_destination(self.dest_port);
+                tcp_packet.set_data_offset(5);
+                tcp_packet.set_flags(TcpFlags::SYN);
+                tcp_packet.set_sequence(12345);
+                tcp_packet.set_window(64240);
+                let checksum = checksum(tcp_packet.packet(), 8, IpNextHeaderProtocols::Tcp);
+                tcp_packet.set_checksum(checksum);
+                
+                (IpNextHeaderProtocols::Tcp, tcp_buffer.to_vec())
+            }
+            Protocol::Udp => {
+                let mut udp_buffer = [0u8; 8];
+                let mut udp_packet = MutableUdpPacket::new(&mut udp_buffer).unwrap();
+                
+                udp_packet.set_source(self.source_port);
+                udp_packet.set_destination(self.dest_port);
+                udp_packet.set_length(8);
+                let checksum = checksum(udp_packet.packet(), 3, IpNextHeaderProtocols::Udp);
+                udp_packet.set_checksum(checksum);
+                
+                (IpNextHeaderProtocols::Udp, udp_buffer.to_vec())
+            }
+            Protocol::Icmp | Protocol::Raw => {
+                (IpNextHeaderProtocols::Tcp, self.payload.as_bytes().to_vec())
+            }
         }
     }
 }
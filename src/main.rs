@@ -1,10 +1,15 @@
 use crate::widgets::{
     packet_crafter::PacketCrafter, packet_sniffer::PacketSniffer, port_scanner::PortScanner,
 };
+use crate::ratelimit::RateLimit;
 use eframe::egui;
 
+mod cli;
 mod crafter;
+mod oui;
+mod ratelimit;
 mod scanner;
+mod services;
 mod sniffer;
 mod utils;
 mod widgets;
@@ -15,6 +20,9 @@ struct DeepNetApp {
     packet_crafter: PacketCrafter,
     packet_sniffer: PacketSniffer,
     active_tab: Tab,
+    // Zero for unlimited
+    max_packet_rate: u32,
+    max_byte_rate: u64,
 }
 
 #[derive(PartialEq, Eq, Default)]
@@ -39,11 +47,18 @@ impl DeepNetApp {
 
 impl eframe::App for DeepNetApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.port_scanner.background();
+
         egui::TopBottomPanel::top("header").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 ui.heading("DeepNet - Advanced Network Toolkit");
                 ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                     ui.label("v0.1.0");
+                    ui.separator();
+                    ui.add(egui::DragValue::new(&mut self.max_byte_rate).suffix(" B/s"));
+                    ui.add(egui::DragValue::new(&mut self.max_packet_rate).suffix(" pps"));
+                    ui.label("Rate limit (0 = none):")
+                        .on_hover_text("Shared by every scan and the packet crafter");
                 });
             });
         });
@@ -62,12 +77,23 @@ impl eframe::App for DeepNetApp {
             Tab::Sniffer => self.packet_sniffer.ui(ui),
         });
 
+        ratelimit::set_limit(RateLimit {
+            packets_per_second: (self.max_packet_rate > 0).then_some(self.max_packet_rate),
+            bytes_per_second: (self.max_byte_rate > 0).then_some(self.max_byte_rate),
+        });
+
         // Update sniffers and scanners in the background
         ctx.request_repaint();
     }
 }
 
 fn main() -> eframe::Result<()> {
+    // Any arguments mean a run from the command line, without the GUI
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&args));
+    }
+
     let options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(1200.0, 800.0)),
         vsync: false,
@@ -0,0 +1,132 @@
+use pnet::util::MacAddr;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+// Who made a network card, from the prefix of its MAC address. The IEEE
+// Registration Authority hands out prefixes in three sizes: 24 bits (MA-L, the
+// classic OUI), 28 bits (MA-M) and 36 bits (MA-S, and the older IAB blocks),
+// the smaller blocks carved out of OUIs registered to the authority itself.
+//
+// A snapshot of well-known vendors ships with DeepNet. For the full registry,
+// download IEEE's CSV files into the config directory, e.g.
+// ~/.config/deepnet/oui.csv from https://standards-oui.ieee.org/oui/oui.csv,
+// mam.csv from .../oui28/mam.csv and oui36.csv from .../oui36/oui36.csv.
+// Entries there take precedence over the snapshot.
+
+const BUNDLED: &str = include_str!("oui-registry");
+
+const REGISTRY_FILES: [&str; 4] = ["oui.csv", "mam.csv", "oui36.csv", "iab.csv"];
+
+// Longest first, so a block carved out of an OUI wins over the OUI itself
+const PREFIX_BITS: [u32; 3] = [36, 28, 24];
+
+static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+#[derive(Default)]
+struct Registry {
+    // Vendor names by prefix length in bits and prefix
+    vendors: HashMap<(u32, u64), String>,
+}
+
+impl Registry {
+    fn load() -> Self {
+        let mut registry = Registry::default();
+        registry.parse(BUNDLED);
+        let Some(dir) = config_dir() else { return registry };
+        for name in REGISTRY_FILES {
+            let path = dir.join(name);
+            match fs::read_to_string(&path) {
+                Ok(contents) => registry.parse(&contents),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("Error reading {}: {}", path.display(), e),
+            }
+        }
+        registry
+    }
+
+    // IEEE's CSV layout: Registry,Assignment,Organization Name,Organization Address
+    fn parse(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let fields = csv_fields(line);
+            let [registry, assignment, name, ..] = fields.as_slice() else { continue };
+            let bits = match registry.as_str() {
+                "MA-L" => 24,
+                "MA-M" => 28,
+                "MA-S" | "IAB" => 36,
+                // The header, or a registry that isn't about MAC addresses
+                _ => continue,
+            };
+            let name = name.trim();
+            if assignment.len() != bits as usize / 4 || name.is_empty() {
+                continue;
+            }
+            if let Ok(prefix) = u64::from_str_radix(assignment, 16) {
+                self.vendors.insert((bits, prefix), name.to_string());
+            }
+        }
+    }
+}
+
+pub fn vendor(mac: MacAddr) -> Option<&'static str> {
+    let octets = [mac.0, mac.1, mac.2, mac.3, mac.4, mac.5];
+    // Locally administered addresses (randomized, virtual, broadcast) aren't
+    // registered with anyone
+    if octets[0] & 0x02 != 0 {
+        return None;
+    }
+    // Group addresses use the owner's prefix with the multicast bit set
+    let address = octets.iter().fold(0u64, |address, &octet| address << 8 | octet as u64) & !(1 << 40);
+
+    let registry = REGISTRY.get_or_init(Registry::load);
+    PREFIX_BITS.iter().find_map(|&bits| {
+        registry
+            .vendors
+            .get(&(bits, address >> (48 - bits)))
+            .map(String::as_str)
+    })
+}
+
+// e.g. "b8:27:eb:12:34:56 (Raspberry Pi Foundation)"
+pub fn describe(mac: MacAddr) -> String {
+    if mac == MacAddr::broadcast() {
+        return format!("{} (Broadcast)", mac);
+    }
+    match vendor(mac) {
+        Some(vendor) => format!("{} ({})", mac, vendor),
+        None => mac.to_string(),
+    }
+}
+
+// Where the user's copies of the registry live
+fn config_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("deepnet"))
+}
+
+// Splits a CSV line, honouring quoted fields with commas and doubled quotes.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A process-wide limit on how fast we put packets on the wire, in packets and
+// bytes per second. Everything that sends probes or crafted packets takes its
+// tokens from the same buckets, however many threads are doing the sending.
+
+// How much sending may bunch up after a quiet spell, as a fraction of a second
+const BURST: f64 = 0.05;
+
+static BUCKETS: Mutex<Buckets> = Mutex::new(Buckets {
+    limit: RateLimit {
+        packets_per_second: None,
+        bytes_per_second: None,
+    },
+    packets: None,
+    bytes: None,
+});
+
+// None means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub packets_per_second: Option<u32>,
+    pub bytes_per_second: Option<u64>,
+}
+
+struct Buckets {
+    limit: RateLimit,
+    packets: Option<Bucket>,
+    bytes: Option<Bucket>,
+}
+
+struct Bucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64) -> Self {
+        let capacity = (rate * BURST).max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    // Take `cost` tokens and return how long to wait before sending. The bucket
+    // may go into debt, so a packet bigger than the burst still gets through and
+    // whoever comes next waits it off.
+    fn take(&mut self, cost: f64, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity) - cost;
+        self.updated = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.rate)
+        }
+    }
+}
+
+pub fn limit() -> RateLimit {
+    BUCKETS.lock().unwrap().limit
+}
+
+pub fn set_limit(limit: RateLimit) {
+    let mut buckets = BUCKETS.lock().unwrap();
+    if buckets.limit == limit {
+        return;
+    }
+    buckets.limit = limit;
+    buckets.packets = limit
+        .packets_per_second
+        .filter(|&rate| rate > 0)
+        .map(|rate| Bucket::new(rate as f64));
+    buckets.bytes = limit
+        .bytes_per_second
+        .filter(|&rate| rate > 0)
+        .map(|rate| Bucket::new(rate as f64));
+}
+
+// Block until a packet of `length` bytes may be sent.
+pub fn acquire(length: usize) {
+    let wait = {
+        let mut buckets = BUCKETS.lock().unwrap();
+        let now = Instant::now();
+        let mut wait = Duration::ZERO;
+        if let Some(bucket) = &mut buckets.packets {
+            wait = wait.max(bucket.take(1.0, now));
+        }
+        if let Some(bucket) = &mut buckets.bytes {
+            wait = wait.max(bucket.take(length as f64, now));
+        }
+        wait
+    };
+    if !wait.is_zero() {
+        thread::sleep(wait);
+    }
+}
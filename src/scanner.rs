@@ -1,118 +0,0 @@
-use pnet::packet::ethernet::{EthernetPacket, EtherTypes};
-use pnet::packet::ip::IpNextHeaderProtocols;
-use pnet::packet::ipv4::Ipv4Packet;
-use pnet::packet::tcp::{TcpPacket, TcpFlags};
-use pnet::packet::udp::UdpPacket;
-use pnet::packet::Packet;
-use std::net::{IpAddr, Ipv4Addr};
-use std::sync::mpsc::Sender;
-use std::thread;
-use std::time::Duration;
-use crossbeam_channel::Sender as CrossbeamSender;
-
-#[derive(PartialEq, Debug, Clone, Copy)]
-pub enum ScanType {
-    TcpSyn,
-    TcpConnect,
-    Udp,
-}
-
-pub struct PortScanner {
-    target_ip: Ipv4Addr,
-    port_range: (u16, u16),
-    scan_type: ScanType,
-    threads: usize,
-    results: Vec<(u16, String)>,
-}
-
-impl PortScanner {
-    pub fn new(target: &str, port_range: (u16, u16), scan_type: ScanType, threads: usize) -> Self {
-        let target_ip = target.parse::<Ipv4Addr>().unwrap_or_else(|_| {
-            // DNS resolution would go here in a real implementation
-            Ipv4Addr::new(127, 0, 0, 1)
-        });
-        
-        Self {
-            target_ip,
-            port_range,
-            scan_type,
-            threads,
-            results: Vec::new(),
-        }
-    }
-    
-    pub fn scan(&mut self, tx: CrossbeamSender<(u16, String)>) {
-        match self.scan_type {
-            ScanType::TcpSyn => self.syn_scan(tx),
-            ScanType::TcpConnect => self.connect_scan(tx),
-            ScanType::Udp => self.udp_scan(tx),
-        }
-    }
-    
-    fn syn_scan(&mut self, tx: CrossbeamSender<(u16, String)>) {
-        let interfaces = pnet_datalink::interfaces();
-        let interface = interfaces.iter()
-            .find(|iface| iface.is_up() && !iface.is_loopback() && !iface.ips.is_empty())
-            .expect("No suitable interface found");
-        
-        let (_, mut rx) = match pnet_datalink::channel(&interface, Default::default()) {
-            Ok(pnet_datalink::Channel::Ethernet(tx, rx)) => (tx, rx),
-            Ok(_) => panic!("Unsupported channel type"),
-            Err(e) => panic!("Error creating channel: {}", e),
-        };
-        
-        // SYN scan implementation would go here
-        // This is a simplified placeholder
-        
-        let total_ports = (self.port_range.1 - self.port_range.0 + 1) as usize;
-        let ports_per_thread = total_ports / self.threads;
-        
-        let mut handles = vec![];
-        
-        for i in 0..self.threads {
-            let start_port = self.port_range.0 + (i as u16 * ports_per_thread as u16);
-            let end_port = if i == self.threads - 1 {
-                self.port_range.1
-            } else {
-                start_port + ports_per_thread as u16 - 1
-            };
-            
-            let target_ip = self.target_ip;
-            let tx = tx.clone();
-            
-            handles.push(thread::spawn(move || {
-                for port in start_port..=end_port {
-                    // In a real implementation, we would craft and send SYN packets
-                    // and listen for SYN-ACK responses
-                    
-                    // Simulate finding open ports
-                    let status = if port % 10 == 0 {
-                        "Open".to_string()
-                    } else {
-                        "Closed".to_string()
-                    };
-                    
-                    tx.send((port, status)).unwrap();
-                    thread::sleep(Duration::from_millis(10));
-                }
-            }));
-        }
-        
-        for handle in handles {
-            handle.join().unwrap();
-        }
-    }
-    
-    fn connect_scan(&mut self, tx: CrossbeamSender<(u16, String)>) {
-        // TCP Connect scan implementation
-        // Placeholder - similar to SYN scan but using OS TCP stack
-    }
-    
-    fn udp_scan(&mut self, tx: CrossbeamSender<(u16, String)>) {
-        // UDP scan implementation
-    }
-    
-    pub fn get_results(&self) -> Vec<(u16, String)> {
-        self.results.clone()
-    }
-}
\ No newline at end of file
@@ -0,0 +1,105 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::diff::ScanDiff;
+use super::report;
+
+// Telling someone when a recurring scan finds hosts more or less exposed than
+// the run before it did.
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Default)]
+pub struct Alerts {
+    // A line on stderr
+    pub log: bool,
+    // Through the desktop's notification service, with notify-send
+    pub desktop: bool,
+    // An http:// URL to POST the summary and the diff to, as JSON
+    pub webhook: Option<String>,
+}
+
+impl Alerts {
+    // Notifications and webhooks go out from a thread of their own, so a slow
+    // receiver doesn't hold up the next scan or the GUI.
+    pub fn fire(&self, diff: &ScanDiff) {
+        let summary = diff.summary();
+        if self.log {
+            eprintln!(
+                "{}: exposure changed: {}",
+                report::time_string(SystemTime::now()),
+                summary
+            );
+        }
+        if !self.desktop && self.webhook.is_none() {
+            return;
+        }
+
+        let body = serde_json::json!({ "summary": summary, "diff": diff }).to_string();
+        let desktop = self.desktop;
+        let webhook = self.webhook.clone();
+        thread::spawn(move || {
+            if desktop && let Err(e) = notify(&summary) {
+                eprintln!("Error showing notification: {}", e);
+            }
+            if let Some(url) = webhook
+                && let Err(e) = post(&url, &body)
+            {
+                eprintln!("Error calling webhook {}: {}", url, e);
+            }
+        });
+    }
+}
+
+fn notify(summary: &str) -> io::Result<()> {
+    let status = Command::new("notify-send")
+        .args(["DeepNet: exposure changed", summary])
+        .status()
+        .map_err(|e| io::Error::new(e.kind(), format!("notify-send: {}", e)))?;
+    if !status.success() {
+        return Err(io::Error::other(format!("notify-send {}", status)));
+    }
+    Ok(())
+}
+
+// Plain HTTP/1.1 is all a webhook on our own network needs, e.g.
+// http://127.0.0.1:8080/alerts. Any 2xx answer counts as delivered.
+fn post(url: &str, body: &str) -> io::Result<()> {
+    let rest = url.strip_prefix("http://").ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "only http:// URLs are supported")
+    })?;
+    let (authority, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    // Port 80 unless one is given
+    let address = authority
+        .to_socket_addrs()
+        .or_else(|_| (authority.trim_start_matches('[').trim_end_matches(']'), 80).to_socket_addrs())?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for host"))?;
+
+    let mut stream = TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT)?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        authority,
+        body.len(),
+        body
+    )?;
+
+    let mut response = Vec::new();
+    stream.take(4096).read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!("answered \"{}\"", status))),
+    }
+}
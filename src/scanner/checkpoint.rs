@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::result::{HostStatus, PortResult, ScanEvent};
+use super::ScanConfig;
+
+// Checkpoints let a scan that was stopped, or died along with the process, carry
+// on where it left off. They're JSON, rewritten in full every so often while the
+// scan runs and once more when it ends.
+
+// How often a running scan saves its checkpoint
+const SAVE_INTERVAL: Duration = Duration::from_secs(15);
+// Bumped whenever the layout changes in a way older checkpoints can't be read as
+const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    Version(u32),
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckpointError::Io(e) => write!(f, "Error reading checkpoint: {}", e),
+            CheckpointError::Format(e) => write!(f, "Invalid checkpoint: {}", e),
+            CheckpointError::Version(version) => {
+                write!(f, "Unsupported checkpoint version {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CheckpointError {}
+
+// Everything needed to pick a scan up again: what it was asked to do and what
+// it has done.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub config: ScanConfig,
+    // Every target as discovery found it, with later details such as hostnames
+    // and OS guesses filled in; None until discovery has finished
+    pub hosts: Option<Vec<HostStatus>>,
+    // Every port scanned so far, with service details once detected
+    pub ports: Vec<PortResult>,
+    // Hosts OS detection has finished with
+    pub os_detected: Vec<IpAddr>,
+    // Open ports service detection has finished with, identified or not
+    pub services_detected: Vec<(IpAddr, u16)>,
+}
+
+impl Checkpoint {
+    pub fn new(config: &ScanConfig) -> Self {
+        Self {
+            version: VERSION,
+            config: config.clone(),
+            hosts: None,
+            ports: Vec::new(),
+            os_detected: Vec::new(),
+            services_detected: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, CheckpointError> {
+        // Check the version first, other versions needn't parse as this one
+        #[derive(Deserialize)]
+        struct Header {
+            version: u32,
+        }
+
+        let data = fs::read(path).map_err(CheckpointError::Io)?;
+        let header: Header = serde_json::from_slice(&data).map_err(CheckpointError::Format)?;
+        if header.version != VERSION {
+            return Err(CheckpointError::Version(header.version));
+        }
+        serde_json::from_slice(&data).map_err(CheckpointError::Format)
+    }
+
+    // Written next to the old checkpoint and moved over it, so dying part way
+    // through never leaves a truncated file behind.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+
+        fs::write(&partial, serde_json::to_vec(self)?)?;
+        fs::rename(&partial, path)
+    }
+}
+
+// Keeps the checkpoint of a running scan up to date as its events go by, and
+// saves it every SAVE_INTERVAL.
+pub(super) struct Recorder {
+    path: PathBuf,
+    state: Mutex<(Checkpoint, Instant)>,
+}
+
+impl Recorder {
+    pub fn new(path: &Path, checkpoint: Checkpoint) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            state: Mutex::new((checkpoint, Instant::now())),
+        }
+    }
+
+    pub fn discovered(&self, hosts: &[HostStatus]) {
+        self.update(|checkpoint| checkpoint.hosts = Some(hosts.to_vec()));
+    }
+
+    pub fn record(&self, event: &ScanEvent) {
+        self.update(|checkpoint| match event {
+            ScanEvent::Host(host) => {
+                let hosts = checkpoint.hosts.get_or_insert_with(Vec::new);
+                match hosts.iter_mut().find(|existing| existing.address == host.address) {
+                    Some(existing) => *existing = host.clone(),
+                    None => hosts.push(host.clone()),
+                }
+            }
+            ScanEvent::Port(result) => checkpoint.ports.push(result.clone()),
+            ScanEvent::Service(update) => {
+                let existing = checkpoint.ports.iter_mut().find(|result| {
+                    result.host == update.host
+                        && result.port == update.port
+                        && result.protocol == update.protocol
+                });
+                match existing {
+                    Some(existing) => *existing = update.clone(),
+                    None => checkpoint.ports.push(update.clone()),
+                }
+            }
+            ScanEvent::Progress(_) => {}
+        });
+    }
+
+    pub fn os_detected(&self, address: IpAddr) {
+        self.update(|checkpoint| checkpoint.os_detected.push(address));
+    }
+
+    pub fn service_detected(&self, address: IpAddr, port: u16) {
+        self.update(|checkpoint| checkpoint.services_detected.push((address, port)));
+    }
+
+    // Save now, whether or not it's due.
+    pub fn save(&self) {
+        let mut state = self.state.lock().unwrap();
+        self.write(&mut state);
+    }
+
+    fn update<F: FnOnce(&mut Checkpoint)>(&self, change: F) {
+        let mut state = self.state.lock().unwrap();
+        change(&mut state.0);
+        if state.1.elapsed() >= SAVE_INTERVAL {
+            self.write(&mut state);
+        }
+    }
+
+    fn write(&self, (checkpoint, saved): &mut (Checkpoint, Instant)) {
+        if let Err(e) = checkpoint.save(&self.path) {
+            eprintln!("Error saving checkpoint {}: {}", self.path.display(), e);
+        }
+        *saved = Instant::now();
+    }
+}
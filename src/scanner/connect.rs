@@ -0,0 +1,215 @@
+use crossbeam_channel::Sender as CrossbeamSender;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use crate::ratelimit;
+
+use super::progress::{CancelToken, ProgressMeter};
+use super::result::{PortResult, PortState, Reason, ScanEvent, Transport};
+use super::target::Target;
+use super::{HostPorts, PortScanner};
+
+// Upper bound on a single poll() so expired connections are noticed promptly
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// What the kernel's SYN comes to with its options, for the rate limiter
+pub(super) const SYN_LEN_V4: usize = 60;
+pub(super) const SYN_LEN_V6: usize = 80;
+
+struct PendingConnect {
+    address: SocketAddr,
+    socket: Socket,
+    started: Instant,
+}
+
+enum ConnectOutcome {
+    InProgress(Socket),
+    Done(PortState, Reason),
+}
+
+impl PortScanner {
+    // Full three-way handshakes through the OS TCP stack, so no privileges are
+    // needed. Sockets are non-blocking and at most `max_connections` are in flight,
+    // with the hosts' ports taken in turn so they're all scanned together.
+    pub(super) fn connect_scan(
+        &self,
+        hosts: &[HostPorts],
+        tx: CrossbeamSender<ScanEvent>,
+        cancel: &CancelToken,
+        progress: &ProgressMeter,
+    ) -> io::Result<()> {
+        let mut addresses = VecDeque::new();
+        let longest = hosts.iter().map(|host| host.ports.len()).max().unwrap_or(0);
+        for i in 0..longest {
+            for host in hosts {
+                if let Some(&port) = host.ports.get(i) {
+                    addresses.push_back(SocketAddr::new(host.target.address, port));
+                }
+            }
+        }
+
+        let by_address: HashMap<IpAddr, &Target> =
+            hosts.iter().map(|host| (host.target.address, &host.target)).collect();
+        let report = |address: SocketAddr, state, reason, rtt| {
+            let target = by_address[&address.ip()];
+            let mut result = PortResult::new(target, address.port(), Transport::Tcp, state, reason);
+            result.rtt = rtt;
+            let _ = tx.send(ScanEvent::Port(result));
+        };
+        connect_all(
+            addresses,
+            self.max_connections,
+            self.connect_timeout,
+            cancel,
+            progress,
+            report,
+        )
+    }
+}
+
+// Connect to every address, reporting how each attempt ended along with the time
+// it took to get an answer. Connections still pending when the scan is cancelled
+// are dropped unreported.
+pub(super) fn connect_all<F>(
+    mut queue: VecDeque<SocketAddr>,
+    max_connections: usize,
+    timeout: Duration,
+    cancel: &CancelToken,
+    progress: &ProgressMeter,
+    mut report: F,
+) -> io::Result<()>
+where
+    F: FnMut(SocketAddr, PortState, Reason, Option<Duration>),
+{
+    let mut pending: Vec<PendingConnect> = Vec::with_capacity(max_connections);
+    let mut report = |address, state, reason, rtt| {
+        if reason != Reason::NoResponse {
+            progress.received(1);
+        }
+        report(address, state, reason, rtt);
+    };
+
+    while (!queue.is_empty() || !pending.is_empty()) && !cancel.is_cancelled() {
+        while pending.len() < max_connections {
+            let Some(address) = queue.pop_front() else {
+                break;
+            };
+
+            ratelimit::acquire(if address.is_ipv4() { SYN_LEN_V4 } else { SYN_LEN_V6 });
+            let outcome = start_connect(address);
+            if outcome.is_ok() {
+                progress.sent(1);
+            }
+            match outcome {
+                Ok(ConnectOutcome::InProgress(socket)) => pending.push(PendingConnect {
+                    address,
+                    socket,
+                    started: Instant::now(),
+                }),
+                Ok(ConnectOutcome::Done(state, reason)) => report(address, state, reason, None),
+                // Out of file descriptors: retry once some connections finish
+                Err(e) if e.raw_os_error() == Some(libc::EMFILE) && !pending.is_empty() => {
+                    queue.push_front(address);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        if pending.is_empty() {
+            continue;
+        }
+        progress.tick();
+
+        let mut fds: Vec<libc::pollfd> = pending
+            .iter()
+            .map(|connect| libc::pollfd {
+                fd: connect.socket.as_raw_fd(),
+                events: libc::POLLOUT,
+                revents: 0,
+            })
+            .collect();
+
+        let now = Instant::now();
+        let next_expiry = pending
+            .iter()
+            .map(|connect| (connect.started + timeout).saturating_duration_since(now))
+            .min()
+            .unwrap_or(POLL_INTERVAL)
+            .min(POLL_INTERVAL);
+
+        let poll_timeout = next_expiry.as_millis() as libc::c_int;
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, poll_timeout) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+
+        let now = Instant::now();
+        let mut index = 0;
+        pending.retain(|connect| {
+            let revents = fds[index].revents;
+            index += 1;
+
+            let elapsed = now.duration_since(connect.started);
+            if revents != 0 {
+                let (state, reason) = finished_status(&connect.socket);
+                report(connect.address, state, reason, Some(elapsed));
+            } else if elapsed >= timeout {
+                // Nothing came back at all, something is dropping our SYNs
+                report(connect.address, PortState::Filtered, Reason::NoResponse, None);
+            } else {
+                return true;
+            }
+            false
+        });
+    }
+
+    Ok(())
+}
+
+fn start_connect(address: SocketAddr) -> io::Result<ConnectOutcome> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_nonblocking(true)?;
+    // Reset instead of FIN on close so we don't leave TIME_WAIT sockets behind
+    socket.set_linger(Some(Duration::ZERO))?;
+
+    match socket.connect(&SockAddr::from(address)) {
+        Ok(()) => Ok(ConnectOutcome::Done(PortState::Open, Reason::SynAck)),
+        Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {
+            Ok(ConnectOutcome::InProgress(socket))
+        }
+        Err(e) => match error_status(&e) {
+            Some((state, reason)) => Ok(ConnectOutcome::Done(state, reason)),
+            None => Err(e),
+        },
+    }
+}
+
+// State of a socket poll() reported as writable or errored.
+fn finished_status(socket: &Socket) -> (PortState, Reason) {
+    match socket.take_error() {
+        Ok(None) => (PortState::Open, Reason::SynAck),
+        Ok(Some(e)) | Err(e) => {
+            error_status(&e).unwrap_or((PortState::Filtered, Reason::NoResponse))
+        }
+    }
+}
+
+fn error_status(error: &io::Error) -> Option<(PortState, Reason)> {
+    match error.raw_os_error()? {
+        libc::ECONNREFUSED => Some((PortState::Closed, Reason::ConnRefused)),
+        // The kernel turns ICMP unreachables into these
+        libc::EHOSTUNREACH | libc::ENETUNREACH | libc::EACCES | libc::EPERM => {
+            Some((PortState::Filtered, Reason::IcmpUnreach))
+        }
+        libc::ETIMEDOUT => Some((PortState::Filtered, Reason::NoResponse)),
+        _ => None,
+    }
+}
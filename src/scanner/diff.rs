@@ -0,0 +1,501 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::report::{self, ScanReport};
+use super::result::{HostResult, PortResult, PortState, Transport};
+
+// What changed between two runs of a scan, after nmap's ndiff: hosts that came
+// up or went down, ports whose state changed and services whose identification
+// changed.
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DiffFormat {
+    Text,
+    Json,
+}
+
+impl DiffFormat {
+    pub const ALL: [DiffFormat; 2] = [DiffFormat::Text, DiffFormat::Json];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            DiffFormat::Text => "diff",
+            DiffFormat::Json => "json",
+        }
+    }
+}
+
+impl fmt::Display for DiffFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            DiffFormat::Text => "Text",
+            DiffFormat::Json => "JSON",
+        })
+    }
+}
+
+// When a scan ran and how, to tell the two apart.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanHeader {
+    pub started: SystemTime,
+    pub command: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanDiff {
+    pub before: ScanHeader,
+    pub after: ScanHeader,
+    // Only the hosts something changed on, by address
+    pub hosts: Vec<HostDiff>,
+}
+
+// One host's changes. `None` means the scan didn't cover the host, or didn't
+// fingerprint it.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostDiff {
+    pub address: IpAddr,
+    pub hostname: Option<String>,
+    pub up_before: Option<bool>,
+    pub up_after: Option<bool>,
+    pub os_before: Option<String>,
+    pub os_after: Option<String>,
+    // Ports that changed, by protocol and number
+    pub ports: Vec<PortDiff>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PortDiff {
+    pub port: u16,
+    pub protocol: Transport,
+    pub before: Option<PortDetails>,
+    pub after: Option<PortDetails>,
+}
+
+// Counts of the changes that make hosts more or less exposed.
+#[derive(PartialEq, Eq, Debug, Default)]
+struct Exposure {
+    up: usize,
+    down: usize,
+    opened: usize,
+    closed: usize,
+    changed: usize,
+}
+
+// The parts of a port's result worth comparing.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
+pub struct PortDetails {
+    pub state: PortState,
+    pub service: String,
+    // Product, version and extra info as service detection found them
+    pub version: String,
+}
+
+impl PortDetails {
+    fn new(result: &PortResult) -> Self {
+        let version: Vec<&str> = [&result.product, &result.version, &result.info]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+        Self {
+            state: result.state,
+            service: result.service.clone(),
+            version: version.join(" "),
+        }
+    }
+}
+
+impl ScanDiff {
+    pub fn new(before: &ScanReport, after: &ScanReport) -> Self {
+        let addresses: BTreeSet<IpAddr> = before
+            .hosts
+            .iter()
+            .chain(&after.hosts)
+            .map(|host| host.status.address)
+            .collect();
+        let hosts = addresses
+            .into_iter()
+            .filter_map(|address| {
+                let before = before.host(address);
+                let after = after.host(address);
+                let ports = diff_ports(&ports_by_number(before), &ports_by_number(after));
+                let os = |host: Option<&HostResult>| {
+                    host.and_then(|host| host.status.os.as_ref()).map(|os| os.name.clone())
+                };
+                let diff = HostDiff {
+                    address,
+                    hostname: after.or(before).and_then(|host| host.status.hostname.clone()),
+                    up_before: before.map(|host| host.status.up),
+                    up_after: after.map(|host| host.status.up),
+                    os_before: os(before),
+                    os_after: os(after),
+                    ports,
+                };
+                diff.changed().then_some(diff)
+            })
+            .collect();
+
+        Self {
+            before: ScanHeader {
+                started: before.started,
+                command: report::command_line(&before.config),
+            },
+            after: ScanHeader {
+                started: after.started,
+                command: report::command_line(&after.config),
+            },
+            hosts,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    // Hosts coming up or going down, ports opening or closing and what listens
+    // on open ports changing. Other changes, say from closed to filtered, don't
+    // expose anything.
+    pub fn exposure_changed(&self) -> bool {
+        self.exposure() != Exposure::default()
+    }
+
+    // e.g. "1 host came up, 2 ports opened, 1 service changed"
+    pub fn summary(&self) -> String {
+        let exposure = self.exposure();
+        let parts: Vec<String> = [
+            (exposure.up, "host", "hosts", "came up"),
+            (exposure.down, "host", "hosts", "went down"),
+            (exposure.opened, "port", "ports", "opened"),
+            (exposure.closed, "port", "ports", "no longer open"),
+            (exposure.changed, "service", "services", "changed"),
+        ]
+        .into_iter()
+        .filter(|&(count, ..)| count > 0)
+        .map(|(count, one, many, what)| format!("{} {} {}", count, if count == 1 { one } else { many }, what))
+        .collect();
+        if parts.is_empty() {
+            return "no change in exposure".to_string();
+        }
+        parts.join(", ")
+    }
+
+    fn exposure(&self) -> Exposure {
+        let mut exposure = Exposure::default();
+        for host in &self.hosts {
+            match (host.up_before.unwrap_or(false), host.up_after.unwrap_or(false)) {
+                (false, true) => exposure.up += 1,
+                (true, false) => exposure.down += 1,
+                _ => {}
+            }
+            let open = |details: &Option<PortDetails>| {
+                details.as_ref().is_some_and(|details| details.state == PortState::Open)
+            };
+            for port in &host.ports {
+                match (open(&port.before), open(&port.after)) {
+                    (false, true) => exposure.opened += 1,
+                    (true, false) => exposure.closed += 1,
+                    // Open both times, so the service must be what changed
+                    (true, true) => exposure.changed += 1,
+                    (false, false) => {}
+                }
+            }
+        }
+        exposure
+    }
+
+    pub fn export<W: Write>(&self, format: DiffFormat, mut out: W) -> io::Result<()> {
+        match format {
+            DiffFormat::Text => write!(out, "{}", self),
+            DiffFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, self)?;
+                writeln!(out)
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path, format: DiffFormat) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.export(format, &mut out)?;
+        out.flush()
+    }
+}
+
+impl HostDiff {
+    fn changed(&self) -> bool {
+        // A host only one scan covered has changed if it's up in that one
+        let up_changed = self.up_before.unwrap_or(false) != self.up_after.unwrap_or(false);
+        // Not fingerprinting a host, or a host being down, isn't an OS change
+        let os_changed = self.os_before.is_some() && self.os_after.is_some() && self.os_before != self.os_after;
+        up_changed || os_changed || !self.ports.is_empty()
+    }
+}
+
+// Unified diff style, as ndiff writes it: lines from the first scan start with
+// "-", lines from the second with "+" and shared context with a space.
+//
+//  10.0.0.5 (printer):
+// -Host is down.
+// +Host is up.
+//  PORT    STATE  SERVICE  VERSION
+// +22/tcp  open   ssh      OpenSSH 9.6
+impl fmt::Display for ScanDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (sign, header) in [('-', &self.before), ('+', &self.after)] {
+            writeln!(
+                f,
+                "{}DeepNet scan initiated {} as: {}",
+                sign,
+                report::time_string(header.started),
+                header.command
+            )?;
+        }
+
+        for host in &self.hosts {
+            writeln!(f)?;
+            match &host.hostname {
+                Some(hostname) => writeln!(f, " {} ({}):", host.address, hostname)?,
+                None => writeln!(f, " {}:", host.address)?,
+            }
+            if host.up_before != host.up_after {
+                for (sign, up) in [('-', host.up_before), ('+', host.up_after)] {
+                    if let Some(up) = up {
+                        writeln!(f, "{}Host is {}.", sign, if up { "up" } else { "down" })?;
+                    }
+                }
+            }
+            if host.os_before != host.os_after {
+                for (sign, os) in [('-', &host.os_before), ('+', &host.os_after)] {
+                    if let Some(os) = os {
+                        writeln!(f, "{}OS: {}", sign, os)?;
+                    }
+                }
+            }
+            if host.ports.is_empty() {
+                continue;
+            }
+
+            let mut rows = Vec::new();
+            for port in &host.ports {
+                let name = format!("{}/{}", port.port, port.protocol);
+                for (sign, details) in [('-', &port.before), ('+', &port.after)] {
+                    if let Some(details) = details {
+                        rows.push([
+                            format!("{}{}", sign, name),
+                            details.state.to_string(),
+                            details.service.clone(),
+                            details.version.clone(),
+                        ]);
+                    }
+                }
+            }
+            let header = [" PORT", "STATE", "SERVICE", "VERSION"].map(String::from);
+            let mut widths = [0; 3];
+            for row in rows.iter().chain([&header]) {
+                for (width, cell) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(cell.chars().count());
+                }
+            }
+            for row in [&header].into_iter().chain(&rows) {
+                let line = format!(
+                    "{:<w0$}  {:<w1$}  {:<w2$}  {}",
+                    row[0],
+                    row[1],
+                    row[2],
+                    row[3],
+                    w0 = widths[0],
+                    w1 = widths[1],
+                    w2 = widths[2]
+                );
+                writeln!(f, "{}", line.trim_end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn ports_by_number(host: Option<&HostResult>) -> BTreeMap<(Transport, u16), &PortResult> {
+    host.into_iter()
+        .flat_map(|host| &host.ports)
+        .map(|result| ((result.protocol, result.port), result))
+        .collect()
+}
+
+// Ports whose state or service differ. A port only one scan covered counts when
+// it's open there, so a host that came up isn't listed with every closed port.
+fn diff_ports(
+    before: &BTreeMap<(Transport, u16), &PortResult>,
+    after: &BTreeMap<(Transport, u16), &PortResult>,
+) -> Vec<PortDiff> {
+    let keys: BTreeSet<(Transport, u16)> = before.keys().chain(after.keys()).copied().collect();
+    keys.into_iter()
+        .filter_map(|(protocol, port)| {
+            let before = before.get(&(protocol, port)).map(|result| PortDetails::new(result));
+            let after = after.get(&(protocol, port)).map(|result| PortDetails::new(result));
+            let changed = match (&before, &after) {
+                (Some(before), Some(after)) => before != after,
+                (Some(only), None) | (None, Some(only)) => only.state == PortState::Open,
+                (None, None) => false,
+            };
+            changed.then_some(PortDiff {
+                port,
+                protocol,
+                before,
+                after,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::result::{HostStatus, HostnameSource, Reason, ScanEvent};
+    use crate::scanner::target::Target;
+    use crate::scanner::ScanConfig;
+
+    fn report() -> ScanReport {
+        ScanReport::new(&ScanConfig {
+            target: "192.0.2.0/29".to_string(),
+            ports: "22,23,80".to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn target(address: &str) -> Target {
+        Target { address: address.parse().unwrap(), hostname: None }
+    }
+
+    fn host(report: &mut ScanReport, address: &str, up: bool) {
+        report.record(&ScanEvent::Host(HostStatus {
+            address: address.parse().unwrap(),
+            hostname: None,
+            hostname_source: HostnameSource::User,
+            up,
+            reason: if up { Reason::EchoReply } else { Reason::NoResponse },
+            mac: None,
+            vendor: None,
+            latency: None,
+            os: None,
+        }));
+    }
+
+    fn port(report: &mut ScanReport, address: &str, port: u16, state: PortState) {
+        let reason = match state {
+            PortState::Open => Reason::SynAck,
+            PortState::Closed => Reason::Rst,
+            _ => Reason::NoResponse,
+        };
+        let result = PortResult::new(&target(address), port, Transport::Tcp, state, reason);
+        report.record(&ScanEvent::Port(result));
+    }
+
+    fn service(report: &mut ScanReport, address: &str, port: u16, product: &str, version: &str) {
+        let mut result =
+            PortResult::new(&target(address), port, Transport::Tcp, PortState::Open, Reason::SynAck);
+        result.product = Some(product.to_string());
+        result.version = Some(version.to_string());
+        report.record(&ScanEvent::Service(result));
+    }
+
+    // The diff as text, less the two header lines with the start times
+    fn text(diff: &ScanDiff) -> String {
+        diff.to_string().lines().skip(2).map(|line| format!("{}\n", line)).collect()
+    }
+
+    #[test]
+    fn host_coming_up() {
+        let mut before = report();
+        host(&mut before, "192.0.2.5", false);
+        let mut after = report();
+        host(&mut after, "192.0.2.5", true);
+        port(&mut after, "192.0.2.5", 22, PortState::Open);
+        service(&mut after, "192.0.2.5", 22, "OpenSSH", "9.6");
+        port(&mut after, "192.0.2.5", 23, PortState::Closed);
+
+        let diff = ScanDiff::new(&before, &after);
+        assert_eq!(diff.hosts.len(), 1);
+        let changed = &diff.hosts[0];
+        assert_eq!((changed.up_before, changed.up_after), (Some(false), Some(true)));
+        // A closed port on a host that only just came up isn't news
+        assert_eq!(changed.ports.len(), 1);
+        assert_eq!(diff.exposure(), Exposure { up: 1, opened: 1, ..Default::default() });
+        assert!(diff.exposure_changed());
+        assert_eq!(diff.summary(), "1 host came up, 1 port opened");
+        assert_eq!(
+            text(&diff),
+            "\n 192.0.2.5:\n-Host is down.\n+Host is up.\n PORT    STATE  SERVICE  VERSION\n+22/tcp  open   ssh      OpenSSH 9.6\n"
+        );
+    }
+
+    #[test]
+    fn ports_opening_and_closing() {
+        let mut before = report();
+        host(&mut before, "192.0.2.1", true);
+        port(&mut before, "192.0.2.1", 22, PortState::Open);
+        port(&mut before, "192.0.2.1", 80, PortState::Closed);
+        let mut after = report();
+        host(&mut after, "192.0.2.1", true);
+        port(&mut after, "192.0.2.1", 22, PortState::Closed);
+        port(&mut after, "192.0.2.1", 80, PortState::Open);
+
+        let diff = ScanDiff::new(&before, &after);
+        assert_eq!(diff.exposure(), Exposure { opened: 1, closed: 1, ..Default::default() });
+        assert_eq!(diff.summary(), "1 port opened, 1 port no longer open");
+        assert_eq!(
+            text(&diff),
+            "\n 192.0.2.1:\n PORT    STATE   SERVICE  VERSION\n\
+             -22/tcp  open    ssh\n+22/tcp  closed  ssh\n-80/tcp  closed  http\n+80/tcp  open    http\n"
+        );
+    }
+
+    #[test]
+    fn service_changing_on_an_open_port() {
+        let mut before = report();
+        host(&mut before, "192.0.2.1", true);
+        port(&mut before, "192.0.2.1", 22, PortState::Open);
+        service(&mut before, "192.0.2.1", 22, "OpenSSH", "9.6");
+        let mut after = before.clone();
+        service(&mut after, "192.0.2.1", 22, "OpenSSH", "9.7");
+
+        let diff = ScanDiff::new(&before, &after);
+        assert_eq!(diff.exposure(), Exposure { changed: 1, ..Default::default() });
+        assert_eq!(diff.summary(), "1 service changed");
+        assert!(text(&diff).ends_with("-22/tcp  open   ssh      OpenSSH 9.6\n+22/tcp  open   ssh      OpenSSH 9.7\n"));
+    }
+
+    #[test]
+    fn closed_to_filtered_is_no_change_in_exposure() {
+        let mut before = report();
+        host(&mut before, "192.0.2.1", true);
+        port(&mut before, "192.0.2.1", 23, PortState::Closed);
+        let mut after = report();
+        host(&mut after, "192.0.2.1", true);
+        port(&mut after, "192.0.2.1", 23, PortState::Filtered);
+
+        let diff = ScanDiff::new(&before, &after);
+        // Still listed, but nothing to alert on
+        assert_eq!(diff.hosts.len(), 1);
+        assert!(!diff.exposure_changed());
+        assert_eq!(diff.summary(), "no change in exposure");
+    }
+
+    #[test]
+    fn unchanged_and_down_hosts_are_left_out() {
+        let mut before = report();
+        host(&mut before, "192.0.2.1", true);
+        port(&mut before, "192.0.2.1", 22, PortState::Open);
+        let mut after = before.clone();
+        // Only the second scan covered this one, and it's down
+        host(&mut after, "192.0.2.6", false);
+
+        let diff = ScanDiff::new(&before, &after);
+        assert!(diff.is_empty());
+        assert_eq!(text(&diff), "");
+    }
+}
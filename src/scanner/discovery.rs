@@ -0,0 +1,577 @@
+use pnet::packet::arp::{ArpHardwareTypes, ArpOperations, ArpPacket, MutableArpPacket};
+use pnet::packet::ethernet::{EtherTypes, EthernetPacket, MutableEthernetPacket};
+use pnet::packet::icmp::IcmpTypes;
+use pnet::packet::icmpv6::Icmpv6Types;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::tcp::{MutableTcpPacket, TcpFlags, TcpPacket};
+use pnet::packet::util;
+use pnet::packet::Packet;
+use pnet::util::MacAddr;
+use pnet_datalink::{DataLinkReceiver, DataLinkSender, NetworkInterface};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::{oui, ratelimit};
+
+use super::connect;
+use super::progress::{CancelToken, ProgressMeter};
+use super::raw::{self, RawSender, Reply, Route};
+use super::result::{HostStatus, HostnameSource, Reason};
+use super::target::Target;
+
+// Gap between hosts while sending probes, so a /16 takes seconds rather than minutes
+const PROBE_INTERVAL: Duration = Duration::from_micros(200);
+// How long to wait for stragglers after each round of probes
+const DISCOVERY_WAIT: Duration = Duration::from_secs(1);
+// Hosts that stay silent are probed this many times in total
+pub(super) const DISCOVERY_ATTEMPTS: usize = 2;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Ports tried by unprivileged connect pings when none are configured
+const CONNECT_PING_PORTS: [u16; 2] = [80, 443];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveryConfig {
+    // Treat every target as up and go straight to port scanning
+    pub skip: bool,
+    // Used instead of IP probes for targets on a directly attached Ethernet subnet
+    pub arp: bool,
+    pub icmp_echo: bool,
+    pub icmp_timestamp: bool,
+    pub syn_ports: Vec<u16>,
+    pub ack_ports: Vec<u16>,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            skip: false,
+            arp: true,
+            icmp_echo: true,
+            icmp_timestamp: true,
+            syn_ports: vec![443],
+            ack_ports: vec![80],
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Response {
+    reason: Reason,
+    mac: Option<MacAddr>,
+    latency: Option<Duration>,
+}
+
+// Replies seen so far, keyed by the address that answered.
+struct Responses {
+    sent: Mutex<HashMap<IpAddr, Instant>>,
+    found: Mutex<HashMap<IpAddr, Response>>,
+    progress: Arc<ProgressMeter>,
+}
+
+impl Responses {
+    fn sent(&self, address: IpAddr) {
+        self.sent.lock().unwrap().insert(address, Instant::now());
+    }
+
+    fn found(&self, address: IpAddr) -> bool {
+        self.found.lock().unwrap().contains_key(&address)
+    }
+
+    fn all_found(&self) -> bool {
+        self.found.lock().unwrap().len() >= self.sent.lock().unwrap().len()
+    }
+
+    // One of our own addresses, which needs no probing.
+    fn local(&self, address: IpAddr) {
+        let response = Response { reason: Reason::LocalhostResponse, mac: None, latency: None };
+        self.found.lock().unwrap().insert(address, response);
+    }
+
+    // Only addresses we probed count; the first answer wins.
+    fn record(&self, address: IpAddr, reason: Reason, mac: Option<MacAddr>) {
+        let Some(sent) = self.sent.lock().unwrap().get(&address).copied() else {
+            return;
+        };
+        self.found
+            .lock()
+            .unwrap()
+            .entry(address)
+            .or_insert(Response { reason, mac, latency: Some(sent.elapsed()) });
+    }
+}
+
+// The IP probes sent to one host, and how to recognise the replies.
+struct PingProbe {
+    identifier: u16,
+    source_port: u16,
+}
+
+impl PingProbe {
+    fn icmp(&self, route: &Route, timestamp: bool) -> Vec<u8> {
+        let [id_high, id_low] = self.identifier.to_be_bytes();
+        match route {
+            Route::V4(..) if timestamp => {
+                // Originate timestamp in milliseconds since midnight UTC
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                let originate = ((now.as_millis() % 86_400_000) as u32).to_be_bytes();
+                let mut packet = vec![13, 0, 0, 0, id_high, id_low, 0, 1];
+                packet.extend_from_slice(&originate);
+                packet.extend_from_slice(&[0; 8]);
+                let checksum = util::checksum(&packet, 1);
+                packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+                packet
+            }
+            Route::V4(..) => {
+                let mut packet = vec![8, 0, 0, 0, id_high, id_low, 0, 1];
+                let checksum = util::checksum(&packet, 1);
+                packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+                packet
+            }
+            Route::V6(..) => {
+                let mut packet = vec![128, 0, 0, 0, id_high, id_low, 0, 1];
+                let checksum = route.checksum(IpNextHeaderProtocols::Icmpv6, &packet, 1);
+                packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+                packet
+            }
+        }
+    }
+
+    fn tcp(&self, route: &Route, port: u16, flags: u16) -> Vec<u8> {
+        let mut buffer = vec![0u8; 20];
+        let mut packet = MutableTcpPacket::new(&mut buffer).unwrap();
+        packet.set_source(self.source_port);
+        packet.set_destination(port);
+        packet.set_sequence(rand::random());
+        if flags & TcpFlags::ACK != 0 {
+            packet.set_acknowledgement(rand::random());
+        }
+        packet.set_data_offset(5);
+        packet.set_flags(flags);
+        packet.set_window(1024);
+        let checksum = route.checksum(IpNextHeaderProtocols::Tcp, packet.packet(), 8);
+        packet.set_checksum(checksum);
+        buffer
+    }
+
+    fn classify_icmp(&self, reply: &Reply) -> Option<Reason> {
+        let payload = reply.payload;
+        if payload.get(4..6)? != self.identifier.to_be_bytes() {
+            return None;
+        }
+        match (reply.source, payload[0]) {
+            (IpAddr::V4(_), t) if t == IcmpTypes::EchoReply.0 => Some(Reason::EchoReply),
+            (IpAddr::V4(_), t) if t == IcmpTypes::TimestampReply.0 => Some(Reason::TimestampReply),
+            (IpAddr::V6(_), t) if t == Icmpv6Types::EchoReply.0 => Some(Reason::EchoReply),
+            _ => None,
+        }
+    }
+
+    // Either a SYN-ACK to a SYN ping or a reset to any of our pings means someone is home.
+    fn classify_tcp(&self, reply: &Reply) -> Option<Reason> {
+        let tcp = TcpPacket::new(reply.payload)?;
+        if tcp.get_destination() != self.source_port {
+            return None;
+        }
+        let flags = tcp.get_flags();
+        if flags & TcpFlags::RST != 0 {
+            Some(Reason::Rst)
+        } else if flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK {
+            Some(Reason::SynAck)
+        } else {
+            None
+        }
+    }
+}
+
+// Raw sockets for one address family.
+struct PingSockets {
+    icmp: Option<Mutex<RawSender>>,
+    tcp: Option<Mutex<RawSender>>,
+}
+
+// An Ethernet interface we can ARP on, with our address on it.
+struct ArpInterface {
+    interface: NetworkInterface,
+    mac: MacAddr,
+    source: Ipv4Addr,
+    targets: Vec<Ipv4Addr>,
+}
+
+// Find out which targets are up. Hosts on a local Ethernet subnet are asked with
+// ARP, everything else gets the configured ICMP and TCP pings. Without the
+// privileges for raw sockets this falls back to TCP connect pings. Progress is
+// counted in rounds of probes, one per host for each attempt.
+pub(super) fn discover(
+    targets: &[Target],
+    config: &DiscoveryConfig,
+    max_connections: usize,
+    connect_timeout: Duration,
+    cancel: &CancelToken,
+    progress: Arc<ProgressMeter>,
+) -> io::Result<Vec<HostStatus>> {
+    let local: HashSet<IpAddr> = pnet_datalink::interfaces()
+        .iter()
+        .flat_map(|interface| interface.ips.iter().map(|network| network.ip()))
+        .collect();
+
+    let responses = Arc::new(Responses {
+        sent: Mutex::new(HashMap::new()),
+        found: Mutex::new(HashMap::new()),
+        progress,
+    });
+
+    let mut arp_interfaces: Vec<ArpInterface> = Vec::new();
+    let mut ping_targets: Vec<IpAddr> = Vec::new();
+    for target in targets {
+        if local.contains(&target.address) {
+            responses.local(target.address);
+            responses.progress.done(DISCOVERY_ATTEMPTS);
+            continue;
+        }
+        if let IpAddr::V4(address) = target.address
+            && let Some(interface) = arp_interface(config, address, &mut arp_interfaces)
+        {
+            interface.targets.push(address);
+            continue;
+        }
+        ping_targets.push(target.address);
+    }
+
+    match ping_and_arp(config, &ping_targets, &arp_interfaces, &responses, cancel) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            let targets: Vec<IpAddr> = arp_interfaces
+                .iter()
+                .flat_map(|interface| interface.targets.iter().map(|address| IpAddr::V4(*address)))
+                .chain(ping_targets.iter().copied())
+                .collect();
+            connect_ping(config, &targets, max_connections, connect_timeout, cancel, &responses)?;
+        }
+        Err(e) => return Err(e),
+    }
+
+    let found = responses.found.lock().unwrap();
+    Ok(targets
+        .iter()
+        .map(|target| {
+            let response = found.get(&target.address);
+            let mac = response.and_then(|response| response.mac);
+            HostStatus {
+                address: target.address,
+                hostname: target.hostname.clone(),
+                hostname_source: HostnameSource::User,
+                up: response.is_some(),
+                reason: response.map_or(Reason::NoResponse, |response| response.reason),
+                mac,
+                vendor: mac.and_then(oui::vendor).map(String::from),
+                latency: response.and_then(|response| response.latency),
+                os: None,
+            }
+        })
+        .collect())
+}
+
+// The Ethernet interface whose subnet contains `address`, if ARP is enabled.
+fn arp_interface<'a>(
+    config: &DiscoveryConfig,
+    address: Ipv4Addr,
+    interfaces: &'a mut Vec<ArpInterface>,
+) -> Option<&'a mut ArpInterface> {
+    if !config.arp || address.is_loopback() {
+        return None;
+    }
+
+    if let Some(index) = interfaces.iter().position(|interface| {
+        interface.interface.ips.iter().any(|network| network.contains(IpAddr::V4(address)))
+    }) {
+        return interfaces.get_mut(index);
+    }
+
+    let interface = pnet_datalink::interfaces().into_iter().find(|interface| {
+        interface.is_up()
+            && !interface.is_loopback()
+            && interface.mac.is_some_and(|mac| mac != MacAddr::zero())
+            && interface.ips.iter().any(|network| network.contains(IpAddr::V4(address)))
+    })?;
+    let source = interface.ips.iter().find_map(|network| match network.ip() {
+        IpAddr::V4(source) if network.contains(IpAddr::V4(address)) => Some(source),
+        _ => None,
+    })?;
+    interfaces.push(ArpInterface {
+        mac: interface.mac?,
+        interface,
+        source,
+        targets: Vec::new(),
+    });
+    interfaces.last_mut()
+}
+
+fn ping_and_arp(
+    config: &DiscoveryConfig,
+    ping_targets: &[IpAddr],
+    arp_interfaces: &[ArpInterface],
+    responses: &Arc<Responses>,
+    cancel: &CancelToken,
+) -> io::Result<()> {
+    let probe = Arc::new(PingProbe {
+        identifier: rand::random(),
+        source_port: rand::random::<u16>() % 16384 + 49152,
+    });
+    let done = Arc::new(AtomicBool::new(false));
+
+    let routes: Vec<(IpAddr, Option<Route>)> = ping_targets
+        .iter()
+        .map(|address| (*address, Route::to(*address).ok()))
+        .collect();
+
+    // One set of sockets per address family in use. Every socket is opened
+    // before any listener starts, so a failure leaves nothing running.
+    let mut families: [Option<PingSockets>; 2] = [None, None];
+    let mut receivers = Vec::new();
+    for (_, route) in &routes {
+        let Some(route) = route else { continue };
+        let family = matches!(route, Route::V6(..)) as usize;
+        if families[family].is_some() {
+            continue;
+        }
+
+        let mut sockets = PingSockets { icmp: None, tcp: None };
+        if config.icmp_echo || config.icmp_timestamp {
+            let (tx, rx) = raw::open(route, IpNextHeaderProtocols::Icmp)?;
+            receivers.push((rx, true));
+            sockets.icmp = Some(Mutex::new(tx));
+        }
+        if !config.syn_ports.is_empty() || !config.ack_ports.is_empty() {
+            let (tx, rx) = raw::open(route, IpNextHeaderProtocols::Tcp)?;
+            receivers.push((rx, false));
+            sockets.tcp = Some(Mutex::new(tx));
+        }
+        families[family] = Some(sockets);
+    }
+
+    let mut arp_senders = Vec::new();
+    let mut arp_receivers = Vec::new();
+    for interface in arp_interfaces {
+        let (tx, rx) = arp_channel(interface)?;
+        arp_senders.push(tx);
+        arp_receivers.push(rx);
+    }
+
+    let mut listeners = Vec::new();
+    for (rx, icmp) in receivers {
+        let (probe, responses) = (probe.clone(), responses.clone());
+        listeners.push(raw::listen(rx, done.clone(), move |reply| {
+            let reason = if icmp { probe.classify_icmp(reply) } else { probe.classify_tcp(reply) };
+            if let Some(reason) = reason {
+                responses.progress.received(1);
+                responses.record(reply.source, reason, None);
+            }
+        }));
+    }
+    for rx in arp_receivers {
+        listeners.push(arp_listen(rx, done.clone(), responses.clone()));
+    }
+
+    let progress = &responses.progress;
+    for attempt in 0..DISCOVERY_ATTEMPTS {
+        if cancel.is_cancelled() || (attempt > 0 && responses.all_found()) {
+            break;
+        }
+
+        for (interface, tx) in arp_interfaces.iter().zip(&mut arp_senders) {
+            for target in &interface.targets {
+                if cancel.is_cancelled() {
+                    break;
+                }
+                progress.done(1);
+                if attempt > 0 && responses.found(IpAddr::V4(*target)) {
+                    continue;
+                }
+                let request = arp_request(interface, *target);
+                ratelimit::acquire(request.len());
+                responses.sent(IpAddr::V4(*target));
+                if let Some(Err(e)) = tx.send_to(&request, None) {
+                    eprintln!("Error sending ARP request to {}: {}", target, e);
+                }
+                progress.sent(1);
+                thread::sleep(PROBE_INTERVAL);
+            }
+        }
+
+        for (address, route) in &routes {
+            if cancel.is_cancelled() {
+                break;
+            }
+            progress.done(1);
+            let Some(route) = route else { continue };
+            if attempt > 0 && responses.found(*address) {
+                continue;
+            }
+            let family = matches!(route, Route::V6(..)) as usize;
+            let Some(sockets) = &families[family] else { continue };
+
+            responses.sent(*address);
+            let result = send_pings(config, &probe, route, sockets, progress);
+            if let Err(e) = result {
+                eprintln!("Error sending ping to {}: {}", address, e);
+            }
+            thread::sleep(PROBE_INTERVAL);
+        }
+
+        let waited = Instant::now();
+        while waited.elapsed() < DISCOVERY_WAIT && !cancel.is_cancelled() {
+            thread::sleep(POLL_INTERVAL);
+            progress.tick();
+        }
+    }
+
+    done.store(true, Ordering::Relaxed);
+    for listener in listeners {
+        listener.join().unwrap();
+    }
+    Ok(())
+}
+
+fn send_pings(
+    config: &DiscoveryConfig,
+    probe: &PingProbe,
+    route: &Route,
+    sockets: &PingSockets,
+    progress: &ProgressMeter,
+) -> io::Result<()> {
+    if let Some(icmp) = &sockets.icmp {
+        let mut icmp = icmp.lock().unwrap();
+        if config.icmp_echo {
+            icmp.send(route, &probe.icmp(route, false))?;
+            progress.sent(1);
+        }
+        // There's no timestamp request in ICMPv6
+        if config.icmp_timestamp && matches!(route, Route::V4(..)) {
+            icmp.send(route, &probe.icmp(route, true))?;
+            progress.sent(1);
+        }
+    }
+    if let Some(tcp) = &sockets.tcp {
+        let mut tcp = tcp.lock().unwrap();
+        for port in &config.syn_ports {
+            tcp.send(route, &probe.tcp(route, *port, TcpFlags::SYN))?;
+            progress.sent(1);
+        }
+        for port in &config.ack_ports {
+            tcp.send(route, &probe.tcp(route, *port, TcpFlags::ACK))?;
+            progress.sent(1);
+        }
+    }
+    Ok(())
+}
+
+fn arp_channel(interface: &ArpInterface) -> io::Result<(Box<dyn DataLinkSender>, Box<dyn DataLinkReceiver>)> {
+    let config = pnet_datalink::Config {
+        read_timeout: Some(POLL_INTERVAL),
+        promiscuous: false,
+        ..Default::default()
+    };
+    match pnet_datalink::channel(&interface.interface, config)? {
+        pnet_datalink::Channel::Ethernet(tx, rx) => Ok((tx, rx)),
+        _ => Err(io::Error::other("Unsupported channel type")),
+    }
+}
+
+fn arp_listen(
+    mut rx: Box<dyn DataLinkReceiver>,
+    done: Arc<AtomicBool>,
+    responses: Arc<Responses>,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        while !done.load(Ordering::Relaxed) {
+            match rx.next() {
+                Ok(frame) => {
+                    let Some(ethernet) = EthernetPacket::new(frame) else { continue };
+                    if ethernet.get_ethertype() != EtherTypes::Arp {
+                        continue;
+                    }
+                    let Some(arp) = ArpPacket::new(ethernet.payload()) else { continue };
+                    if arp.get_operation() == ArpOperations::Reply {
+                        let address = IpAddr::V4(arp.get_sender_proto_addr());
+                        let mac = Some(arp.get_sender_hw_addr());
+                        responses.progress.received(1);
+                        responses.record(address, Reason::ArpResponse, mac);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    break;
+                }
+            }
+        }
+    })
+}
+
+fn arp_request(interface: &ArpInterface, target: Ipv4Addr) -> Vec<u8> {
+    let mut arp_buffer = [0u8; 28];
+    let mut arp = MutableArpPacket::new(&mut arp_buffer).unwrap();
+    arp.set_hardware_type(ArpHardwareTypes::Ethernet);
+    arp.set_protocol_type(EtherTypes::Ipv4);
+    arp.set_hw_addr_len(6);
+    arp.set_proto_addr_len(4);
+    arp.set_operation(ArpOperations::Request);
+    arp.set_sender_hw_addr(interface.mac);
+    arp.set_sender_proto_addr(interface.source);
+    arp.set_target_hw_addr(MacAddr::zero());
+    arp.set_target_proto_addr(target);
+
+    let mut frame = vec![0u8; 14 + arp_buffer.len()];
+    let mut ethernet = MutableEthernetPacket::new(&mut frame).unwrap();
+    ethernet.set_destination(MacAddr::broadcast());
+    ethernet.set_source(interface.mac);
+    ethernet.set_ethertype(EtherTypes::Arp);
+    ethernet.set_payload(&arp_buffer);
+    frame
+}
+
+// Unprivileged fallback: any answer to a connect, even a refusal, means the host is up.
+fn connect_ping(
+    config: &DiscoveryConfig,
+    targets: &[IpAddr],
+    max_connections: usize,
+    timeout: Duration,
+    cancel: &CancelToken,
+    responses: &Responses,
+) -> io::Result<()> {
+    let mut ports: Vec<u16> = config.syn_ports.iter().chain(&config.ack_ports).copied().collect();
+    if ports.is_empty() {
+        ports = CONNECT_PING_PORTS.to_vec();
+    }
+
+    let mut queue = VecDeque::new();
+    for address in targets {
+        responses.sent(*address);
+        queue.extend(ports.iter().map(|port| SocketAddr::new(*address, *port)));
+    }
+
+    // A host is settled once every port has been tried; it stands for all of
+    // its discovery rounds
+    let mut remaining: HashMap<IpAddr, usize> =
+        targets.iter().map(|address| (*address, ports.len())).collect();
+    let progress = &responses.progress;
+    connect::connect_all(queue, max_connections, timeout, cancel, progress, |address, _, reason, _| {
+        if matches!(reason, Reason::SynAck | Reason::ConnRefused) {
+            responses.record(address.ip(), reason, None);
+        }
+        if let Some(count) = remaining.get_mut(&address.ip()) {
+            *count -= 1;
+            if *count == 0 {
+                progress.done(DISCOVERY_ATTEMPTS);
+            }
+        }
+    })
+}
@@ -0,0 +1,472 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+// Forward and reverse lookups, either through the system resolver or by talking
+// DNS over UDP to a server of the user's choosing.
+
+const DNS_PORT: u16 = 53;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+const QUERY_ATTEMPTS: usize = 2;
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+const RCODE_NXDOMAIN: u8 = 3;
+
+// Which of a hostname's addresses to scan.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum AddressChoice {
+    #[default]
+    FirstIpv4,
+    FirstIpv6,
+    AllIpv4,
+    AllIpv6,
+    All,
+}
+
+impl AddressChoice {
+    pub fn select(self, addresses: Vec<IpAddr>) -> Vec<IpAddr> {
+        let mut addresses: Vec<IpAddr> = addresses
+            .into_iter()
+            .filter(|address| match self {
+                AddressChoice::FirstIpv4 | AddressChoice::AllIpv4 => address.is_ipv4(),
+                AddressChoice::FirstIpv6 | AddressChoice::AllIpv6 => address.is_ipv6(),
+                AddressChoice::All => true,
+            })
+            .collect();
+        if matches!(self, AddressChoice::FirstIpv4 | AddressChoice::FirstIpv6) {
+            addresses.truncate(1);
+        }
+        addresses
+    }
+}
+
+impl fmt::Display for AddressChoice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            AddressChoice::FirstIpv4 => "First IPv4",
+            AddressChoice::FirstIpv6 => "First IPv6",
+            AddressChoice::AllIpv4 => "All IPv4",
+            AddressChoice::AllIpv6 => "All IPv6",
+            AddressChoice::All => "All",
+        })
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Resolver {
+    #[default]
+    System,
+    Server(SocketAddr),
+}
+
+impl Resolver {
+    // An empty string means the system resolver; the port defaults to 53.
+    pub fn parse(server: &str) -> Option<Self> {
+        let server = server.trim();
+        if server.is_empty() {
+            return Some(Resolver::System);
+        }
+        if let Ok(address) = server.parse::<SocketAddr>() {
+            return Some(Resolver::Server(address));
+        }
+        let address = server.trim_start_matches('[').trim_end_matches(']');
+        address
+            .parse::<IpAddr>()
+            .ok()
+            .map(|address| Resolver::Server(SocketAddr::new(address, DNS_PORT)))
+    }
+
+    // Every A and AAAA record for the host, in the order they were returned.
+    pub fn lookup(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        let mut addresses = Vec::new();
+        match self {
+            Resolver::System => {
+                for address in (host, 0).to_socket_addrs()? {
+                    addresses.push(address.ip());
+                }
+            }
+            Resolver::Server(server) => {
+                // Plenty of resolvers drop or refuse AAAA queries; that mustn't
+                // cost us the A records, so only fail if every query did
+                let mut answered = false;
+                let mut error = None;
+                for record_type in [TYPE_A, TYPE_AAAA] {
+                    match query(*server, host, record_type) {
+                        Ok(records) => {
+                            answered = true;
+                            addresses.extend(records.into_iter().filter_map(|record| match record {
+                                Record::Address(address) => Some(address),
+                                Record::Name(_) => None,
+                            }));
+                        }
+                        Err(e) => {
+                            error.get_or_insert(e);
+                        }
+                    }
+                }
+                if !answered && let Some(e) = error {
+                    return Err(e);
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        addresses.retain(|address| seen.insert(*address));
+        if addresses.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no addresses found"));
+        }
+        Ok(addresses)
+    }
+
+    // The PTR name for an address, or None if it has none.
+    pub fn reverse(&self, address: IpAddr) -> io::Result<Option<String>> {
+        match self {
+            Resolver::System => system_reverse(address),
+            Resolver::Server(server) => {
+                let records = query(*server, &reverse_name(address), TYPE_PTR)?;
+                Ok(records.into_iter().find_map(|record| match record {
+                    Record::Name(name) => Some(name),
+                    Record::Address(_) => None,
+                }))
+            }
+        }
+    }
+}
+
+enum Record {
+    Address(IpAddr),
+    Name(String),
+}
+
+fn system_reverse(address: IpAddr) -> io::Result<Option<String>> {
+    let address = socket2::SockAddr::from(SocketAddr::new(address, 0));
+    let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+    let status = unsafe {
+        libc::getnameinfo(
+            address.as_ptr(),
+            address.len(),
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    match status {
+        0 => {
+            let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+            Ok(Some(name.to_string_lossy().into_owned()))
+        }
+        libc::EAI_NONAME => Ok(None),
+        libc::EAI_SYSTEM => Err(io::Error::last_os_error()),
+        _ => {
+            let message = unsafe { CStr::from_ptr(libc::gai_strerror(status)) };
+            Err(io::Error::other(message.to_string_lossy().into_owned()))
+        }
+    }
+}
+
+// 4.3.2.1.in-addr.arpa, or the nibble-reversed ip6.arpa name.
+fn reverse_name(address: IpAddr) -> String {
+    match address {
+        IpAddr::V4(address) => {
+            let [a, b, c, d] = address.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(address) => {
+            let mut name = String::new();
+            for byte in address.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+fn query(server: SocketAddr, name: &str, record_type: u16) -> io::Result<Vec<Record>> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.connect(server)?;
+    socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+
+    let id = rand::random::<u16>();
+    let request = build_query(id, name, record_type)?;
+    let mut buffer = [0u8; 4096];
+
+    for _ in 0..QUERY_ATTEMPTS {
+        socket.send(&request)?;
+        loop {
+            let length = match socket.recv(&mut buffer) {
+                Ok(length) => length,
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            // Ignore anything that isn't the answer to this query
+            if let Some(records) = parse_response(&buffer[..length], id, record_type)? {
+                return Ok(records);
+            }
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("no answer from DNS server {}", server),
+    ))
+}
+
+fn build_query(id: u16, name: &str, record_type: u16) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(name.len() + 18);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Standard query, recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid DNS name \"{}\"", name),
+            ));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+// Ok(None) for packets that aren't a response to our query. Records of other
+// types (such as the CNAMEs leading to the answer) are skipped.
+fn parse_response(packet: &[u8], id: u16, record_type: u16) -> io::Result<Option<Vec<Record>>> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response");
+
+    if packet.len() < 12 || u16::from_be_bytes([packet[0], packet[1]]) != id || packet[2] & 0x80 == 0 {
+        return Ok(None);
+    }
+    match packet[3] & 0x0f {
+        0 => {}
+        RCODE_NXDOMAIN => return Ok(Some(Vec::new())),
+        rcode => {
+            return Err(io::Error::other(format!("DNS server returned error code {}", rcode)));
+        }
+    }
+
+    let questions = u16::from_be_bytes([packet[4], packet[5]]);
+    let answers = u16::from_be_bytes([packet[6], packet[7]]);
+    let mut offset = 12;
+    for _ in 0..questions {
+        offset = skip_name(packet, offset).ok_or_else(malformed)? + 4;
+    }
+
+    let mut records = Vec::new();
+    for _ in 0..answers {
+        offset = skip_name(packet, offset).ok_or_else(malformed)?;
+        let header = packet.get(offset..offset + 10).ok_or_else(malformed)?;
+        let answer_type = u16::from_be_bytes([header[0], header[1]]);
+        let length = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let data_start = offset + 10;
+        let data = packet.get(data_start..data_start + length).ok_or_else(malformed)?;
+        offset = data_start + length;
+
+        if answer_type != record_type {
+            continue;
+        }
+        let record = match answer_type {
+            TYPE_A => {
+                let octets: [u8; 4] = data.try_into().map_err(|_| malformed())?;
+                Record::Address(IpAddr::from(octets))
+            }
+            TYPE_AAAA => {
+                let octets: [u8; 16] = data.try_into().map_err(|_| malformed())?;
+                Record::Address(IpAddr::from(octets))
+            }
+            TYPE_PTR => Record::Name(read_name(packet, data_start).ok_or_else(malformed)?),
+            _ => continue,
+        };
+        records.push(record);
+    }
+    Ok(Some(records))
+}
+
+// Offset just past the (possibly compressed) name starting at `offset`.
+fn skip_name(packet: &[u8], mut offset: usize) -> Option<usize> {
+    loop {
+        let length = *packet.get(offset)?;
+        match length {
+            0 => return Some(offset + 1),
+            length if length & 0xc0 == 0xc0 => return Some(offset + 2),
+            length => offset += 1 + length as usize,
+        }
+    }
+}
+
+fn read_name(packet: &[u8], mut offset: usize) -> Option<String> {
+    let mut labels = Vec::new();
+    // Bound the number of compression pointers followed so loops can't hang us
+    let mut jumps = 0;
+    loop {
+        let length = *packet.get(offset)?;
+        if length == 0 {
+            break;
+        }
+        if length & 0xc0 == 0xc0 {
+            jumps += 1;
+            if jumps > 16 {
+                return None;
+            }
+            offset = (((length & 0x3f) as usize) << 8) | *packet.get(offset + 1)? as usize;
+            continue;
+        }
+        let label = packet.get(offset + 1..offset + 1 + length as usize)?;
+        labels.push(String::from_utf8_lossy(label).into_owned());
+        offset += 1 + length as usize;
+    }
+    Some(labels.join("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    const RCODE_SERVFAIL: u8 = 2;
+    const RCODE_REFUSED: u8 = 5;
+
+    // What the stub server does with a query.
+    enum Answer {
+        Records(Vec<Vec<u8>>),
+        Error(u8),
+        Drop,
+    }
+
+    // The canned zone: record data for each name and type.
+    fn answer(name: &str, record_type: u16) -> Answer {
+        match (name, record_type) {
+            ("dual.test", TYPE_A) => Answer::Records(vec![vec![192, 0, 2, 10], vec![192, 0, 2, 11]]),
+            ("dual.test", TYPE_AAAA) => {
+                Answer::Records(vec!["2001:db8::10".parse::<Ipv6Addr>().unwrap().octets().to_vec()])
+            }
+            ("refused-aaaa.test", TYPE_A) | ("dropped-aaaa.test", TYPE_A) => {
+                Answer::Records(vec![vec![192, 0, 2, 20]])
+            }
+            ("refused-aaaa.test", TYPE_AAAA) => Answer::Error(RCODE_REFUSED),
+            ("dropped-aaaa.test", TYPE_AAAA) => Answer::Drop,
+            ("broken.test", _) => Answer::Error(RCODE_SERVFAIL),
+            ("10.2.0.192.in-addr.arpa", TYPE_PTR) => Answer::Records(vec![encode_name("host.example.test")]),
+            _ => Answer::Error(RCODE_NXDOMAIN),
+        }
+    }
+
+    fn encode_name(name: &str) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for label in name.split('.') {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    // A DNS server on a loopback port answering from the canned zone.
+    fn stub_server() -> SocketAddr {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 512];
+            while let Ok((length, client)) = socket.recv_from(&mut buffer) {
+                let request = &buffer[..length];
+                let name = read_name(request, 12).unwrap();
+                let question_end = skip_name(request, 12).unwrap() + 4;
+                let record_type = u16::from_be_bytes([request[question_end - 4], request[question_end - 3]]);
+
+                let (rcode, records) = match answer(&name, record_type) {
+                    Answer::Records(records) => (0, records),
+                    Answer::Error(rcode) => (rcode, Vec::new()),
+                    Answer::Drop => continue,
+                };
+                let mut response = request[..2].to_vec();
+                response.extend_from_slice(&[0x81, 0x80 | rcode, 0x00, 0x01]);
+                response.extend_from_slice(&(records.len() as u16).to_be_bytes());
+                response.extend_from_slice(&[0x00, 0x00, 0x00, 0x00]);
+                response.extend_from_slice(&request[12..question_end]);
+                for data in records {
+                    // The name is a pointer back to the question
+                    response.extend_from_slice(&[0xc0, 0x0c]);
+                    response.extend_from_slice(&record_type.to_be_bytes());
+                    response.extend_from_slice(&CLASS_IN.to_be_bytes());
+                    response.extend_from_slice(&300u32.to_be_bytes());
+                    response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                    response.extend_from_slice(&data);
+                }
+                socket.send_to(&response, client).unwrap();
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn lookup_returns_a_and_aaaa_records() {
+        let resolver = Resolver::Server(stub_server());
+        let addresses = resolver.lookup("dual.test").unwrap();
+        let expected: Vec<IpAddr> = ["192.0.2.10", "192.0.2.11", "2001:db8::10"]
+            .iter()
+            .map(|address| address.parse().unwrap())
+            .collect();
+        assert_eq!(addresses, expected);
+    }
+
+    #[test]
+    fn lookup_of_missing_name_is_not_found() {
+        let resolver = Resolver::Server(stub_server());
+        let error = resolver.lookup("missing.test").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn lookup_keeps_a_records_when_aaaa_is_refused() {
+        let resolver = Resolver::Server(stub_server());
+        let addresses = resolver.lookup("refused-aaaa.test").unwrap();
+        assert_eq!(addresses, vec!["192.0.2.20".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn lookup_keeps_a_records_when_aaaa_times_out() {
+        let resolver = Resolver::Server(stub_server());
+        let addresses = resolver.lookup("dropped-aaaa.test").unwrap();
+        assert_eq!(addresses, vec!["192.0.2.20".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn lookup_fails_when_every_query_fails() {
+        let resolver = Resolver::Server(stub_server());
+        let error = resolver.lookup("broken.test").unwrap_err();
+        assert!(error.to_string().contains("error code 2"), "{}", error);
+    }
+
+    #[test]
+    fn reverse_finds_ptr_name() {
+        let resolver = Resolver::Server(stub_server());
+        let name = resolver.reverse("192.0.2.10".parse().unwrap()).unwrap();
+        assert_eq!(name.as_deref(), Some("host.example.test"));
+    }
+
+    #[test]
+    fn reverse_without_ptr_is_none() {
+        let resolver = Resolver::Server(stub_server());
+        assert_eq!(resolver.reverse("192.0.2.99".parse().unwrap()).unwrap(), None);
+    }
+}
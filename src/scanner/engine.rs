@@ -0,0 +1,377 @@
+use crossbeam_channel::{RecvTimeoutError, Sender as CrossbeamSender};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::progress::{CancelToken, ProgressMeter};
+use super::raw::{self, RawSender, Reply, Route, Unreachable};
+use super::result::{PortResult, PortState, Reason, ScanEvent, Transport};
+use super::sctp::SctpProbe;
+use super::syn::TcpProbe;
+use super::target::Target;
+use super::timing::Timing;
+use super::udp::UdpProbe;
+use super::{HostPorts, PortScanner, ScanType};
+
+// The engine behind the raw-packet scans. One loop sends every probe and one
+// thread reads every reply, however many hosts and ports are involved. Probes to
+// the hosts being scanned are interleaved, and how many may be awaiting an
+// answer at once is capped by the probe window rather than by a thread count.
+
+// Longest the send loop sleeps before looking for work again
+const MAX_WAIT: Duration = Duration::from_millis(100);
+
+// What a reply said about one of our probes.
+pub(super) struct Answer {
+    pub port: u16,
+    pub state: PortState,
+    pub reason: Reason,
+    // Packet to send back so the target can free what the probe half-opened
+    pub teardown: Option<Vec<u8>>,
+}
+
+// Builds one scan type's probes to one host and recognises the replies to them.
+pub(super) trait Prober: Send + Sync {
+    // The transport packet to send to a port
+    fn probe(&self, port: u16) -> Vec<u8>;
+
+    // Match a packet from the host in the scanned protocol
+    fn classify(&self, reply: &Reply) -> Option<Answer>;
+
+    // Match an ICMP unreachable quoting one of our probes
+    fn classify_unreachable(&self, unreachable: &Unreachable) -> Option<Answer>;
+
+    // What a port that never answered is taken to be
+    fn unanswered_state(&self) -> PortState;
+}
+
+fn prober(scan_type: ScanType, route: Route) -> Arc<dyn Prober> {
+    match scan_type {
+        ScanType::Udp => Arc::new(UdpProbe::new(route)),
+        ScanType::SctpInit | ScanType::SctpCookieEcho => Arc::new(SctpProbe::new(scan_type)),
+        _ => Arc::new(TcpProbe::new(route, scan_type)),
+    }
+}
+
+// Progress of the scan of one host.
+struct HostScan {
+    target: Target,
+    route: Route,
+    protocol: Transport,
+    prober: Arc<dyn Prober>,
+    timing: Timing,
+    // Ports yet to be probed, and those due a retransmission
+    fresh: VecDeque<u16>,
+    retries: VecDeque<u16>,
+    // When each port's latest probe went out and how many have gone out
+    sent: HashMap<u16, (Instant, u32)>,
+    // Ports awaiting an answer, and the probes sent to them oldest first. Probes
+    // to ports that have since been answered are skipped.
+    waiting: HashSet<u16>,
+    in_flight: VecDeque<(u16, Instant)>,
+    finished: HashSet<u16>,
+    next_send: Instant,
+}
+
+impl HostScan {
+    fn has_work(&self) -> bool {
+        !self.fresh.is_empty() || !self.retries.is_empty()
+    }
+
+    fn done(&self) -> bool {
+        !self.has_work() && self.waiting.is_empty()
+    }
+
+    fn next_port(&mut self) -> Option<u16> {
+        while let Some(port) = self.retries.pop_front() {
+            if !self.finished.contains(&port) {
+                return Some(port);
+            }
+        }
+        self.fresh.pop_front()
+    }
+
+    fn sent(&mut self, port: u16) {
+        let now = Instant::now();
+        let tries = self.sent.get(&port).map_or(0, |&(_, tries)| tries);
+        self.sent.insert(port, (now, tries + 1));
+        self.waiting.insert(port);
+        self.in_flight.push_back((port, now));
+        self.next_send = now + self.timing.delay();
+    }
+
+    // Stream the first answer for a port; duplicates and late copies are ignored.
+    fn finish(
+        &mut self,
+        port: u16,
+        state: PortState,
+        reason: Reason,
+        ttl: Option<u8>,
+        tx: &CrossbeamSender<ScanEvent>,
+    ) {
+        if !self.finished.insert(port) {
+            return;
+        }
+        self.waiting.remove(&port);
+
+        let mut result = PortResult::new(&self.target, port, self.protocol, state, reason);
+        result.ttl = ttl;
+        if reason != Reason::NoResponse
+            && let Some(&(sent, tries)) = self.sent.get(&port)
+        {
+            let rtt = sent.elapsed();
+            self.timing.answered(tries - 1, rtt);
+            result.rtt = Some(rtt);
+        }
+        let _ = tx.send(ScanEvent::Port(result));
+    }
+
+    // Give up on the probes that have waited out the timeout, queueing their ports
+    // for another try while the timing allows.
+    fn expire(&mut self, now: Instant, tx: &CrossbeamSender<ScanEvent>) {
+        let timeout = self.timing.timeout();
+        while let Some(&(port, sent)) = self.in_flight.front() {
+            if !self.waiting.contains(&port) {
+                self.in_flight.pop_front();
+                continue;
+            }
+            if now < sent + timeout {
+                break;
+            }
+
+            self.in_flight.pop_front();
+            self.waiting.remove(&port);
+            self.timing.timed_out();
+            let tries = self.sent.get(&port).map_or(0, |&(_, tries)| tries);
+            if self.timing.retry_allowed(tries) {
+                self.retries.push_back(port);
+            } else {
+                let state = self.prober.unanswered_state();
+                self.finish(port, state, Reason::NoResponse, None, tx);
+            }
+        }
+    }
+
+    // When the loop next has something to do for this host.
+    fn deadline(&self, can_send: bool) -> Option<Instant> {
+        let expiry = self.in_flight.front().map(|&(_, sent)| sent + self.timing.timeout());
+        let can_send = can_send && self.waiting.len() < self.timing.window();
+        let send = (can_send && self.has_work()).then_some(self.next_send);
+        expiry.into_iter().chain(send).min()
+    }
+}
+
+// Senders for the scanned protocol, one per address family.
+struct Sockets {
+    v4: Option<RawSender>,
+    v6: Option<RawSender>,
+}
+
+impl Sockets {
+    fn send(&mut self, route: &Route, packet: &[u8]) -> io::Result<()> {
+        let sender = match route {
+            Route::V4(..) => self.v4.as_mut(),
+            Route::V6(..) => self.v6.as_mut(),
+        };
+        sender.expect("socket opened for every address family scanned").send(route, packet)
+    }
+}
+
+impl PortScanner {
+    // Scan the hosts with crafted packets, up to `max_hosts` of them at a time.
+    // Discovery's latency seeds each host's round-trip estimate. Once cancelled,
+    // whatever is still in flight is abandoned.
+    pub(super) fn raw_scan(
+        &self,
+        hosts: &[HostPorts],
+        tx: CrossbeamSender<ScanEvent>,
+        cancel: &CancelToken,
+        progress: &ProgressMeter,
+    ) -> io::Result<()> {
+        let transport = self.scan_type.transport();
+        let protocol = match transport {
+            Transport::Tcp => IpNextHeaderProtocols::Tcp,
+            Transport::Udp => IpNextHeaderProtocols::Udp,
+            Transport::Sctp => IpNextHeaderProtocols::Sctp,
+        };
+
+        let mut pending = VecDeque::new();
+        for host in hosts {
+            match Route::to(host.target.address) {
+                Ok(route) => pending.push_back((host, route)),
+                Err(e) => eprintln!("Skipping {}: {}", host.target.address, e),
+            }
+        }
+
+        // One socket for the scanned protocol and one for ICMP per address family
+        let mut sockets = Sockets { v4: None, v6: None };
+        let mut receivers = Vec::new();
+        let mut icmp = Vec::new();
+        for (_, route) in &pending {
+            let sender = match route {
+                Route::V4(..) => &mut sockets.v4,
+                Route::V6(..) => &mut sockets.v6,
+            };
+            if sender.is_none() {
+                let (tx, rx) = raw::open(route, protocol)?;
+                let (_, icmp_rx) = raw::open(route, IpNextHeaderProtocols::Icmp)?;
+                *sender = Some(tx);
+                receivers.extend([rx, icmp_rx]);
+                icmp.extend([false, true]);
+            }
+        }
+        if receivers.is_empty() {
+            return Ok(());
+        }
+
+        let probers: Arc<Mutex<HashMap<IpAddr, Arc<dyn Prober>>>> = Default::default();
+        let (answer_tx, answer_rx) = crossbeam_channel::unbounded();
+        let done = Arc::new(AtomicBool::new(false));
+        let listener = {
+            let probers = probers.clone();
+            raw::listen_all(receivers, done.clone(), move |index, reply| {
+                let (address, answer) = if icmp[index] {
+                    // Unreachables may come from anywhere along the path, so go by
+                    // who the quoted probe was for
+                    let Some(unreachable) = raw::unreachable(reply) else {
+                        return;
+                    };
+                    if unreachable.protocol != protocol {
+                        return;
+                    }
+                    let prober = probers.lock().unwrap().get(&unreachable.destination).cloned();
+                    let answer = prober.and_then(|prober| prober.classify_unreachable(&unreachable));
+                    (unreachable.destination, answer)
+                } else {
+                    let prober = probers.lock().unwrap().get(&reply.source).cloned();
+                    (reply.source, prober.and_then(|prober| prober.classify(reply)))
+                };
+                if let Some(answer) = answer {
+                    let _ = answer_tx.send((address, answer, reply.hop_limit));
+                }
+            })
+        };
+
+        // The slow templates are about not being noticed, so they keep a single
+        // probe out at a time
+        let window = if self.timing.serial() { 1 } else { self.max_in_flight.max(1) };
+        let mut active: Vec<HostScan> = Vec::new();
+        let mut cursor = 0;
+
+        let scanned = loop {
+            while active.len() < self.max_hosts.max(1)
+                && let Some((host, route)) = pending.pop_front()
+            {
+                let prober = prober(self.scan_type, route);
+                probers.lock().unwrap().insert(host.target.address, prober.clone());
+                active.push(HostScan {
+                    target: host.target.clone(),
+                    route,
+                    protocol: transport,
+                    prober,
+                    timing: Timing::new(self.timing, self.max_retries, host.latency),
+                    fresh: host.ports.iter().copied().collect(),
+                    retries: VecDeque::new(),
+                    sent: HashMap::new(),
+                    waiting: HashSet::new(),
+                    in_flight: VecDeque::new(),
+                    finished: HashSet::new(),
+                    next_send: Instant::now(),
+                });
+            }
+            if active.is_empty() || cancel.is_cancelled() {
+                break Ok(());
+            }
+
+            for received in answer_rx.try_iter() {
+                take_answer(&mut active, &mut sockets, received, &tx, progress);
+            }
+
+            let now = Instant::now();
+            for host in &mut active {
+                host.expire(now, &tx);
+            }
+
+            // Hand out probes round-robin until the window is full or no host
+            // is due one
+            let mut in_flight: usize = active.iter().map(|host| host.waiting.len()).sum();
+            let mut idle = 0;
+            while in_flight < window && idle < active.len() {
+                cursor = (cursor + 1) % active.len();
+                let host = &mut active[cursor];
+                let due = host.next_send <= Instant::now()
+                    && host.waiting.len() < host.timing.window();
+                let port = if due { host.next_port() } else { None };
+                let Some(port) = port else {
+                    idle += 1;
+                    continue;
+                };
+
+                if let Err(e) = sockets.send(&host.route, &host.prober.probe(port)) {
+                    eprintln!("Error sending probe to {} port {}: {}", host.target.address, port, e);
+                }
+                host.sent(port);
+                progress.sent(1);
+                in_flight += 1;
+                idle = 0;
+            }
+            progress.tick();
+
+            active.retain(|host| {
+                let done = host.done();
+                if done {
+                    probers.lock().unwrap().remove(&host.target.address);
+                }
+                !done
+            });
+
+            let can_send = in_flight < window;
+            let wait = active
+                .iter()
+                .filter_map(|host| host.deadline(can_send))
+                .min()
+                .map_or(MAX_WAIT, |deadline| deadline.saturating_duration_since(Instant::now()))
+                .min(MAX_WAIT);
+            match answer_rx.recv_timeout(wait) {
+                Ok(received) => take_answer(&mut active, &mut sockets, received, &tx, progress),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    break Err(io::Error::other("Stopped receiving replies"));
+                }
+            }
+        };
+
+        done.store(true, Ordering::Relaxed);
+        listener.join().unwrap();
+        scanned
+    }
+}
+
+// Record an answer against the host it came from, tearing down whatever the probe
+// half-opened.
+fn take_answer(
+    active: &mut [HostScan],
+    sockets: &mut Sockets,
+    (address, answer, ttl): (IpAddr, Answer, Option<u8>),
+    tx: &CrossbeamSender<ScanEvent>,
+    progress: &ProgressMeter,
+) {
+    let Some(host) = active.iter_mut().find(|host| host.target.address == address) else {
+        return;
+    };
+    // Duplicate and retransmitted answers were counted, and torn down, the
+    // first time round
+    if !host.sent.contains_key(&answer.port) || host.finished.contains(&answer.port) {
+        return;
+    }
+    progress.received(1);
+
+    if let Some(teardown) = &answer.teardown {
+        let _ = sockets.send(&host.route, teardown);
+    }
+    host.finish(answer.port, answer.state, answer.reason, ttl, tx);
+}
@@ -0,0 +1,547 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use crossbeam_channel::Sender as CrossbeamSender;
+use serde::{Deserialize, Serialize};
+
+use crate::ratelimit::RateLimit;
+
+mod alert;
+mod checkpoint;
+mod connect;
+mod diff;
+mod discovery;
+mod dns;
+mod engine;
+mod os;
+mod payloads;
+mod ports;
+mod probes;
+mod progress;
+mod raw;
+mod report;
+mod result;
+mod sctp;
+mod service;
+mod syn;
+mod target;
+mod timing;
+mod udp;
+
+pub use alert::Alerts;
+pub use checkpoint::{Checkpoint, CheckpointError};
+pub use diff::{DiffFormat, ScanDiff};
+pub use discovery::DiscoveryConfig;
+pub use dns::AddressChoice;
+pub use ports::PortSpecError;
+pub use progress::{CancelToken, Progress, ScanPhase};
+pub use report::{ExportFormat, ScanReport};
+pub use result::{HostResult, HostStatus, HostnameSource, PortResult, PortState, Reason, ScanEvent, Transport};
+pub use target::TargetError;
+pub use timing::TimingTemplate;
+use checkpoint::Recorder;
+use dns::Resolver;
+use os::OsDetector;
+use ports::PortSpec;
+use progress::ProgressMeter;
+use raw::Route;
+use rand::seq::SliceRandom;
+use service::ServiceDetector;
+use target::{Target, TargetSpec};
+
+#[derive(PartialEq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum ScanType {
+    TcpSyn,
+    TcpConnect,
+    Udp,
+    TcpFin,
+    TcpNull,
+    TcpXmas,
+    TcpAck,
+    TcpWindow,
+    TcpMaimon,
+    SctpInit,
+    SctpCookieEcho,
+}
+
+impl ScanType {
+    fn transport(self) -> Transport {
+        match self {
+            ScanType::Udp => Transport::Udp,
+            ScanType::SctpInit | ScanType::SctpCookieEcho => Transport::Sctp,
+            _ => Transport::Tcp,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanConfig {
+    pub target: String,
+    pub target_file: Option<PathBuf>,
+    pub exclude: String,
+    // Empty for the system resolver, otherwise "address[:port]"
+    pub dns_server: String,
+    pub address_choice: AddressChoice,
+    pub reverse_dns: bool,
+    pub discovery: DiscoveryConfig,
+    // e.g. "22,80,8000-8100,U:53", "top 100" or "http,ssh"
+    pub ports: String,
+    // Probe the ports in random order rather than walking them in sequence
+    pub randomize_ports: bool,
+    pub scan_type: ScanType,
+    // How many raw-packet probes may await an answer at once, across all hosts
+    pub max_in_flight: usize,
+    // How many hosts the raw-packet scans work on at once
+    pub max_hosts: usize,
+    // Pacing and patience of the raw-packet scans
+    pub timing: TimingTemplate,
+    // Overrides how often the timing template resends unanswered probes
+    pub max_retries: Option<u32>,
+    // Probe open ports to identify the service, product and version behind them
+    pub service_detection: bool,
+    // Probe file, or directory of them, to use alongside the bundled probes
+    pub service_probes: Option<PathBuf>,
+    // Fingerprint each live host's TCP/IP stack; needs raw sockets
+    pub os_detection: bool,
+    // Used by the connect scan and service detection
+    pub connect_timeout: Duration,
+    pub max_connections: usize,
+    // Where to keep a checkpoint the scan can be resumed from
+    pub checkpoint: Option<PathBuf>,
+    // The rate limit in force when the scan started, which headless runs of
+    // the scan put back in force
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+}
+
+impl Default for ScanConfig {
+    fn default() -> Self {
+        Self {
+            target: "127.0.0.1".to_string(),
+            target_file: None,
+            exclude: String::new(),
+            dns_server: String::new(),
+            address_choice: AddressChoice::FirstIpv4,
+            reverse_dns: false,
+            discovery: DiscoveryConfig::default(),
+            ports: "1-1024".to_string(),
+            randomize_ports: false,
+            scan_type: ScanType::TcpSyn,
+            max_in_flight: 1000,
+            max_hosts: 64,
+            timing: TimingTemplate::Normal,
+            max_retries: None,
+            service_detection: false,
+            service_probes: None,
+            os_detection: false,
+            connect_timeout: Duration::from_millis(1000),
+            max_connections: 256,
+            checkpoint: None,
+            rate_limit: RateLimit::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScanError {
+    Target(TargetError),
+    Ports(PortSpecError),
+    Checkpoint(CheckpointError),
+    Io(io::Error),
+    Cancelled,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::Target(e) => e.fmt(f),
+            ScanError::Ports(e) => e.fmt(f),
+            ScanError::Checkpoint(e) => e.fmt(f),
+            ScanError::Io(e) => e.fmt(f),
+            ScanError::Cancelled => f.write_str("Scan cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {}
+
+impl From<TargetError> for ScanError {
+    fn from(e: TargetError) -> Self {
+        ScanError::Target(e)
+    }
+}
+
+impl From<PortSpecError> for ScanError {
+    fn from(e: PortSpecError) -> Self {
+        ScanError::Ports(e)
+    }
+}
+
+impl From<CheckpointError> for ScanError {
+    fn from(e: CheckpointError) -> Self {
+        ScanError::Checkpoint(e)
+    }
+}
+
+impl From<io::Error> for ScanError {
+    fn from(e: io::Error) -> Self {
+        ScanError::Io(e)
+    }
+}
+
+pub struct PortScanner {
+    targets: Vec<Target>,
+    resolver: Resolver,
+    reverse_dns: bool,
+    discovery: DiscoveryConfig,
+    ports: Vec<u16>,
+    randomize_ports: bool,
+    scan_type: ScanType,
+    max_in_flight: usize,
+    max_hosts: usize,
+    timing: TimingTemplate,
+    max_retries: Option<u32>,
+    services: Option<ServiceDetector>,
+    os_detection: bool,
+    connect_timeout: Duration,
+    max_connections: usize,
+    recorder: Option<Arc<Recorder>>,
+    // The work an earlier run recorded in the checkpoint being resumed
+    resumed: Option<Checkpoint>,
+}
+
+// A live host and the ports left to scan on it, in the order to scan them.
+struct HostPorts {
+    target: Target,
+    // Round trip host discovery measured, if it did
+    latency: Option<Duration>,
+    ports: Vec<u16>,
+}
+
+impl PortScanner {
+    pub fn new(config: &ScanConfig) -> Result<Self, ScanError> {
+        let protocol = config.scan_type.transport();
+        let ports = PortSpec::parse(&config.ports)?.ports(protocol)?.to_vec();
+        if ports.is_empty() {
+            return Err(PortSpecError::Empty(protocol).into());
+        }
+        
+        let resolver = Resolver::parse(&config.dns_server)
+            .ok_or_else(|| TargetError::DnsServer(config.dns_server.clone()))?;
+        
+        let mut spec = TargetSpec::parse(&config.target, &config.exclude)?;
+        if let Some(path) = &config.target_file {
+            spec.add_file(path)?;
+        }
+        
+        let targets = spec.targets(&resolver, config.address_choice)?;
+        
+        Ok(Self {
+            targets,
+            resolver,
+            reverse_dns: config.reverse_dns,
+            discovery: config.discovery.clone(),
+            ports,
+            randomize_ports: config.randomize_ports,
+            scan_type: config.scan_type,
+            max_in_flight: config.max_in_flight,
+            max_hosts: config.max_hosts,
+            timing: config.timing,
+            max_retries: config.max_retries,
+            services: if config.service_detection {
+                Some(ServiceDetector::new(config.service_probes.as_deref())?)
+            } else {
+                None
+            },
+            os_detection: config.os_detection,
+            connect_timeout: config.connect_timeout,
+            max_connections: config.max_connections.max(1),
+            recorder: config
+                .checkpoint
+                .as_deref()
+                .map(|path| Arc::new(Recorder::new(path, Checkpoint::new(config)))),
+            resumed: None,
+        })
+    }
+    
+    // Pick up the scan saved in a checkpoint loaded from `path`, with the
+    // settings it was started with, carrying on saving to the same file.
+    pub fn from_checkpoint(path: &Path, checkpoint: Checkpoint) -> Result<Self, ScanError> {
+        let mut config = checkpoint.config.clone();
+        config.checkpoint = Some(path.to_path_buf());
+        
+        let mut scanner = Self::new(&config)?;
+        scanner.recorder = Some(Arc::new(Recorder::new(path, checkpoint.clone())));
+        scanner.resumed = Some(checkpoint);
+        Ok(scanner)
+    }
+    
+    // Find the live hosts, scan their ports, then fingerprint them and identify
+    // their services, streaming results and progress as they arrive. Cancelling
+    // stops the scan at the next probe and returns ScanError::Cancelled. The
+    // checkpoint, if there is one, is saved however the scan ends.
+    pub fn scan(
+        &mut self,
+        tx: CrossbeamSender<ScanEvent>,
+        cancel: &CancelToken,
+    ) -> Result<(), ScanError> {
+        let scanned = self.run(&tx, cancel);
+        if let Some(recorder) = &self.recorder {
+            recorder.save();
+        }
+        scanned
+    }
+    
+    fn run(&mut self, tx: &CrossbeamSender<ScanEvent>, cancel: &CancelToken) -> Result<(), ScanError> {
+        let resumed = self.resumed.take();
+        let hosts = match resumed.as_ref().and_then(|checkpoint| checkpoint.hosts.clone()) {
+            // Discovery finished before, no need to go through it again
+            Some(hosts) => hosts,
+            None if self.discovery.skip => self
+                .targets
+                .iter()
+                .map(|target| HostStatus {
+                    address: target.address,
+                    hostname: target.hostname.clone(),
+                    hostname_source: HostnameSource::User,
+                    up: true,
+                    reason: Reason::UserSet,
+                    mac: None,
+                    vendor: None,
+                    latency: None,
+                    os: None,
+                })
+                .collect(),
+            None => {
+                let total = self.targets.len() * discovery::DISCOVERY_ATTEMPTS;
+                let progress = Arc::new(ProgressMeter::new(ScanPhase::Discovery, total, tx));
+                let hosts = discovery::discover(
+                    &self.targets,
+                    &self.discovery,
+                    self.max_connections,
+                    self.connect_timeout,
+                    cancel,
+                    progress.clone(),
+                )?;
+                if cancel.is_cancelled() {
+                    return Err(ScanError::Cancelled);
+                }
+                progress.finish();
+                hosts
+            }
+        };
+        if let Some(recorder) = &self.recorder {
+            recorder.discovered(&hosts);
+        }
+        
+        let mut live = Vec::new();
+        for mut host in hosts {
+            if !host.up {
+                self.send(tx, ScanEvent::Host(host));
+                continue;
+            }
+            
+            if self.reverse_dns && host.hostname.is_none() && !cancel.is_cancelled() {
+                match self.resolver.reverse(host.address) {
+                    Ok(Some(hostname)) => {
+                        host.hostname = Some(hostname);
+                        host.hostname_source = HostnameSource::Ptr;
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!("Reverse lookup of {} failed: {}", host.address, e),
+                }
+            }
+            self.send(tx, ScanEvent::Host(host.clone()));
+            live.push(host);
+        }
+        if cancel.is_cancelled() {
+            return Err(ScanError::Cancelled);
+        }
+        
+        // Hand back what the earlier run found, with any service details it got
+        let (previous, os_detected, services_detected) = match resumed {
+            Some(checkpoint) => {
+                let os_detected: HashSet<IpAddr> = checkpoint.os_detected.into_iter().collect();
+                let services_detected: HashSet<(IpAddr, u16)> =
+                    checkpoint.services_detected.into_iter().collect();
+                (checkpoint.ports, os_detected, services_detected)
+            }
+            None => Default::default(),
+        };
+        for result in &previous {
+            let _ = tx.send(ScanEvent::Port(result.clone()));
+        }
+        
+        let mut ports = self.scan_ports(&live, &previous, tx, cancel)?;
+        for result in previous {
+            if matches!(result.state, PortState::Open | PortState::Closed) {
+                ports.entry(result.host).or_default().push(result);
+            }
+        }
+        
+        if self.os_detection {
+            let mut pending = Vec::new();
+            for host in live.iter_mut().filter(|host| !os_detected.contains(&host.address)) {
+                match Route::to(host.address) {
+                    Ok(route) => pending.push((host, route)),
+                    Err(e) => eprintln!("Skipping OS detection of {}: {}", host.address, e),
+                }
+            }
+            // One set of raw sockets serves every host, and without root the
+            // whole phase is skipped rather than failing host by host
+            let detector = match OsDetector::open(pending.iter().map(|(_, route)| route)) {
+                Ok(detector) => Some(detector),
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    eprintln!("Skipping OS detection, it needs root: {}", e);
+                    None
+                }
+                Err(e) => return Err(e.into()),
+            };
+            if let Some(mut detector) = detector {
+                let progress = ProgressMeter::new(ScanPhase::OsDetection, pending.len(), tx);
+                for (host, route) in pending {
+                    if cancel.is_cancelled() {
+                        return Err(ScanError::Cancelled);
+                    }
+                    let tcp_port = |state| {
+                        ports
+                            .get(&host.address)
+                            .into_iter()
+                            .flatten()
+                            .find(|result| result.protocol == Transport::Tcp && result.state == state)
+                            .map(|result| result.port)
+                    };
+                    // Without a known closed port, a random high one will most likely do
+                    let closed = tcp_port(PortState::Closed)
+                        .unwrap_or_else(|| rand::random::<u16>() % 16384 + 32768);
+                    host.os = detector.detect(route, tcp_port(PortState::Open), closed)?;
+                    if host.os.is_some() {
+                        progress.received(1);
+                    }
+                    self.send(tx, ScanEvent::Host(host.clone()));
+                    if let Some(recorder) = &self.recorder {
+                        recorder.os_detected(host.address);
+                    }
+                    progress.done(1);
+                }
+                progress.finish();
+            }
+        }
+        
+        if let Some(services) = &self.services {
+            let open: Vec<PortResult> = ports
+                .into_values()
+                .flatten()
+                .filter(|result| result.state == PortState::Open)
+                .filter(|result| !services_detected.contains(&(result.host, result.port)))
+                .collect();
+            let progress = ProgressMeter::new(ScanPhase::ServiceDetection, open.len(), tx);
+            services.detect_all(open, self.connect_timeout, cancel, &progress, |result, identified| {
+                let (host, port) = (result.host, result.port);
+                if identified {
+                    self.send(tx, ScanEvent::Service(result));
+                }
+                if let Some(recorder) = &self.recorder {
+                    recorder.service_detected(host, port);
+                }
+            });
+            if cancel.is_cancelled() {
+                return Err(ScanError::Cancelled);
+            }
+            progress.finish();
+        }
+        Ok(())
+    }
+    
+    // Pass an event on, keeping the checkpoint up to date with it.
+    fn send(&self, tx: &CrossbeamSender<ScanEvent>, event: ScanEvent) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&event);
+        }
+        let _ = tx.send(event);
+    }
+    
+    // Port scan the live hosts together, leaving out the ports `previous` has
+    // results for. Results are passed on as they come, and each host's open and
+    // closed ports are returned.
+    fn scan_ports(
+        &mut self,
+        hosts: &[HostStatus],
+        previous: &[PortResult],
+        tx: &CrossbeamSender<ScanEvent>,
+        cancel: &CancelToken,
+    ) -> Result<HashMap<IpAddr, Vec<PortResult>>, ScanError> {
+        let mut scanned: HashMap<IpAddr, HashSet<u16>> = HashMap::new();
+        for result in previous {
+            scanned.entry(result.host).or_default().insert(result.port);
+        }
+        let jobs: Vec<HostPorts> = hosts
+            .iter()
+            .map(|host| {
+                let done = scanned.get(&host.address);
+                let mut ports: Vec<u16> = self
+                    .ports
+                    .iter()
+                    .copied()
+                    .filter(|port| done.is_none_or(|done| !done.contains(port)))
+                    .collect();
+                // Shuffled afresh for each host if asked to
+                if self.randomize_ports {
+                    ports.shuffle(&mut rand::thread_rng());
+                }
+                HostPorts {
+                    target: Target {
+                        address: host.address,
+                        hostname: host.hostname.clone(),
+                    },
+                    latency: host.latency,
+                    ports,
+                }
+            })
+            .filter(|job| !job.ports.is_empty())
+            .collect();
+        
+        let total = jobs.iter().map(|job| job.ports.len()).sum();
+        let progress = Arc::new(ProgressMeter::new(ScanPhase::PortScan, total, tx));
+        let (host_tx, host_rx) = crossbeam_channel::unbounded();
+        let forwarder = {
+            let tx = tx.clone();
+            let progress = progress.clone();
+            let recorder = self.recorder.clone();
+            thread::spawn(move || {
+                let mut ports: HashMap<IpAddr, Vec<PortResult>> = HashMap::new();
+                for event in host_rx {
+                    if let ScanEvent::Port(result) = &event {
+                        progress.done(1);
+                        if matches!(result.state, PortState::Open | PortState::Closed) {
+                            ports.entry(result.host).or_default().push(result.clone());
+                        }
+                    }
+                    if let Some(recorder) = &recorder {
+                        recorder.record(&event);
+                    }
+                    let _ = tx.send(event);
+                }
+                ports
+            })
+        };
+        
+        // The engines drop their senders on return, which ends the forwarder
+        let scanned = match self.scan_type {
+            ScanType::TcpConnect => self.connect_scan(&jobs, host_tx, cancel, &progress),
+            _ => self.raw_scan(&jobs, host_tx, cancel, &progress),
+        };
+        let ports = forwarder.join().unwrap();
+        scanned?;
+        if cancel.is_cancelled() {
+            return Err(ScanError::Cancelled);
+        }
+        progress.finish();
+        Ok(ports)
+    }
+}
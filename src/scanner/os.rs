@@ -0,0 +1,495 @@
+use pnet::packet::icmp::IcmpTypes;
+use pnet::packet::icmpv6::Icmpv6Types;
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::tcp::{MutableTcpPacket, TcpFlags, TcpOption, TcpPacket};
+use pnet::packet::util;
+use pnet::packet::Packet;
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use super::raw::{self, RawSender, Reply, Route};
+use super::result::OsGuess;
+
+// Active OS detection: SYNs with a fixed set of options to an open port, a SYN to
+// a closed one and ICMP echo requests with an unusual code. How the target's stack
+// answers is reduced to a handful of tests and compared against `os-fingerprints`.
+
+const FINGERPRINTS: &str = include_str!("os-fingerprints");
+
+static PARSED: OnceLock<Vec<Fingerprint>> = OnceLock::new();
+
+// Several SYN-ACKs are needed to tell how IP IDs are assigned
+const SEQUENCE_PROBES: u16 = 6;
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+const RESPONSE_WAIT: Duration = Duration::from_secs(1);
+const ECHO_PROBES: u16 = 2;
+// Non-zero code on the echo requests; some stacks copy it into the reply
+const ECHO_CODE: u8 = 9;
+const TCP_HEADER_LEN: usize = 40;
+
+// How much each test counts towards a match, roughly by how well it separates
+// operating systems.
+const WEIGHTS: &[(&str, u32)] = &[
+    ("O", 20),
+    ("W", 10),
+    ("DF", 5),
+    ("TTL", 15),
+    ("TI", 10),
+    ("CF", 5),
+    ("CW", 5),
+    ("ICD", 10),
+    ("ITTL", 10),
+];
+
+// Guesses resting on fewer points than this aren't worth reporting
+const MIN_TESTED: u32 = 25;
+
+struct Fingerprint {
+    name: String,
+    family: String,
+    tests: HashMap<String, Vec<String>>,
+}
+
+impl Fingerprint {
+    // Points scored and points possible against the observed test results.
+    fn score(&self, observed: &HashMap<&str, String>) -> (u32, u32) {
+        let (mut score, mut possible) = (0, 0);
+        for (test, weight) in WEIGHTS {
+            let (Some(value), Some(expected)) = (observed.get(test), self.tests.get(*test)) else {
+                continue;
+            };
+            possible += weight;
+            let matches = expected.iter().any(|expected| match expected.strip_suffix('*') {
+                Some(prefix) => value.starts_with(prefix),
+                None => value == expected,
+            });
+            if matches {
+                score += weight;
+            }
+        }
+        (score, possible)
+    }
+}
+
+fn fingerprints() -> &'static [Fingerprint] {
+    PARSED.get_or_init(parse_fingerprints)
+}
+
+fn parse_fingerprints() -> Vec<Fingerprint> {
+    let mut fingerprints: Vec<Fingerprint> = Vec::new();
+    for line in FINGERPRINTS.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("Fingerprint ") {
+            fingerprints.push(Fingerprint {
+                name: name.to_string(),
+                family: String::new(),
+                tests: HashMap::new(),
+            });
+            continue;
+        }
+        let Some(fingerprint) = fingerprints.last_mut() else {
+            continue;
+        };
+        if let Some(family) = line.strip_prefix("Family ") {
+            fingerprint.family = family.to_string();
+            continue;
+        }
+        for test in line.split_whitespace() {
+            if let Some((name, values)) = test.split_once('=') {
+                let values = values.split('|').map(str::to_string).collect();
+                fingerprint.tests.insert(name.to_string(), values);
+            }
+        }
+    }
+    fingerprints
+}
+
+// What came back from the target, as far as the tests are concerned.
+struct TcpResponse {
+    source_port: u16,
+    flags: u16,
+    window: u16,
+    options: String,
+    hop_limit: Option<u8>,
+    identification: Option<u16>,
+    dont_fragment: Option<bool>,
+}
+
+struct EchoResponse {
+    code: u8,
+    hop_limit: Option<u8>,
+}
+
+#[derive(Clone)]
+struct OsProbe {
+    route: Route,
+    // The sequence probes use consecutive ports from here, the closed port probe the next one
+    source_port: u16,
+    identifier: u16,
+}
+
+impl OsProbe {
+    fn syn(&self, tx: &mut RawSender, source_port: u16, port: u16) -> io::Result<()> {
+        let mut buffer = [0u8; TCP_HEADER_LEN];
+        let mut packet = MutableTcpPacket::new(&mut buffer).unwrap();
+        packet.set_source(source_port);
+        packet.set_destination(port);
+        packet.set_sequence(rand::random());
+        packet.set_data_offset((TCP_HEADER_LEN / 4) as u8);
+        packet.set_flags(TcpFlags::SYN);
+        packet.set_window(1024);
+        // The option set of nmap's first sequence probe
+        packet.set_options(&[
+            TcpOption::wscale(10),
+            TcpOption::nop(),
+            TcpOption::mss(1460),
+            TcpOption::timestamp(u32::MAX, 0),
+            TcpOption::sack_perm(),
+        ]);
+        let checksum = self.route.checksum(IpNextHeaderProtocols::Tcp, packet.packet(), 8);
+        packet.set_checksum(checksum);
+
+        tx.send(&self.route, packet.packet())
+    }
+
+    fn echo(&self, tx: &mut RawSender, sequence: u16) -> io::Result<()> {
+        let [id_high, id_low] = self.identifier.to_be_bytes();
+        let [seq_high, seq_low] = sequence.to_be_bytes();
+        let mut packet = vec![0, ECHO_CODE, 0, 0, id_high, id_low, seq_high, seq_low];
+        packet.extend_from_slice(&[0; 120]);
+        let checksum = match self.route {
+            Route::V4(..) => {
+                packet[0] = IcmpTypes::EchoRequest.0;
+                util::checksum(&packet, 1)
+            }
+            Route::V6(..) => {
+                packet[0] = Icmpv6Types::EchoRequest.0;
+                self.route.checksum(IpNextHeaderProtocols::Icmpv6, &packet, 1)
+            }
+        };
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+
+        tx.send(&self.route, &packet)
+    }
+
+    fn classify_tcp(&self, reply: &Reply) -> Option<TcpResponse> {
+        if reply.source != self.route.destination() {
+            return None;
+        }
+        let tcp = TcpPacket::new(reply.payload)?;
+        let port = tcp.get_destination();
+        if !(self.source_port..=self.source_port + SEQUENCE_PROBES).contains(&port) {
+            return None;
+        }
+        Some(TcpResponse {
+            source_port: port,
+            flags: tcp.get_flags(),
+            window: tcp.get_window(),
+            options: options_string(tcp.get_options_raw()),
+            hop_limit: reply.hop_limit,
+            identification: reply.identification,
+            dont_fragment: reply.dont_fragment,
+        })
+    }
+
+    fn classify_icmp(&self, reply: &Reply) -> Option<EchoResponse> {
+        let payload = reply.payload;
+        let echo_reply = match reply.source {
+            IpAddr::V4(_) => IcmpTypes::EchoReply.0,
+            IpAddr::V6(_) => Icmpv6Types::EchoReply.0,
+        };
+        if reply.source != self.route.destination()
+            || *payload.first()? != echo_reply
+            || payload.get(4..6)? != self.identifier.to_be_bytes()
+        {
+            return None;
+        }
+        Some(EchoResponse { code: payload[1], hop_limit: reply.hop_limit })
+    }
+}
+
+// The probe under way and what has come back for it so far.
+struct Responses {
+    probe: OsProbe,
+    tcp: Vec<TcpResponse>,
+    echo: Vec<EchoResponse>,
+}
+
+// Raw TCP and ICMP sockets opened once for the whole OS detection phase, with a
+// single listener handing replies to whichever host is being probed.
+pub struct OsDetector {
+    // TCP and ICMP senders, one pair per address family
+    v4: Option<(RawSender, RawSender)>,
+    v6: Option<(RawSender, RawSender)>,
+    current: Arc<Mutex<Option<Responses>>>,
+    done: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+}
+
+impl OsDetector {
+    // Open sockets for the address families of the routes to be probed. Fails
+    // with PermissionDenied without root.
+    pub fn open<'a>(routes: impl IntoIterator<Item = &'a Route>) -> io::Result<Self> {
+        let (mut v4, mut v6) = (None, None);
+        let mut receivers = Vec::new();
+        let mut icmp = Vec::new();
+        for route in routes {
+            let senders = match route {
+                Route::V4(..) => &mut v4,
+                Route::V6(..) => &mut v6,
+            };
+            if senders.is_none() {
+                let (tcp_tx, tcp_rx) = raw::open(route, IpNextHeaderProtocols::Tcp)?;
+                let (icmp_tx, icmp_rx) = raw::open(route, IpNextHeaderProtocols::Icmp)?;
+                *senders = Some((tcp_tx, icmp_tx));
+                receivers.extend([tcp_rx, icmp_rx]);
+                icmp.extend([false, true]);
+            }
+        }
+
+        let current: Arc<Mutex<Option<Responses>>> = Default::default();
+        let done = Arc::new(AtomicBool::new(false));
+        let listener = (!receivers.is_empty()).then(|| {
+            let current = current.clone();
+            raw::listen_all(receivers, done.clone(), move |index, reply| {
+                let mut current = current.lock().unwrap();
+                let Some(responses) = current.as_mut() else { return };
+                if icmp[index] {
+                    if let Some(response) = responses.probe.classify_icmp(reply) {
+                        responses.echo.push(response);
+                    }
+                } else if let Some(response) = responses.probe.classify_tcp(reply) {
+                    responses.tcp.push(response);
+                }
+            })
+        });
+        Ok(Self { v4, v6, current, done, listener })
+    }
+
+    // Probe the target and return the closest fingerprint. Without an open port
+    // the guess rests on the closed port and echo tests alone.
+    pub fn detect(&mut self, route: Route, open_port: Option<u16>, closed_port: u16) -> io::Result<Option<OsGuess>> {
+        let probe = OsProbe {
+            route,
+            source_port: rand::random::<u16>() % 16384 + 49152 - SEQUENCE_PROBES,
+            identifier: rand::random(),
+        };
+        let (tcp_tx, icmp_tx) = match probe.route {
+            Route::V4(..) => self.v4.as_mut(),
+            Route::V6(..) => self.v6.as_mut(),
+        }
+        .expect("sockets opened for every address family probed");
+
+        *self.current.lock().unwrap() = Some(Responses {
+            probe: probe.clone(),
+            tcp: Vec::new(),
+            echo: Vec::new(),
+        });
+        let sent = send_probes(&probe, tcp_tx, icmp_tx, open_port, closed_port);
+        if sent.is_ok() {
+            thread::sleep(RESPONSE_WAIT);
+        }
+        let responses = self.current.lock().unwrap().take().expect("probe under way");
+        sent?;
+
+        let observed = observe(&probe, &responses.tcp, &responses.echo);
+        Ok(best_match(&observed))
+    }
+}
+
+impl Drop for OsDetector {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(listener) = self.listener.take() {
+            listener.join().unwrap();
+        }
+    }
+}
+
+fn send_probes(
+    probe: &OsProbe,
+    tcp_tx: &mut RawSender,
+    icmp_tx: &mut RawSender,
+    open_port: Option<u16>,
+    closed_port: u16,
+) -> io::Result<()> {
+    if let Some(open_port) = open_port {
+        for index in 0..SEQUENCE_PROBES {
+            probe.syn(tcp_tx, probe.source_port + index, open_port)?;
+            thread::sleep(PROBE_INTERVAL);
+        }
+    }
+    probe.syn(tcp_tx, probe.source_port + SEQUENCE_PROBES, closed_port)?;
+    for sequence in 0..ECHO_PROBES {
+        probe.echo(icmp_tx, sequence)?;
+    }
+    Ok(())
+}
+
+// Reduce the responses to the test values used by the fingerprints.
+fn observe(
+    probe: &OsProbe,
+    tcp: &[TcpResponse],
+    echo: &[EchoResponse],
+) -> HashMap<&'static str, String> {
+    let mut observed = HashMap::new();
+    let closed_port = probe.source_port + SEQUENCE_PROBES;
+
+    let syn_acks: Vec<&TcpResponse> = tcp
+        .iter()
+        .filter(|response| response.source_port != closed_port)
+        .filter(|response| response.flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK)
+        .collect();
+    if let Some(first) = syn_acks.iter().min_by_key(|response| response.source_port) {
+        observed.insert("O", first.options.clone());
+        observed.insert("W", format!("{:X}", first.window));
+        if let Some(dont_fragment) = first.dont_fragment {
+            observed.insert("DF", yes_no(dont_fragment));
+        }
+        if let Some(ttl) = first.hop_limit {
+            observed.insert("TTL", initial_ttl(ttl).to_string());
+        }
+    }
+
+    let mut ids: Vec<(u16, u16)> = syn_acks
+        .iter()
+        .filter_map(|response| Some((response.source_port, response.identification?)))
+        .collect();
+    ids.sort();
+    ids.dedup_by_key(|(port, _)| *port);
+    let ids: Vec<u16> = ids.into_iter().map(|(_, id)| id).collect();
+    if let Some(class) = ip_id_class(&ids) {
+        observed.insert("TI", class.to_string());
+    }
+
+    let reset = tcp
+        .iter()
+        .find(|response| response.source_port == closed_port && response.flags & TcpFlags::RST != 0);
+    if let Some(reset) = reset {
+        let flags = if reset.flags & TcpFlags::ACK != 0 { "AR" } else { "R" };
+        observed.insert("CF", flags.to_string());
+        observed.insert("CW", format!("{:X}", reset.window));
+        if let (None, Some(ttl)) = (observed.get("TTL"), reset.hop_limit) {
+            observed.insert("TTL", initial_ttl(ttl).to_string());
+        }
+    }
+
+    if let Some(reply) = echo.first() {
+        let code = if reply.code == ECHO_CODE { "S" } else { "Z" };
+        observed.insert("ICD", code.to_string());
+        if let Some(ttl) = reply.hop_limit {
+            observed.insert("ITTL", initial_ttl(ttl).to_string());
+        }
+    }
+    observed
+}
+
+fn best_match(observed: &HashMap<&str, String>) -> Option<OsGuess> {
+    let mut best: Option<(&Fingerprint, u32, u32)> = None;
+    for fingerprint in fingerprints() {
+        let (score, possible) = fingerprint.score(observed);
+        if possible < MIN_TESTED {
+            continue;
+        }
+        // Compare score / possible without dividing, preferring more tests on a tie
+        let better = best.as_ref().is_none_or(|(_, best_score, best_possible)| {
+            let (ours, theirs) = (score * best_possible, best_score * possible);
+            ours > theirs || (ours == theirs && possible > *best_possible)
+        });
+        if better {
+            best = Some((fingerprint, score, possible));
+        }
+    }
+
+    let (fingerprint, score, possible) = best?;
+    Some(OsGuess {
+        name: fingerprint.name.clone(),
+        family: fingerprint.family.clone(),
+        confidence: (score * 100 / possible) as u8,
+    })
+}
+
+// Options in the order the target sent them, in the notation of `os-fingerprints`.
+fn options_string(options: &[u8]) -> String {
+    let mut string = String::new();
+    let mut offset = 0;
+    while let Some(&kind) = options.get(offset) {
+        let length = match kind {
+            0 => {
+                string.push('L');
+                break;
+            }
+            1 => {
+                string.push('N');
+                offset += 1;
+                continue;
+            }
+            _ => *options.get(offset + 1).unwrap_or(&0) as usize,
+        };
+        if length < 2 {
+            break;
+        }
+        let data = options.get(offset + 2..offset + length).unwrap_or_default();
+        match (kind, data) {
+            (2, _) => string.push('M'),
+            (3, [scale]) => string.push_str(&format!("W{:X}", scale)),
+            (4, _) => string.push('S'),
+            // Whether the timestamp value and echo reply are non-zero
+            (8, data) if data.len() == 8 => {
+                let nonzero = |bytes: &[u8]| if bytes.iter().any(|byte| *byte != 0) { '1' } else { '0' };
+                string.push('T');
+                string.push(nonzero(&data[..4]));
+                string.push(nonzero(&data[4..]));
+            }
+            _ => {}
+        }
+        offset += length;
+    }
+    string
+}
+
+// IP ID sequence class of successive responses.
+fn ip_id_class(ids: &[u16]) -> Option<&'static str> {
+    if ids.len() < 3 {
+        return None;
+    }
+    if ids.iter().all(|id| *id == 0) {
+        return Some("Z");
+    }
+    let differences: Vec<u16> = ids.windows(2).map(|pair| pair[1].wrapping_sub(pair[0])).collect();
+    let class = if differences.iter().all(|difference| *difference == 0) {
+        "C"
+    } else if differences.iter().any(|difference| *difference >= 20000) {
+        "RD"
+    } else if differences.iter().all(|difference| difference % 256 == 0 && *difference <= 5120) {
+        "BI"
+    } else if differences.iter().all(|difference| *difference < 10) {
+        "I"
+    } else if differences.iter().all(|difference| *difference < 1000) {
+        "RI"
+    } else {
+        "RD"
+    };
+    Some(class)
+}
+
+// The TTL the target most likely started with.
+fn initial_ttl(ttl: u8) -> u8 {
+    match ttl {
+        0..=32 => 32,
+        33..=64 => 64,
+        65..=128 => 128,
+        _ => 255,
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "Y" } else { "N" }.to_string()
+}
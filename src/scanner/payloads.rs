@@ -0,0 +1,50 @@
+// Protocol-specific UDP probe payloads. Most UDP services silently drop a datagram
+// they can't parse, so an empty probe would leave them looking open|filtered.
+
+// DNS: standard recursive query for the root NS records
+const DNS: &[u8] = &[
+    0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02,
+    0x00, 0x01,
+];
+
+// NTP: version 4 client request, everything else zeroed
+const NTP: &[u8] = &[
+    0xe3, 0x00, 0x04, 0xfa, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00,
+];
+
+// NetBIOS: node status (NBSTAT) request for the wildcard name "*"
+const NETBIOS_NS: &[u8] = b"\x80\xf0\x00\x10\x00\x01\x00\x00\x00\x00\x00\x00\
+\x20CKAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\x00\x00\x21\x00\x01";
+
+// SNMP: v1 get-request for sysDescr.0 with community "public"
+const SNMP: &[u8] = &[
+    0x30, 0x29, 0x02, 0x01, 0x00, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0, 0x1c,
+    0x02, 0x04, 0x71, 0xb4, 0xb5, 0x68, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30,
+    0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x01, 0x00, 0x05, 0x00,
+];
+
+// SSDP: discovery request for every service type
+const SSDP: &[u8] = b"M-SEARCH * HTTP/1.1\r\n\
+HOST: 239.255.255.250:1900\r\n\
+MAN: \"ssdp:discover\"\r\n\
+MX: 1\r\n\
+ST: ssdp:all\r\n\r\n";
+
+// mDNS: DNS-SD service enumeration
+const MDNS: &[u8] = b"\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\
+\x09_services\x07_dns-sd\x04_udp\x05local\x00\x00\x0c\x00\x01";
+
+pub fn udp_payload(port: u16) -> &'static [u8] {
+    match port {
+        53 => DNS,
+        123 => NTP,
+        137 => NETBIOS_NS,
+        161 => SNMP,
+        1900 => SSDP,
+        5353 => MDNS,
+        _ => &[],
+    }
+}
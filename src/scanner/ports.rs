@@ -0,0 +1,168 @@
+use std::fmt;
+
+use crate::services;
+
+use super::result::Transport;
+
+// Port specifications in the nmap style: "22,80,443,8000-8100,U:53,T:3389",
+// "top 100", or service names such as "http,ssh". A T:, U: or S: prefix limits
+// that term and the ones after it to TCP, UDP or SCTP; terms before any prefix
+// apply to all three.
+
+#[derive(Debug)]
+pub enum PortSpecError {
+    Invalid(String),
+    UnknownService(String),
+    Empty(Transport),
+    // "top N" asked for more ports than are ranked for the protocol
+    TooFewRanked(Transport, usize, usize),
+}
+
+impl fmt::Display for PortSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortSpecError::Invalid(term) => write!(f, "Invalid port specification \"{}\"", term),
+            PortSpecError::UnknownService(name) => write!(f, "Unknown service \"{}\"", name),
+            PortSpecError::Empty(protocol) => write!(f, "No {} ports to scan", protocol),
+            PortSpecError::TooFewRanked(protocol, requested, ranked) => write!(
+                f,
+                "Only {} {} ports are ranked, too few for \"top {}\"",
+                ranked, protocol, requested
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PortSpecError {}
+
+// One comma-separated term, less its protocol prefix.
+enum Term {
+    Range(u16, u16),
+    Top(usize),
+    Name,
+}
+
+impl Term {
+    fn parse(term: &str) -> Result<Self, PortSpecError> {
+        let invalid = || PortSpecError::Invalid(term.to_string());
+
+        // "top 100", "top100" or "top-100"
+        if let Some(top) = term.get(..3)
+            && top.eq_ignore_ascii_case("top")
+        {
+            let count = term[3..].trim_start().trim_start_matches('-');
+            let count = count.parse().ok().filter(|&count| count > 0).ok_or_else(invalid)?;
+            return Ok(Term::Top(count));
+        }
+
+        if !term.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+            return Ok(Term::Name);
+        }
+
+        // A port, "a-b", or a range left open at either end
+        let port = |s: &str, default: u16| {
+            let s = s.trim();
+            if s.is_empty() {
+                return Ok(default);
+            }
+            s.parse::<u16>().ok().filter(|&port| port > 0).ok_or_else(invalid)
+        };
+        let (start, end) = match term.split_once('-') {
+            Some((start, end)) => (port(start, 1)?, port(end, u16::MAX)?),
+            None => {
+                let port = port(term, 0)?;
+                (port, port)
+            }
+        };
+        if start > end {
+            return Err(invalid());
+        }
+        Ok(Term::Range(start, end))
+    }
+}
+
+// The ports to probe for each protocol, sorted and without duplicates.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PortSpec {
+    tcp: Vec<u16>,
+    udp: Vec<u16>,
+    sctp: Vec<u16>,
+    // "top N" terms the ranking couldn't fill: protocol, N and the ports
+    // ranked. Only an error for the protocol actually scanned.
+    short: Vec<(Transport, usize, usize)>,
+}
+
+impl PortSpec {
+    pub fn parse(spec: &str) -> Result<Self, PortSpecError> {
+        let mut ports = PortSpec::default();
+        let mut protocols = vec![Transport::Tcp, Transport::Udp, Transport::Sctp];
+
+        for term in spec.split(',').map(str::trim).filter(|term| !term.is_empty()) {
+            let mut term = term;
+            if let Some((prefix, rest)) = term.split_once(':') {
+                protocols = match prefix.trim().to_ascii_uppercase().as_str() {
+                    "T" => vec![Transport::Tcp],
+                    "U" => vec![Transport::Udp],
+                    "S" => vec![Transport::Sctp],
+                    _ => return Err(PortSpecError::Invalid(term.to_string())),
+                };
+                term = rest.trim();
+            }
+
+            match Term::parse(term)? {
+                Term::Range(start, end) => {
+                    for &protocol in &protocols {
+                        ports.list_mut(protocol).extend(start..=end);
+                    }
+                }
+                Term::Top(count) => {
+                    for &protocol in &protocols {
+                        let top = services::top(protocol, count);
+                        if top.len() < count {
+                            ports.short.push((protocol, count, top.len()));
+                        }
+                        ports.list_mut(protocol).extend(top);
+                    }
+                }
+                Term::Name => {
+                    // The name only has to exist for one of the protocols in play
+                    let matching: Vec<_> = services::ports(term)
+                        .iter()
+                        .filter(|(protocol, _)| protocols.contains(protocol))
+                        .collect();
+                    if matching.is_empty() {
+                        return Err(PortSpecError::UnknownService(term.to_string()));
+                    }
+                    for &&(protocol, port) in &matching {
+                        ports.list_mut(protocol).push(port);
+                    }
+                }
+            }
+        }
+
+        for list in [&mut ports.tcp, &mut ports.udp, &mut ports.sctp] {
+            list.sort_unstable();
+            list.dedup();
+        }
+        Ok(ports)
+    }
+
+    pub fn ports(&self, protocol: Transport) -> Result<&[u16], PortSpecError> {
+        if let Some(&(_, requested, ranked)) = self.short.iter().find(|short| short.0 == protocol) {
+            return Err(PortSpecError::TooFewRanked(protocol, requested, ranked));
+        }
+        Ok(match protocol {
+            Transport::Tcp => &self.tcp,
+            Transport::Udp => &self.udp,
+            Transport::Sctp => &self.sctp,
+        })
+    }
+
+    fn list_mut(&mut self, protocol: Transport) -> &mut Vec<u16> {
+        match protocol {
+            Transport::Tcp => &mut self.tcp,
+            Transport::Udp => &mut self.udp,
+            Transport::Sctp => &mut self.sctp,
+        }
+    }
+}
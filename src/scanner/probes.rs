@@ -0,0 +1,704 @@
+use regex::bytes::{Captures, Regex, RegexBuilder};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use super::result::Transport;
+
+// Probe and signature definitions in the nmap-service-probes format, so existing
+// fingerprint collections work as they are. Patterns needing PCRE features the
+// regex crate lacks (lookaround, backreferences) are skipped with a warning.
+
+const BUNDLED: &str = include_str!("service-probes");
+
+// Probes rarer than this are only sent to the ports they list
+const INTENSITY: u8 = 7;
+const DEFAULT_RARITY: u8 = 5;
+
+// What a probe response identified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceMatch {
+    pub service: String,
+    pub product: Option<String>,
+    pub version: Option<String>,
+    pub info: Option<String>,
+}
+
+pub enum Identified {
+    Hard(ServiceMatch),
+    // Only the service is known; other probes may still pin down the version
+    Soft(ServiceMatch),
+}
+
+#[derive(Clone, Copy)]
+struct PortRange {
+    // None for entries that apply to both protocols
+    protocol: Option<Transport>,
+    start: u16,
+    end: u16,
+}
+
+impl PortRange {
+    fn contains(&self, protocol: Transport, port: u16) -> bool {
+        self.protocol.is_none_or(|own| own == protocol) && (self.start..=self.end).contains(&port)
+    }
+}
+
+pub struct Probe {
+    pub protocol: Transport,
+    pub name: String,
+    pub payload: Vec<u8>,
+    pub wait: Option<Duration>,
+    rarity: u8,
+    ports: Vec<PortRange>,
+    fallback: Vec<String>,
+    matches: Vec<Match>,
+}
+
+struct Match {
+    service: String,
+    regex: Regex,
+    soft: bool,
+    product: Option<String>,
+    version: Option<String>,
+    info: Option<String>,
+}
+
+impl Match {
+    fn apply(&self, captures: &Captures) -> ServiceMatch {
+        let expand = |template: &Option<String>| {
+            template.as_deref().and_then(|template| expand(template, captures))
+        };
+        ServiceMatch {
+            service: self.service.clone(),
+            product: expand(&self.product),
+            version: expand(&self.version),
+            info: expand(&self.info),
+        }
+    }
+}
+
+pub struct ServiceProbes {
+    probes: Vec<Probe>,
+    exclude: Vec<PortRange>,
+}
+
+impl ServiceProbes {
+    pub fn bundled() -> Self {
+        let mut probes = Self { probes: Vec::new(), exclude: Vec::new() };
+        probes.parse(BUNDLED, "bundled service probes").expect("invalid bundled service probes");
+        probes
+    }
+
+    // Add the definitions from a probe file, or from every file in a directory.
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        if path.is_dir() {
+            let mut files = Vec::new();
+            for entry in fs::read_dir(path)? {
+                let path = entry?.path();
+                if path.is_file() {
+                    files.push(path);
+                }
+            }
+            files.sort();
+            for file in files {
+                self.load(&file)?;
+            }
+            return Ok(());
+        }
+
+        let source = fs::read(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        self.parse(&String::from_utf8_lossy(&source), &path.display().to_string())
+    }
+
+    fn parse(&mut self, source: &str, name: &str) -> io::Result<()> {
+        let mut current: Option<usize> = None;
+        let mut unsupported = 0;
+
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {}", name, number + 1, message))
+            };
+
+            let (directive, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let rest = rest.trim();
+            if directive == "Exclude" {
+                self.exclude.extend(parse_ports(rest).map_err(error)?);
+                continue;
+            }
+            if directive == "Probe" {
+                current = Some(self.declare(rest).map_err(error)?);
+                continue;
+            }
+
+            let Some(index) = current else {
+                return Err(error(format!("{} before the first Probe", directive)));
+            };
+            let probe = &mut self.probes[index];
+            match directive {
+                "match" | "softmatch" => {
+                    let parsed = parse_match(rest, directive == "softmatch").map_err(error)?;
+                    match parsed {
+                        Some(parsed) => probe.matches.push(parsed),
+                        None => unsupported += 1,
+                    }
+                }
+                "ports" => probe.ports = parse_ports(rest).map_err(error)?,
+                // We can't wrap probes in TLS, so these are tried like any other port
+                "sslports" => probe.ports.extend(parse_ports(rest).map_err(error)?),
+                "rarity" => {
+                    probe.rarity = rest
+                        .parse()
+                        .ok()
+                        .filter(|rarity| (1..=9).contains(rarity))
+                        .ok_or_else(|| error(format!("invalid rarity \"{}\"", rest)))?;
+                }
+                "totalwaitms" => {
+                    let wait = rest.parse().map_err(|_| error(format!("invalid wait \"{}\"", rest)))?;
+                    probe.wait = Some(Duration::from_millis(wait));
+                }
+                "fallback" => {
+                    probe.fallback = rest.split(',').map(|name| name.trim().to_string()).collect();
+                }
+                // Only meaningful to nmap's tcpwrapped detection
+                "tcpwrappedms" => {}
+                _ => return Err(error(format!("unknown directive \"{}\"", directive))),
+            }
+        }
+
+        if unsupported > 0 {
+            eprintln!(
+                "Warning: skipped {} patterns in {} that use unsupported regex features",
+                unsupported, name
+            );
+        }
+        Ok(())
+    }
+
+    // Start a probe, or go back to one declared earlier so its matches can be extended.
+    fn declare(&mut self, declaration: &str) -> Result<usize, String> {
+        let mut parts = declaration.splitn(3, ' ');
+        let protocol = match parts.next() {
+            Some("TCP") => Transport::Tcp,
+            Some("UDP") => Transport::Udp,
+            other => return Err(format!("invalid probe protocol \"{}\"", other.unwrap_or_default())),
+        };
+        let name = parts.next().filter(|name| !name.is_empty()).ok_or("missing probe name")?;
+        let query = parts.next().unwrap_or_default();
+        let (payload, _) = query
+            .strip_prefix('q')
+            .and_then(delimited)
+            .ok_or_else(|| format!("invalid probe string \"{}\"", query))?;
+        let payload = unescape(payload);
+
+        let existing = self
+            .probes
+            .iter()
+            .position(|probe| probe.protocol == protocol && probe.name == name);
+        if let Some(index) = existing {
+            if self.probes[index].payload != payload {
+                return Err(format!("probe {} redeclared with a different payload", name));
+            }
+            return Ok(index);
+        }
+
+        self.probes.push(Probe {
+            protocol,
+            name: name.to_string(),
+            payload,
+            wait: None,
+            rarity: DEFAULT_RARITY,
+            ports: Vec::new(),
+            fallback: Vec::new(),
+            matches: Vec::new(),
+        });
+        Ok(self.probes.len() - 1)
+    }
+
+    pub fn excluded(&self, protocol: Transport, port: u16) -> bool {
+        self.exclude.iter().any(|range| range.contains(protocol, port))
+    }
+
+    // The probes to send to a port, in order: the NULL probe, the ones listing the
+    // port, then any others common enough to be worth a try.
+    pub fn sequence(&self, protocol: Transport, port: u16) -> Vec<&Probe> {
+        let probes = self.probes.iter().filter(|probe| probe.protocol == protocol);
+        let (listed, unlisted): (Vec<&Probe>, Vec<&Probe>) = probes
+            .filter(|probe| probe.name != "NULL")
+            .partition(|probe| probe.ports.iter().any(|range| range.contains(protocol, port)));
+
+        let null = self.find(protocol, "NULL").filter(|_| protocol == Transport::Tcp);
+        null.into_iter()
+            .chain(listed)
+            .chain(unlisted.into_iter().filter(|probe| probe.rarity <= INTENSITY))
+            .collect()
+    }
+
+    // Match a response to `probe` against its own signatures and those of its
+    // fallbacks. Once a softmatch has named the service, only hard matches for
+    // that service count.
+    pub fn identify(&self, probe: &Probe, response: &[u8], soft: Option<&str>) -> Option<Identified> {
+        if response.is_empty() {
+            return None;
+        }
+
+        let mut softmatch = None;
+        for candidate in self.matches(probe) {
+            if soft.is_some_and(|service| service != candidate.service) {
+                continue;
+            }
+            if candidate.soft && (soft.is_some() || softmatch.is_some()) {
+                continue;
+            }
+            let Some(captures) = candidate.regex.captures(response) else {
+                continue;
+            };
+            if !candidate.soft {
+                return Some(Identified::Hard(candidate.apply(&captures)));
+            }
+            softmatch = Some(Identified::Soft(candidate.apply(&captures)));
+        }
+        softmatch
+    }
+
+    // Whether a response to `probe` could ever give a hard match for the service.
+    pub fn can_identify(&self, probe: &Probe, service: &str) -> bool {
+        self.matches(probe).any(|candidate| !candidate.soft && candidate.service == service)
+    }
+
+    fn find(&self, protocol: Transport, name: &str) -> Option<&Probe> {
+        self.probes.iter().find(|probe| probe.protocol == protocol && probe.name == name)
+    }
+
+    // A probe's own matches, then its fallbacks'. TCP probes implicitly fall back
+    // to the NULL probe, since a banner may arrive before the answer.
+    fn matches<'a>(&'a self, probe: &'a Probe) -> impl Iterator<Item = &'a Match> {
+        let fallbacks = probe.fallback.iter().filter_map(|name| self.find(probe.protocol, name));
+        let null = self
+            .find(Transport::Tcp, "NULL")
+            .filter(|_| probe.protocol == Transport::Tcp && probe.name != "NULL");
+        std::iter::once(probe)
+            .chain(fallbacks)
+            .chain(null)
+            .flat_map(|probe| probe.matches.iter())
+    }
+}
+
+// "<service> m<d>pattern<d>[flags] [p/../] [v/../] [i/../] ...". Ok(None) for
+// patterns the regex crate can't compile.
+fn parse_match(line: &str, soft: bool) -> Result<Option<Match>, String> {
+    let (service, rest) = line.split_once(' ').ok_or("missing pattern")?;
+    let (pattern, mut rest) = rest
+        .trim_start()
+        .strip_prefix('m')
+        .and_then(delimited)
+        .ok_or_else(|| format!("invalid pattern in \"{}\"", line))?;
+
+    let flags: String = rest.chars().take_while(|c| c.is_ascii_alphabetic()).collect();
+    rest = &rest[flags.len()..];
+    if let Some(flag) = flags.chars().find(|flag| !matches!(flag, 'i' | 's')) {
+        return Err(format!("unknown pattern flag \"{}\"", flag));
+    }
+
+    let (mut product, mut version, mut info) = (None, None, None);
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        // cpe:/../ and h/o/d fields are accepted but not used
+        let field_length = if rest.starts_with("cpe:") { 4 } else { 1 };
+        let (value, remainder) = delimited(&rest[field_length..])
+            .ok_or_else(|| format!("invalid version field in \"{}\"", line))?;
+        let value = Some(value.to_string());
+        match &rest[..field_length] {
+            "p" => product = value,
+            "v" => version = value,
+            "i" => info = value,
+            "h" | "o" | "d" | "cpe:" => {}
+            field => return Err(format!("unknown version field \"{}\"", field)),
+        }
+        // cpe entries may carry an "a" flag after the closing delimiter
+        rest = remainder.strip_prefix('a').unwrap_or(remainder);
+    }
+
+    let regex = RegexBuilder::new(&translate_pattern(pattern))
+        .case_insensitive(flags.contains('i'))
+        .dot_matches_new_line(flags.contains('s'))
+        // Patterns match raw bytes, so \xff is a single byte rather than a character
+        .unicode(false)
+        .build();
+    let Ok(regex) = regex else {
+        return Ok(None);
+    };
+    Ok(Some(Match { service: service.to_string(), regex, soft, product, version, info }))
+}
+
+// Split "<d>text<d>rest" into the text and what follows the closing delimiter.
+fn delimited(text: &str) -> Option<(&str, &str)> {
+    let delimiter = text.chars().next()?;
+    let body = &text[delimiter.len_utf8()..];
+    let end = body.find(delimiter)?;
+    Some((&body[..end], &body[end + delimiter.len_utf8()..]))
+}
+
+// PCRE octal escapes (\0 mostly) aren't understood by the regex crate.
+fn translate_pattern(pattern: &str) -> String {
+    let mut translated = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            translated.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('0') => {
+                let mut value = 0u32;
+                for _ in 0..2 {
+                    match chars.peek().and_then(|digit| digit.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                translated.push_str(&format!("\\x{:02x}", value));
+            }
+            Some(escaped) => {
+                translated.push('\\');
+                translated.push(escaped);
+            }
+            None => translated.push('\\'),
+        }
+    }
+    translated
+}
+
+// Probe payloads use C-style escapes.
+fn unescape(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buffer = [0u8; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('0') => bytes.push(0),
+            Some('a') => bytes.push(0x07),
+            Some('b') => bytes.push(0x08),
+            Some('f') => bytes.push(0x0c),
+            Some('n') => bytes.push(b'\n'),
+            Some('r') => bytes.push(b'\r'),
+            Some('t') => bytes.push(b'\t'),
+            Some('v') => bytes.push(0x0b),
+            Some('x') => {
+                let hex: String = chars.clone().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(value) if hex.len() == 2 => {
+                        bytes.push(value);
+                        chars.nth(1);
+                    }
+                    _ => bytes.push(b'x'),
+                }
+            }
+            Some(other) => {
+                let mut buffer = [0u8; 4];
+                bytes.extend_from_slice(other.encode_utf8(&mut buffer).as_bytes());
+            }
+            None => bytes.push(b'\\'),
+        }
+    }
+    bytes
+}
+
+// "21,80-85,T:9100,U:53". A T: or U: prefix applies until the next one.
+fn parse_ports(list: &str) -> Result<Vec<PortRange>, String> {
+    let mut protocol = None;
+    let mut ranges = Vec::new();
+    for entry in list.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let entry = if let Some(entry) = entry.strip_prefix("T:") {
+            protocol = Some(Transport::Tcp);
+            entry
+        } else if let Some(entry) = entry.strip_prefix("U:") {
+            protocol = Some(Transport::Udp);
+            entry
+        } else {
+            entry
+        };
+
+        let invalid = || format!("invalid port \"{}\"", entry);
+        let (start, end) = entry.split_once('-').unwrap_or((entry, entry));
+        let start: u16 = start.parse().map_err(|_| invalid())?;
+        let end: u16 = end.parse().map_err(|_| invalid())?;
+        if start > end {
+            return Err(invalid());
+        }
+        ranges.push(PortRange { protocol, start, end });
+    }
+    Ok(ranges)
+}
+
+// Fill in a version template: $1-$9 insert a capture group, and the helpers
+// $P(n), $SUBST(n,"from","to") and $I(n,">") work as they do in nmap.
+fn expand(template: &str, captures: &Captures) -> Option<String> {
+    let group = |number: &str| -> &[u8] {
+        number
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|number| captures.get(number))
+            .map_or(&[], |capture| capture.as_bytes())
+    };
+
+    let mut expanded = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        if let Some(digit) = rest.chars().next().filter(char::is_ascii_digit) {
+            let text = String::from_utf8_lossy(group(&digit.to_string())).into_owned();
+            expanded.extend(text.chars().filter(|c| !c.is_control()));
+            rest = &rest[1..];
+            continue;
+        }
+
+        let Some((function, arguments, remainder)) = function_call(rest) else {
+            expanded.push('$');
+            continue;
+        };
+        rest = remainder;
+        let bytes = arguments.first().map_or(&[][..], |number| group(number));
+        match function {
+            "P" => expanded.extend(
+                bytes.iter().filter(|byte| byte.is_ascii_graphic() || **byte == b' ').map(|byte| *byte as char),
+            ),
+            "SUBST" if arguments.len() == 3 => {
+                let text = String::from_utf8_lossy(bytes);
+                expanded.push_str(&text.replace(arguments[1], arguments[2]));
+            }
+            "I" if bytes.len() <= 8 => {
+                let mut value = 0u64;
+                let little_endian = arguments.get(1) == Some(&"<");
+                let mut push = |byte: &u8| value = (value << 8) | *byte as u64;
+                if little_endian {
+                    bytes.iter().rev().for_each(&mut push);
+                } else {
+                    bytes.iter().for_each(&mut push);
+                }
+                expanded.push_str(&value.to_string());
+            }
+            _ => {}
+        }
+    }
+    expanded.push_str(rest);
+
+    let expanded = expanded.trim();
+    (!expanded.is_empty()).then(|| expanded.to_string())
+}
+
+// "NAME(arg,\"quoted\",...)rest" into the name, unquoted arguments and the rest.
+fn function_call(text: &str) -> Option<(&str, Vec<&str>, &str)> {
+    let open = text.find('(')?;
+    let name = &text[..open];
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_uppercase()) {
+        return None;
+    }
+
+    let mut arguments = Vec::new();
+    let mut quoted = false;
+    let mut start = open + 1;
+    for (index, c) in text.char_indices().skip(open + 1) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' | ')' if !quoted => {
+                arguments.push(text[start..index].trim().trim_matches('"'));
+                start = index + 1;
+                if c == ')' {
+                    return Some((name, arguments, &text[index + 1..]));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROBES: &str = r#"
+# Directives as nmap's own file uses them
+Exclude T:9100-9107
+Probe TCP NULL q||
+totalwaitms 6000
+tcpwrappedms 3000
+match ssh m|^SSH-([\d.]+)-OpenSSH_([\w._-]+)\r?\n| p/OpenSSH/ v/$SUBST(2,"_"," ")/ i/protocol $1/ cpe:/a:openbsd:openssh:$2/a
+match ftp m=^220 Welcome to (\S+) \|FTP\|= p|$1| i/$P(1)/
+softmatch smtp m|^220[ -][^\r\n]*\bE?SMTP\b|i
+
+Probe TCP GetRequest q|GET / HTTP/1.0\r\n\r\n|
+rarity 8
+ports 80,8000-8010
+sslports 443
+fallback NULL
+match http m|^HTTP/1\.[01] \d\d\d.*?\r\nServer: nginx/([\d.]+)|s p/nginx/ v/$1/
+match smtp m|^220 [\w.]+ ESMTP Postfix| p/Postfix smtpd/
+
+Probe UDP DNSStatusRequest q|\0\0\x10\0\0\0\0\0\0\0\0\0|
+rarity 1
+ports 53
+match dns m|^\0\0\x90\x04\0\0\0\0\0\0\0\0| p/ISC BIND/
+match quake m|^\xff\xff\xff\xff(....)(..)|s v/$I(1,">").$I(2,"<")/
+"#;
+
+    fn probes() -> ServiceProbes {
+        let mut probes = ServiceProbes { probes: Vec::new(), exclude: Vec::new() };
+        probes.parse(PROBES, "test").unwrap();
+        probes
+    }
+
+    fn names(probes: Vec<&Probe>) -> Vec<&str> {
+        probes.iter().map(|probe| probe.name.as_str()).collect()
+    }
+
+    // Whether the match was hard, and what it found
+    fn identify(
+        probes: &ServiceProbes,
+        protocol: Transport,
+        name: &str,
+        response: &[u8],
+    ) -> Option<(bool, ServiceMatch)> {
+        let probe = probes.find(protocol, name).unwrap();
+        match probes.identify(probe, response, None)? {
+            Identified::Hard(found) => Some((true, found)),
+            Identified::Soft(found) => Some((false, found)),
+        }
+    }
+
+    fn service(service: &str, product: Option<&str>, version: Option<&str>, info: Option<&str>) -> ServiceMatch {
+        ServiceMatch {
+            service: service.to_string(),
+            product: product.map(String::from),
+            version: version.map(String::from),
+            info: info.map(String::from),
+        }
+    }
+
+    #[test]
+    fn probes_carry_their_directives() {
+        let probes = probes();
+        let get = probes.find(Transport::Tcp, "GetRequest").unwrap();
+        assert_eq!(get.payload, b"GET / HTTP/1.0\r\n\r\n");
+        assert_eq!(get.rarity, 8);
+        assert_eq!(get.fallback, ["NULL"]);
+        assert_eq!(probes.find(Transport::Tcp, "NULL").unwrap().wait, Some(Duration::from_millis(6000)));
+        assert_eq!(probes.find(Transport::Udp, "DNSStatusRequest").unwrap().payload, b"\0\0\x10\0\0\0\0\0\0\0\0\0");
+
+        assert!(probes.excluded(Transport::Tcp, 9100));
+        assert!(probes.excluded(Transport::Tcp, 9107));
+        assert!(!probes.excluded(Transport::Tcp, 9108));
+        assert!(!probes.excluded(Transport::Udp, 9100));
+    }
+
+    #[test]
+    fn sequence_follows_ports_and_rarity() {
+        let probes = probes();
+        assert_eq!(names(probes.sequence(Transport::Tcp, 8005)), ["NULL", "GetRequest"]);
+        // sslports count as listed ports
+        assert_eq!(names(probes.sequence(Transport::Tcp, 443)), ["NULL", "GetRequest"]);
+        // Too rare to try on ports it doesn't list
+        assert_eq!(names(probes.sequence(Transport::Tcp, 22)), ["NULL"]);
+        // No NULL probe for UDP, and common probes are tried everywhere
+        assert_eq!(names(probes.sequence(Transport::Udp, 161)), ["DNSStatusRequest"]);
+    }
+
+    #[test]
+    fn version_templates_are_filled_in() {
+        let probes = probes();
+        let found = identify(&probes, Transport::Tcp, "NULL", b"SSH-2.0-OpenSSH_8.9p1_Ubuntu-3\r\n");
+        let expected = service("ssh", Some("OpenSSH"), Some("8.9p1 Ubuntu-3"), Some("protocol 2.0"));
+        assert_eq!(found, Some((true, expected)));
+
+        let found = identify(&probes, Transport::Udp, "DNSStatusRequest", b"\xff\xff\xff\xff\x00\x01\x00\x02\x03\x00");
+        assert_eq!(found, Some((true, service("quake", None, Some("65538.3"), None))));
+    }
+
+    #[test]
+    fn other_delimiters_allow_the_usual_one_in_patterns() {
+        let probes = probes();
+        // $P keeps printable ASCII only, where $1 keeps the byte as best it can
+        let found = identify(&probes, Transport::Tcp, "NULL", b"220 Welcome to ftp\xe9.test |FTP|\r\n");
+        let expected = service("ftp", Some("ftp\u{fffd}.test"), None, Some("ftp.test"));
+        assert_eq!(found, Some((true, expected)));
+    }
+
+    #[test]
+    fn octal_escapes_and_flags_match_raw_bytes() {
+        let probes = probes();
+        let found = identify(&probes, Transport::Udp, "DNSStatusRequest", b"\0\0\x90\x04\0\0\0\0\0\0\0\0");
+        assert_eq!(found, Some((true, service("dns", Some("ISC BIND"), None, None))));
+
+        // The s flag lets .*? run across lines
+        let response = b"HTTP/1.1 200 OK\r\nDate: today\r\nServer: nginx/1.24.0\r\n\r\n";
+        let found = identify(&probes, Transport::Tcp, "GetRequest", response);
+        assert_eq!(found, Some((true, service("http", Some("nginx"), Some("1.24.0"), None))));
+    }
+
+    #[test]
+    fn softmatches_give_way_to_hard_matches_for_the_service() {
+        let probes = probes();
+        // The i flag: "esmtp" in any case
+        let found = identify(&probes, Transport::Tcp, "NULL", b"220 mail.test esmtp ready\r\n");
+        assert_eq!(found, Some((false, service("smtp", None, None, None))));
+
+        let get = probes.find(Transport::Tcp, "GetRequest").unwrap();
+        assert!(probes.can_identify(get, "smtp"));
+        let found = probes.identify(get, b"220 mail.test ESMTP Postfix\r\n", Some("smtp"));
+        assert!(matches!(found, Some(Identified::Hard(found)) if found.product.as_deref() == Some("Postfix smtpd")));
+        // Hard matches for other services no longer count
+        assert!(probes.identify(get, b"HTTP/1.0 200 OK\r\nServer: nginx/1.0\r\n", Some("smtp")).is_none());
+    }
+
+    #[test]
+    fn redeclared_probes_gain_matches() {
+        let mut probes = probes();
+        let extra = "Probe TCP GetRequest q|GET / HTTP/1.0\\r\\n\\r\\n|\nmatch http m|^HTTP/1\\.0 200 OK\\r\\nServer: Caddy| p/Caddy/\n";
+        probes.parse(extra, "extra").unwrap();
+        assert_eq!(probes.probes.len(), 3);
+        let found = identify(&probes, Transport::Tcp, "GetRequest", b"HTTP/1.0 200 OK\r\nServer: Caddy\r\n");
+        assert_eq!(found, Some((true, service("http", Some("Caddy"), None, None))));
+
+        let changed = "Probe TCP GetRequest q|HEAD / HTTP/1.0\\r\\n\\r\\n|\n";
+        assert!(probes.parse(changed, "changed").is_err());
+    }
+
+    #[test]
+    fn unsupported_patterns_are_skipped_and_bad_lines_rejected() {
+        let mut probes = probes();
+        probes.parse("Probe TCP NULL q||\nmatch http m|^(?!SSH)HTTP|\n", "lookaround").unwrap();
+        assert_eq!(probes.find(Transport::Tcp, "NULL").unwrap().matches.len(), 3);
+
+        for source in [
+            "match http m|^HTTP|\n",
+            "Probe TCP NULL q||\nrarity 10\n",
+            "Probe TCP NULL q||\nmatch http m|^HTTP|x\n",
+            "Probe TCP NULL q||\nmatch http m|^HTTP| z/field/\n",
+            "Probe SCTP NULL q||\n",
+            "Probe TCP NULL q||\nbogus directive\n",
+        ] {
+            let error = probes.parse(source, "bad").unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crossbeam_channel::Sender as CrossbeamSender;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::result::ScanEvent;
+
+// Stopping a scan part way, and telling whoever started it how far along it is.
+
+// Shortest gap between progress events, so a fast scan doesn't flood the channel
+const REPORT_INTERVAL: Duration = Duration::from_millis(200);
+
+// Shared by the caller and every worker of a scan. Workers check it between
+// probes and wind down once it's cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ScanPhase {
+    Discovery,
+    PortScan,
+    OsDetection,
+    ServiceDetection,
+}
+
+impl fmt::Display for ScanPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ScanPhase::Discovery => "Host discovery",
+            ScanPhase::PortScan => "Port scan",
+            ScanPhase::OsDetection => "OS detection",
+            ScanPhase::ServiceDetection => "Service detection",
+        })
+    }
+}
+
+// How far the current phase has got. Work is counted in hosts for discovery and
+// OS detection, in ports of every host for the port scan and in open ports for
+// service detection.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub phase: ScanPhase,
+    pub done: usize,
+    pub total: usize,
+    // Probes put on the wire and the answers they got, this phase
+    pub sent: u64,
+    pub received: u64,
+    pub elapsed: Duration,
+    // Going by the rate so far; unknown until some work is done
+    pub eta: Option<Duration>,
+}
+
+impl Progress {
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        (self.done as f32 / self.total as f32).min(1.0)
+    }
+}
+
+// e.g. "Port scan: 1200/4096, 1350 sent, 230 received, 12s left"
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}/{}", self.phase, self.done, self.total)?;
+        if self.sent > 0 {
+            write!(f, ", {} sent", self.sent)?;
+        }
+        if self.received > 0 {
+            write!(f, ", {} received", self.received)?;
+        }
+        if self.done >= self.total {
+            write!(f, ", took {}", format_duration(self.elapsed))
+        } else if let Some(eta) = self.eta {
+            write!(f, ", {} left", format_duration(eta))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..60 => format!("{}s", seconds),
+        60..3600 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+// Counts one phase's work from whichever threads are doing it and streams
+// Progress events, at most one per REPORT_INTERVAL.
+pub(super) struct ProgressMeter {
+    phase: ScanPhase,
+    total: usize,
+    done: AtomicUsize,
+    sent: AtomicU64,
+    received: AtomicU64,
+    started: Instant,
+    last_report: Mutex<Instant>,
+    tx: CrossbeamSender<ScanEvent>,
+}
+
+impl ProgressMeter {
+    pub fn new(phase: ScanPhase, total: usize, tx: &CrossbeamSender<ScanEvent>) -> Self {
+        let now = Instant::now();
+        let meter = Self {
+            phase,
+            total,
+            done: AtomicUsize::new(0),
+            sent: AtomicU64::new(0),
+            received: AtomicU64::new(0),
+            started: now,
+            last_report: Mutex::new(now),
+            tx: tx.clone(),
+        };
+        meter.send();
+        meter
+    }
+
+    pub fn sent(&self, count: u64) {
+        self.sent.fetch_add(count, Ordering::Relaxed);
+        self.tick();
+    }
+
+    pub fn received(&self, count: u64) {
+        self.received.fetch_add(count, Ordering::Relaxed);
+        self.tick();
+    }
+
+    pub fn done(&self, count: usize) {
+        self.done.fetch_add(count, Ordering::Relaxed);
+        self.tick();
+    }
+
+    // Report if it's been a while, even if nothing was counted.
+    pub fn tick(&self) {
+        // Whoever holds the lock is about to report anyway
+        let Ok(mut last_report) = self.last_report.try_lock() else {
+            return;
+        };
+        if last_report.elapsed() < REPORT_INTERVAL {
+            return;
+        }
+        *last_report = Instant::now();
+        drop(last_report);
+        self.send();
+    }
+
+    // The phase is over, whether or not every unit of work was counted.
+    pub fn finish(&self) {
+        self.done.store(self.total, Ordering::Relaxed);
+        self.send();
+    }
+
+    fn send(&self) {
+        let done = self.done.load(Ordering::Relaxed).min(self.total);
+        let elapsed = self.started.elapsed();
+        let eta = (done > 0).then(|| elapsed.mul_f64((self.total - done) as f64 / done as f64));
+        let _ = self.tx.send(ScanEvent::Progress(Progress {
+            phase: self.phase,
+            done,
+            total: self.total,
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            elapsed,
+            eta,
+        }));
+    }
+}
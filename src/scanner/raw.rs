@@ -0,0 +1,327 @@
+use pnet::packet::icmp::{IcmpPacket, IcmpTypes};
+use pnet::packet::icmpv6::{Icmpv6Packet, Icmpv6Types};
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
+use pnet::packet::ipv4::{self, Ipv4Flags, Ipv4Packet, MutableIpv4Packet};
+use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::util;
+use pnet::packet::Packet;
+use pnet_transport::{
+    self as transport, TransportChannelType, TransportProtocol, TransportReceiver, TransportSender,
+};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::ratelimit;
+
+// Raw sockets for the scan engines that craft their own packets. The kernel still
+// routes and frames what we send, so this works on loopback and any link type.
+// IPv4 packets are sent with our own IP header; IPv6 raw sockets don't allow that,
+// so there the kernel builds the header and we only supply the transport packet.
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+const RECEIVE_BUFFER: usize = 4 << 20;
+
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+
+// Source and destination of our probes, always of the same address family.
+#[derive(Clone, Copy, Debug)]
+pub enum Route {
+    V4(Ipv4Addr, Ipv4Addr),
+    V6(Ipv6Addr, Ipv6Addr),
+}
+
+impl Route {
+    // Route to the target using the source address the kernel would pick for it.
+    pub fn to(target: IpAddr) -> io::Result<Self> {
+        // Connecting a UDP socket only performs the route lookup, nothing is sent
+        let socket = match target {
+            IpAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+            IpAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+        };
+        socket.connect((target, 9))?;
+        match (socket.local_addr()?.ip(), target) {
+            (IpAddr::V4(source), IpAddr::V4(target)) => Ok(Route::V4(source, target)),
+            (IpAddr::V6(source), IpAddr::V6(target)) => Ok(Route::V6(source, target)),
+            _ => Err(io::Error::other("No route to target")),
+        }
+    }
+
+    pub fn destination(&self) -> IpAddr {
+        match self {
+            Route::V4(_, destination) => IpAddr::V4(*destination),
+            Route::V6(_, destination) => IpAddr::V6(*destination),
+        }
+    }
+
+    // Transport checksum including the pseudo-header. `skipword` is the index of
+    // the 16-bit word holding the checksum field itself.
+    pub fn checksum(&self, protocol: IpNextHeaderProtocol, packet: &[u8], skipword: usize) -> u16 {
+        match self {
+            Route::V4(source, destination) => {
+                util::ipv4_checksum(packet, skipword, &[], source, destination, protocol)
+            }
+            Route::V6(source, destination) => {
+                util::ipv6_checksum(packet, skipword, &[], source, destination, protocol)
+            }
+        }
+    }
+}
+
+// A packet read from a raw socket, reduced to what the scan engines look at.
+pub struct Reply<'a> {
+    pub source: IpAddr,
+    // Header fields aren't available for IPv6, where the kernel strips the header
+    // before we see it
+    pub hop_limit: Option<u8>,
+    pub identification: Option<u16>,
+    pub dont_fragment: Option<bool>,
+    pub payload: &'a [u8],
+}
+
+// An ICMP or ICMPv6 destination unreachable and the start of the datagram it quotes.
+pub struct Unreachable<'a> {
+    // Port unreachable, as opposed to the codes sent by filtering devices
+    pub port_unreachable: bool,
+    pub destination: IpAddr,
+    pub protocol: IpNextHeaderProtocol,
+    // Only the first 8 bytes of the quoted transport header are guaranteed
+    pub header: &'a [u8],
+}
+
+impl Unreachable<'_> {
+    pub fn ports(&self) -> Option<(u16, u16)> {
+        let header = self.header.get(..4)?;
+        Some((
+            u16::from_be_bytes([header[0], header[1]]),
+            u16::from_be_bytes([header[2], header[3]]),
+        ))
+    }
+}
+
+pub struct RawSender {
+    tx: TransportSender,
+    protocol: IpNextHeaderProtocol,
+}
+
+impl RawSender {
+    pub fn send(&mut self, route: &Route, packet: &[u8]) -> io::Result<()> {
+        match route {
+            Route::V4(source, destination) => {
+                let packet = ipv4_packet(*source, *destination, self.protocol, packet);
+                ratelimit::acquire(packet.len());
+                self.tx.send_to(Ipv4Packet::new(&packet).unwrap(), route.destination())?;
+            }
+            Route::V6(..) => {
+                ratelimit::acquire(IPV6_HEADER_LEN + packet.len());
+                self.tx.send_to(Bytes(packet), route.destination())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct RawReceiver {
+    rx: TransportReceiver,
+    ipv6: bool,
+}
+
+// Open a raw socket of the route's address family that sends packets of the given
+// protocol and receives every inbound packet carrying it. For ICMP, pass
+// `IpNextHeaderProtocols::Icmp`; IPv6 routes get an ICMPv6 socket instead.
+pub fn open(route: &Route, protocol: IpNextHeaderProtocol) -> io::Result<(RawSender, RawReceiver)> {
+    let (channel, ipv6) = match route {
+        Route::V4(..) => (TransportChannelType::Layer3(protocol), false),
+        Route::V6(..) => {
+            let protocol = match protocol {
+                IpNextHeaderProtocols::Icmp => IpNextHeaderProtocols::Icmpv6,
+                protocol => protocol,
+            };
+            (TransportChannelType::Layer4(TransportProtocol::Ipv6(protocol)), true)
+        }
+    };
+    let (tx, rx) = transport::transport_channel(65535, channel)?;
+
+    // A raw socket sees every packet of its protocol, not just our replies, so
+    // give it room to ride out bursts. The kernel caps this at rmem_max.
+    let size = RECEIVE_BUFFER as libc::c_int;
+    unsafe {
+        libc::setsockopt(
+            rx.socket.fd,
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &size as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+    Ok((RawSender { tx, protocol }, RawReceiver { rx, ipv6 }))
+}
+
+// Wrap a transport payload in a minimal IPv4 header.
+fn ipv4_packet(
+    source: Ipv4Addr,
+    destination: Ipv4Addr,
+    protocol: IpNextHeaderProtocol,
+    payload: &[u8],
+) -> Vec<u8> {
+    let mut buffer = vec![0u8; IPV4_HEADER_LEN + payload.len()];
+    let mut packet = MutableIpv4Packet::new(&mut buffer).unwrap();
+    packet.set_version(4);
+    packet.set_header_length(5);
+    packet.set_total_length((IPV4_HEADER_LEN + payload.len()) as u16);
+    packet.set_identification(rand::random());
+    packet.set_ttl(64);
+    packet.set_next_level_protocol(protocol);
+    packet.set_source(source);
+    packet.set_destination(destination);
+    packet.set_payload(payload);
+    let checksum = ipv4::checksum(&packet.to_immutable());
+    packet.set_checksum(checksum);
+    buffer
+}
+
+// Parse an ICMP or ICMPv6 destination unreachable, returning what it says about
+// the quoted datagram. Codes that say nothing about filtering are ignored.
+pub fn unreachable<'a>(reply: &Reply<'a>) -> Option<Unreachable<'a>> {
+    // The quoted datagram follows 4 unused bytes
+    let quoted = reply.payload.get(8..)?;
+
+    match reply.source {
+        IpAddr::V4(_) => {
+            let icmp = IcmpPacket::new(reply.payload)?;
+            let code = icmp.get_icmp_code().0;
+            if icmp.get_icmp_type() != IcmpTypes::DestinationUnreachable
+                || !matches!(code, 0 | 1 | 2 | 3 | 9 | 10 | 13)
+            {
+                return None;
+            }
+
+            let quoted_ip = Ipv4Packet::new(quoted)?;
+            Some(Unreachable {
+                port_unreachable: code == 3,
+                destination: IpAddr::V4(quoted_ip.get_destination()),
+                protocol: quoted_ip.get_next_level_protocol(),
+                header: quoted.get(quoted_ip.get_header_length() as usize * 4..)?,
+            })
+        }
+        IpAddr::V6(_) => {
+            let icmp = Icmpv6Packet::new(reply.payload)?;
+            let code = icmp.get_icmpv6_code().0;
+            if icmp.get_icmpv6_type() != Icmpv6Types::DestinationUnreachable
+                || !matches!(code, 0 | 1 | 3 | 4 | 5 | 6)
+            {
+                return None;
+            }
+
+            // Probes are sent without extension headers, so the transport header
+            // follows the fixed IPv6 header directly
+            let quoted_ip = Ipv6Packet::new(quoted)?;
+            Some(Unreachable {
+                port_unreachable: code == 4,
+                destination: IpAddr::V6(quoted_ip.get_destination()),
+                protocol: quoted_ip.get_next_header(),
+                header: quoted.get(IPV6_HEADER_LEN..)?,
+            })
+        }
+    }
+}
+
+// Hand every packet received on the socket to `handle` until `done` is set.
+pub fn listen<F>(receiver: RawReceiver, done: Arc<AtomicBool>, mut handle: F) -> JoinHandle<()>
+where
+    F: FnMut(&Reply) + Send + 'static,
+{
+    listen_all(vec![receiver], done, move |_, reply| handle(reply))
+}
+
+// Hand every packet received on any of the sockets to `handle`, along with the
+// index of the socket it arrived on, until `done` is set.
+pub fn listen_all<F>(
+    mut receivers: Vec<RawReceiver>,
+    done: Arc<AtomicBool>,
+    mut handle: F,
+) -> JoinHandle<()>
+where
+    F: FnMut(usize, &Reply) + Send + 'static,
+{
+    thread::spawn(move || {
+        while !done.load(Ordering::Relaxed) {
+            let mut fds: Vec<libc::pollfd> = receivers
+                .iter()
+                .map(|receiver| libc::pollfd {
+                    fd: receiver.rx.socket.fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+            let timeout = POLL_INTERVAL.as_millis() as libc::c_int;
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+            if ready < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                eprintln!("Error: {}", e);
+                break;
+            }
+
+            for (index, fd) in fds.iter().enumerate() {
+                if fd.revents & libc::POLLIN == 0 {
+                    continue;
+                }
+                if let Err(e) = receivers[index].receive(|reply| handle(index, reply)) {
+                    eprintln!("Error: {}", e);
+                    return;
+                }
+            }
+        }
+    })
+}
+
+impl RawReceiver {
+    // Read one packet, which poll() has said is waiting.
+    fn receive<F: FnMut(&Reply)>(&mut self, mut handle: F) -> io::Result<()> {
+        if self.ipv6 {
+            // IPv6 raw sockets deliver just the transport packet; the iterator
+            // type only decides how it's wrapped, so any will do
+            let mut packets = transport::icmpv6_packet_iter(&mut self.rx);
+            let (packet, source) = packets.next()?;
+            handle(&Reply {
+                source,
+                hop_limit: None,
+                identification: None,
+                dont_fragment: None,
+                payload: packet.packet(),
+            });
+        } else {
+            let mut packets = transport::ipv4_packet_iter(&mut self.rx);
+            let (packet, _) = packets.next()?;
+            handle(&Reply {
+                source: IpAddr::V4(packet.get_source()),
+                hop_limit: Some(packet.get_ttl()),
+                identification: Some(packet.get_identification()),
+                dont_fragment: Some(packet.get_flags() & Ipv4Flags::DontFragment != 0),
+                payload: packet.payload(),
+            });
+        }
+        Ok(())
+    }
+}
+
+// Already-built bytes, for sending through pnet's packet-typed sender.
+struct Bytes<'a>(&'a [u8]);
+
+impl Packet for Bytes<'_> {
+    fn packet(&self) -> &[u8] {
+        self.0
+    }
+
+    fn payload(&self) -> &[u8] {
+        self.0
+    }
+}
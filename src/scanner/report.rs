@@ -0,0 +1,569 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::ports::PortSpec;
+use super::result::{HostResult, HostStatus, HostnameSource, PortResult, PortState, Reason, ScanEvent};
+use super::timing::TimingTemplate;
+use super::{ScanConfig, ScanType};
+
+// A whole scan's results, and writing them out for other tools: JSON of the
+// entire report, CSV of the port table, XML following nmap's -oX schema so that
+// anything reading nmap's output reads ours too, and nmap's grepable format of
+// one line per host.
+
+// A host's most common state other than open is summed up rather than listed
+// port by port when more ports than this share it, as nmap does
+const IGNORED_THRESHOLD: usize = 25;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Xml,
+    Grepable,
+}
+
+impl ExportFormat {
+    pub const ALL: [ExportFormat; 4] = [
+        ExportFormat::Json,
+        ExportFormat::Csv,
+        ExportFormat::Xml,
+        ExportFormat::Grepable,
+    ];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Json => "json",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Xml => "xml",
+            ExportFormat::Grepable => "gnmap",
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ExportFormat::Json => "JSON",
+            ExportFormat::Csv => "CSV",
+            ExportFormat::Xml => "nmap XML",
+            ExportFormat::Grepable => "Grepable",
+        })
+    }
+}
+
+// What a scan was asked to do and everything it found, built up from its events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub config: ScanConfig,
+    pub started: SystemTime,
+    // None until the scan has run to the end
+    pub finished: Option<SystemTime>,
+    // In the order they were reported
+    pub hosts: Vec<HostResult>,
+}
+
+impl ScanReport {
+    pub fn new(config: &ScanConfig) -> Self {
+        Self {
+            config: config.clone(),
+            started: SystemTime::now(),
+            finished: None,
+            hosts: Vec::new(),
+        }
+    }
+
+    // Hosts reported again, and ports with their services identified, replace
+    // what was there.
+    pub fn record(&mut self, event: &ScanEvent) {
+        match event {
+            ScanEvent::Host(status) => match self.host_mut(status.address) {
+                Some(host) => host.status = status.clone(),
+                None => self.hosts.push(HostResult::new(status.clone())),
+            },
+            ScanEvent::Port(result) => self.host_for(result).ports.push(result.clone()),
+            ScanEvent::Service(update) => {
+                let host = self.host_for(update);
+                let existing = host
+                    .ports
+                    .iter_mut()
+                    .find(|result| result.port == update.port && result.protocol == update.protocol);
+                match existing {
+                    Some(existing) => *existing = update.clone(),
+                    None => host.ports.push(update.clone()),
+                }
+            }
+            ScanEvent::Progress(_) => {}
+        }
+    }
+
+    pub fn host(&self, address: IpAddr) -> Option<&HostResult> {
+        self.hosts.iter().find(|host| host.status.address == address)
+    }
+
+    pub fn hosts_up(&self) -> usize {
+        self.hosts.iter().filter(|host| host.status.up).count()
+    }
+
+    fn host_mut(&mut self, address: IpAddr) -> Option<&mut HostResult> {
+        self.hosts.iter_mut().find(|host| host.status.address == address)
+    }
+
+    // The host a port belongs to. Scans report each host before its ports, so
+    // one is only made up here for results from elsewhere.
+    fn host_for(&mut self, result: &PortResult) -> &mut HostResult {
+        if self.host_mut(result.host).is_none() {
+            self.hosts.push(HostResult::new(HostStatus {
+                address: result.host,
+                hostname: result.hostname.clone(),
+                hostname_source: HostnameSource::User,
+                up: true,
+                reason: Reason::UserSet,
+                mac: None,
+                vendor: None,
+                latency: None,
+                os: None,
+            }));
+        }
+        self.host_mut(result.host).unwrap()
+    }
+
+    pub fn export<W: Write>(&self, format: ExportFormat, mut out: W) -> io::Result<()> {
+        match format {
+            ExportFormat::Json => {
+                serde_json::to_writer_pretty(&mut out, self)?;
+                writeln!(out)
+            }
+            ExportFormat::Csv => self.write_csv(&mut out),
+            ExportFormat::Xml => self.write_xml(&mut out),
+            ExportFormat::Grepable => self.write_grepable(&mut out),
+        }
+    }
+
+    pub fn save(&self, path: &Path, format: ExportFormat) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.export(format, &mut out)?;
+        out.flush()
+    }
+
+    // Read back a report saved as JSON.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "host,hostname,port,protocol,state,service,product,version,info,reason,ttl,rtt_ms")?;
+        let mut ports: Vec<&PortResult> = self.hosts.iter().flat_map(|host| &host.ports).collect();
+        ports.sort_by_key(|result| (result.host, result.protocol, result.port));
+        for result in ports {
+            let fields = [
+                result.host.to_string(),
+                result.hostname.clone().unwrap_or_default(),
+                result.port.to_string(),
+                result.protocol.to_string(),
+                result.state.to_string(),
+                result.service.clone(),
+                result.product.clone().unwrap_or_default(),
+                result.version.clone().unwrap_or_default(),
+                result.info.clone().unwrap_or_default(),
+                result.reason.to_string(),
+                result.ttl.map(|ttl| ttl.to_string()).unwrap_or_default(),
+                result
+                    .rtt
+                    .map(|rtt| format!("{:.3}", rtt.as_secs_f64() * 1000.0))
+                    .unwrap_or_default(),
+            ];
+            let fields: Vec<Cow<str>> = fields.iter().map(|field| csv_field(field)).collect();
+            writeln!(out, "{}", fields.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn write_xml<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let protocol = self.config.scan_type.transport();
+        let ports = PortSpec::parse(&self.config.ports)
+            .ok()
+            .and_then(|spec| spec.ports(protocol).ok().map(<[u16]>::to_vec))
+            .unwrap_or_default();
+
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(out, "<!DOCTYPE nmaprun>")?;
+        // The DTD only allows "nmap" as the scanner
+        writeln!(
+            out,
+            r#"<nmaprun scanner="nmap" args="{}" start="{}" startstr="{}" version="{}" xmloutputversion="1.05">"#,
+            escape(&command_line(&self.config)),
+            unix_seconds(self.started),
+            escape(&time_string(self.started)),
+            env!("CARGO_PKG_VERSION")
+        )?;
+        writeln!(
+            out,
+            r#"<scaninfo type="{}" protocol="{}" numservices="{}" services="{}"/>"#,
+            nmap_scan_type(self.config.scan_type).1,
+            protocol,
+            ports.len(),
+            port_ranges(&ports)
+        )?;
+        writeln!(out, r#"<verbose level="0"/>"#)?;
+        writeln!(out, r#"<debugging level="0"/>"#)?;
+
+        for (host, ports) in self.up_hosts() {
+            writeln!(out, "<host>")?;
+            writeln!(
+                out,
+                r#"<status state="up" reason="{}" reason_ttl="0"/>"#,
+                nmap_reason(host.reason)
+            )?;
+            let addrtype = if host.address.is_ipv4() { "ipv4" } else { "ipv6" };
+            writeln!(out, r#"<address addr="{}" addrtype="{}"/>"#, host.address, addrtype)?;
+            if let Some(mac) = host.mac {
+                let vendor = host
+                    .vendor
+                    .as_ref()
+                    .map(|vendor| format!(r#" vendor="{}""#, escape(vendor)))
+                    .unwrap_or_default();
+                writeln!(
+                    out,
+                    r#"<address addr="{}" addrtype="mac"{}/>"#,
+                    mac.to_string().to_uppercase(),
+                    vendor
+                )?;
+            }
+            match &host.hostname {
+                Some(hostname) => {
+                    writeln!(out, "<hostnames>")?;
+                    let kind = match host.hostname_source {
+                        HostnameSource::User => "user",
+                        HostnameSource::Ptr => "PTR",
+                    };
+                    writeln!(out, r#"<hostname name="{}" type="{}"/>"#, escape(hostname), kind)?;
+                    writeln!(out, "</hostnames>")?;
+                }
+                None => writeln!(out, "<hostnames/>")?,
+            }
+
+            writeln!(out, "<ports>")?;
+            let ignored = ignored_state(&ports);
+            if let Some((state, count)) = ignored {
+                writeln!(out, r#"<extraports state="{}" count="{}"/>"#, state, count)?;
+            }
+            for result in ports.iter().filter(|result| Some(result.state) != ignored.map(|(state, _)| state)) {
+                write!(
+                    out,
+                    r#"<port protocol="{}" portid="{}"><state state="{}" reason="{}" reason_ttl="{}"/>"#,
+                    result.protocol,
+                    result.port,
+                    result.state,
+                    nmap_reason(result.reason),
+                    result.ttl.unwrap_or(0)
+                )?;
+                let probed = result.product.is_some() || result.version.is_some() || result.info.is_some();
+                let mut service = format!(r#"<service name="{}""#, escape(&result.service));
+                for (attribute, value) in [
+                    ("product", &result.product),
+                    ("version", &result.version),
+                    ("extrainfo", &result.info),
+                ] {
+                    if let Some(value) = value {
+                        service += &format!(r#" {}="{}""#, attribute, escape(value));
+                    }
+                }
+                // nmap's confidence is 10 for a probe match and 3 for a guess by port
+                service += if probed { r#" method="probed" conf="10"/>"# } else { r#" method="table" conf="3"/>"# };
+                writeln!(out, "{}</port>", service)?;
+            }
+            writeln!(out, "</ports>")?;
+
+            if let Some(os) = &host.os {
+                let vendor = os.name.split_whitespace().next().unwrap_or(&os.family);
+                writeln!(out, "<os>")?;
+                writeln!(
+                    out,
+                    r#"<osmatch name="{}" accuracy="{}" line="0">"#,
+                    escape(&os.name),
+                    os.confidence
+                )?;
+                writeln!(
+                    out,
+                    r#"<osclass vendor="{}" osfamily="{}" accuracy="{}"/>"#,
+                    escape(vendor),
+                    escape(&os.family),
+                    os.confidence
+                )?;
+                writeln!(out, "</osmatch>")?;
+                writeln!(out, "</os>")?;
+            }
+            writeln!(out, "</host>")?;
+        }
+
+        let finished = self.finished.unwrap_or_else(SystemTime::now);
+        let up = self.hosts_up();
+        writeln!(out, "<runstats>")?;
+        // A scan that was stopped, or is still going, is reported as an error
+        let exit = match self.finished {
+            Some(_) => r#"exit="success""#,
+            None => r#"exit="error" errormsg="Scan did not finish""#,
+        };
+        writeln!(
+            out,
+            r#"<finished time="{}" timestr="{}" summary="{}" elapsed="{:.2}" {}/>"#,
+            unix_seconds(finished),
+            escape(&time_string(finished)),
+            escape(&self.summary()),
+            self.elapsed().as_secs_f64(),
+            exit
+        )?;
+        writeln!(
+            out,
+            r#"<hosts up="{}" down="{}" total="{}"/>"#,
+            up,
+            self.hosts.len() - up,
+            self.hosts.len()
+        )?;
+        writeln!(out, "</runstats>")?;
+        writeln!(out, "</nmaprun>")
+    }
+
+    // e.g. "Host: 10.0.0.1 (router)  Status: Up  Ports: 22/open/tcp//ssh//OpenSSH 9.6/,
+    // 80/open/tcp//http///  Ignored State: closed (998)  OS: Linux 2.6.32 - 6.x", tab separated
+    fn write_grepable<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(
+            out,
+            "# DeepNet {} scan initiated {} as: {}",
+            env!("CARGO_PKG_VERSION"),
+            time_string(self.started),
+            command_line(&self.config)
+        )?;
+        for (host, ports) in self.up_hosts() {
+            let mut line = format!(
+                "Host: {} ({})\tStatus: Up",
+                host.address,
+                host.hostname.as_deref().unwrap_or_default()
+            );
+            let ignored = ignored_state(&ports);
+            let listed: Vec<String> = ports
+                .iter()
+                .filter(|result| Some(result.state) != ignored.map(|(state, _)| state))
+                .map(|result| {
+                    let version: Vec<&str> = [&result.product, &result.version, &result.info]
+                        .into_iter()
+                        .flatten()
+                        .map(String::as_str)
+                        .collect();
+                    // Slashes separate the fields, nmap swaps them for bars
+                    format!(
+                        "{}/{}/{}//{}//{}/",
+                        result.port,
+                        result.state,
+                        result.protocol,
+                        result.service.replace('/', "|"),
+                        version.join(" ").replace('/', "|")
+                    )
+                })
+                .collect();
+            if !listed.is_empty() {
+                line += &format!("\tPorts: {}", listed.join(", "));
+            }
+            if let Some((state, count)) = ignored {
+                line += &format!("\tIgnored State: {} ({})", state, count);
+            }
+            if let Some(os) = &host.os {
+                line += &format!("\tOS: {}", os.name);
+            }
+            writeln!(out, "{}", line)?;
+        }
+        match self.finished {
+            Some(finished) => writeln!(out, "# DeepNet done at {} -- {}", time_string(finished), self.summary()),
+            None => writeln!(out, "# DeepNet did not finish -- {}", self.summary()),
+        }
+    }
+
+    // Live hosts by address, each with its ports by protocol and number.
+    fn up_hosts(&self) -> Vec<(&HostStatus, Vec<&PortResult>)> {
+        let mut hosts: Vec<(&HostStatus, Vec<&PortResult>)> = self
+            .hosts
+            .iter()
+            .filter(|host| host.status.up)
+            .map(|host| {
+                let mut ports: Vec<&PortResult> = host.ports.iter().collect();
+                ports.sort_by_key(|result| (result.protocol, result.port));
+                (&host.status, ports)
+            })
+            .collect();
+        hosts.sort_by_key(|(host, _)| host.address);
+        hosts
+    }
+
+    // e.g. "256 IP addresses (3 hosts up) scanned in 12.34 seconds"
+    fn summary(&self) -> String {
+        let total = self.hosts.len();
+        let up = self.hosts_up();
+        format!(
+            "{} IP address{} ({} host{} up) scanned in {:.2} seconds",
+            total,
+            if total == 1 { "" } else { "es" },
+            up,
+            if up == 1 { "" } else { "s" },
+            self.elapsed().as_secs_f64()
+        )
+    }
+
+    fn elapsed(&self) -> Duration {
+        let finished = self.finished.unwrap_or_else(SystemTime::now);
+        finished.duration_since(self.started).unwrap_or_default()
+    }
+}
+
+// The state most of a host's other ports share, and how many share it, if that's
+// enough to leave them out of the listing.
+fn ignored_state(ports: &[&PortResult]) -> Option<(PortState, usize)> {
+    let mut counts: BTreeMap<PortState, usize> = BTreeMap::new();
+    for result in ports.iter().filter(|result| result.state != PortState::Open) {
+        *counts.entry(result.state).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(_, count)| count)
+        .filter(|&(_, count)| count > IGNORED_THRESHOLD)
+}
+
+// The nmap options that would run the same scan, for the record.
+pub(super) fn command_line(config: &ScanConfig) -> String {
+    let mut args = vec!["deepnet".to_string(), nmap_scan_type(config.scan_type).0.to_string()];
+    if config.discovery.skip {
+        args.push("-Pn".to_string());
+    }
+    if config.service_detection {
+        args.push("-sV".to_string());
+    }
+    if config.os_detection {
+        args.push("-O".to_string());
+    }
+    if let Some(level) = TimingTemplate::ALL.iter().position(|&timing| timing == config.timing) {
+        args.push(format!("-T{}", level));
+    }
+    if let Some(retries) = config.max_retries {
+        args.push(format!("--max-retries {}", retries));
+    }
+    args.push(format!("-p {}", quoted(&config.ports)));
+    if !config.exclude.trim().is_empty() {
+        args.push(format!("--exclude {}", quoted(&config.exclude)));
+    }
+    if let Some(path) = &config.target_file {
+        args.push(format!("-iL {}", quoted(&path.to_string_lossy())));
+    }
+    if !config.target.trim().is_empty() {
+        args.push(config.target.trim().to_string());
+    }
+    args.join(" ")
+}
+
+// The scan's nmap option and its name in nmap's XML.
+fn nmap_scan_type(scan_type: ScanType) -> (&'static str, &'static str) {
+    match scan_type {
+        ScanType::TcpSyn => ("-sS", "syn"),
+        ScanType::TcpConnect => ("-sT", "connect"),
+        ScanType::Udp => ("-sU", "udp"),
+        ScanType::TcpFin => ("-sF", "fin"),
+        ScanType::TcpNull => ("-sN", "null"),
+        ScanType::TcpXmas => ("-sX", "xmas"),
+        ScanType::TcpAck => ("-sA", "ack"),
+        ScanType::TcpWindow => ("-sW", "window"),
+        ScanType::TcpMaimon => ("-sM", "maimon"),
+        ScanType::SctpInit => ("-sY", "sctpinit"),
+        ScanType::SctpCookieEcho => ("-sZ", "sctpcookieecho"),
+    }
+}
+
+// Our reasons are nmap's, bar the name for a TCP reset.
+fn nmap_reason(reason: Reason) -> String {
+    match reason {
+        Reason::Rst => "reset".to_string(),
+        reason => reason.to_string(),
+    }
+}
+
+// Sorted ports as nmap writes them, e.g. "1-1024,3389,8080-8090".
+fn port_ranges(ports: &[u16]) -> String {
+    let mut ranges: Vec<(u16, u16)> = Vec::new();
+    for &port in ports {
+        match ranges.last_mut() {
+            Some((_, end)) if port as u32 == *end as u32 + 1 => *end = port,
+            _ => ranges.push((port, port)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}-{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn quoted(arg: &str) -> String {
+    if arg.contains(char::is_whitespace) {
+        format!("'{}'", arg)
+    } else {
+        arg.to_string()
+    }
+}
+
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters aren't allowed in XML 1.0, even escaped
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs())
+}
+
+// Local time the way nmap writes it, e.g. "Sun Oct 18 14:03:27 2026".
+pub(super) fn time_string(time: SystemTime) -> String {
+    let seconds = unix_seconds(time) as libc::time_t;
+    let mut buffer = [0u8; 64];
+    let length = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&seconds, &mut tm);
+        libc::strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            c"%a %b %d %H:%M:%S %Y".as_ptr(),
+            &tm,
+        )
+    };
+    String::from_utf8_lossy(&buffer[..length]).into_owned()
+}
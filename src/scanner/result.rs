@@ -0,0 +1,196 @@
+use pnet::util::MacAddr;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use crate::services;
+
+use super::progress::Progress;
+use super::target::Target;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Transport {
+    Tcp,
+    Udp,
+    Sctp,
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum PortState {
+    Open,
+    Closed,
+    Filtered,
+    Unfiltered,
+    OpenFiltered,
+}
+
+// Why a port or host was given its state, named after what came back from the target.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Reason {
+    SynAck,
+    Rst,
+    InitAck,
+    Abort,
+    ConnRefused,
+    UdpResponse,
+    IcmpUnreach,
+    NoResponse,
+    ArpResponse,
+    EchoReply,
+    TimestampReply,
+    LocalhostResponse,
+    // Host discovery was skipped, so the host is assumed to be up
+    UserSet,
+}
+
+// Where a host's name came from: the target specification, or a reverse DNS
+// lookup of its address.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum HostnameSource {
+    #[default]
+    User,
+    Ptr,
+}
+
+// Whether a host answered discovery, and how.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostStatus {
+    pub address: IpAddr,
+    pub hostname: Option<String>,
+    #[serde(default)]
+    pub hostname_source: HostnameSource,
+    pub up: bool,
+    pub reason: Reason,
+    pub mac: Option<MacAddr>,
+    // Maker of the network card the MAC belongs to
+    #[serde(default)]
+    pub vendor: Option<String>,
+    pub latency: Option<Duration>,
+    pub os: Option<OsGuess>,
+}
+
+// Everything a scan found out about one host: its addresses, how it answered
+// discovery, what it's running and its port table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostResult {
+    pub status: HostStatus,
+    // In the order they were scanned
+    pub ports: Vec<PortResult>,
+}
+
+impl HostResult {
+    pub fn new(status: HostStatus) -> Self {
+        Self {
+            status,
+            ports: Vec::new(),
+        }
+    }
+
+    pub fn open_ports(&self) -> usize {
+        self.ports.iter().filter(|result| result.state == PortState::Open).count()
+    }
+}
+
+// The fingerprint that best matched a host's responses to OS detection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OsGuess {
+    pub name: String,
+    pub family: String,
+    // Percentage of the tested points that matched
+    pub confidence: u8,
+}
+
+// Everything a scan reports back while it runs.
+#[derive(Debug, Clone)]
+pub enum ScanEvent {
+    // Sent again with the OS filled in once OS detection has run
+    Host(HostStatus),
+    Port(PortResult),
+    // Service detection results for a port reported earlier
+    Service(PortResult),
+    Progress(Progress),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PortResult {
+    pub host: IpAddr,
+    pub hostname: Option<String>,
+    pub port: u16,
+    pub protocol: Transport,
+    pub state: PortState,
+    pub reason: Reason,
+    pub ttl: Option<u8>,
+    pub rtt: Option<Duration>,
+    pub service: String,
+    pub product: Option<String>,
+    pub version: Option<String>,
+    pub info: Option<String>,
+}
+
+impl PortResult {
+    pub fn new(
+        target: &Target,
+        port: u16,
+        protocol: Transport,
+        state: PortState,
+        reason: Reason,
+    ) -> Self {
+        Self {
+            host: target.address,
+            hostname: target.hostname.clone(),
+            port,
+            protocol,
+            state,
+            reason,
+            ttl: None,
+            rtt: None,
+            service: services::name(port, protocol).unwrap_or("unknown").to_string(),
+            product: None,
+            version: None,
+            info: None,
+        }
+    }
+}
+
+impl fmt::Display for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Transport::Tcp => "tcp",
+            Transport::Udp => "udp",
+            Transport::Sctp => "sctp",
+        })
+    }
+}
+
+impl fmt::Display for PortState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PortState::Open => "open",
+            PortState::Closed => "closed",
+            PortState::Filtered => "filtered",
+            PortState::Unfiltered => "unfiltered",
+            PortState::OpenFiltered => "open|filtered",
+        })
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Reason::SynAck => "syn-ack",
+            Reason::Rst => "rst",
+            Reason::InitAck => "init-ack",
+            Reason::Abort => "abort",
+            Reason::ConnRefused => "conn-refused",
+            Reason::UdpResponse => "udp-response",
+            Reason::IcmpUnreach => "icmp-unreach",
+            Reason::NoResponse => "no-response",
+            Reason::ArpResponse => "arp-response",
+            Reason::EchoReply => "echo-reply",
+            Reason::TimestampReply => "timestamp-reply",
+            Reason::LocalhostResponse => "localhost-response",
+            Reason::UserSet => "user-set",
+        })
+    }
+}
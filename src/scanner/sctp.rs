@@ -0,0 +1,281 @@
+use crate::utils::crc32c;
+
+use super::engine::{Answer, Prober};
+use super::raw::{Reply, Unreachable};
+use super::result::{PortState, Reason};
+use super::ScanType;
+
+const SCTP_HEADER_LEN: usize = 12;
+
+// Chunk types we send or expect back
+const CHUNK_INIT: u8 = 1;
+const CHUNK_INIT_ACK: u8 = 2;
+const CHUNK_ABORT: u8 = 6;
+const CHUNK_COOKIE_ECHO: u8 = 10;
+
+// INIT scans half-open an association the way a SYN scan does with TCP;
+// COOKIE ECHO scans skip the handshake and only hear back from closed ports.
+pub(super) struct SctpProbe {
+    source_port: u16,
+    secret: u32,
+    scan_type: ScanType,
+}
+
+impl SctpProbe {
+    pub fn new(scan_type: ScanType) -> Self {
+        Self {
+            source_port: rand::random::<u16>() % 16384 + 49152,
+            secret: rand::random(),
+            scan_type,
+        }
+    }
+
+    // Per-port tag, so replies can be checked without keeping state. An INIT
+    // offers it as its initiate tag, a COOKIE ECHO carries it as the
+    // verification tag; either way the ABORT or INIT ACK comes back with it.
+    fn tag(&self, port: u16) -> u32 {
+        // Zero isn't a valid initiate tag
+        (self.secret ^ (port as u32).wrapping_mul(0x9e37_79b1)) | 1
+    }
+
+    // Verification tag of the probe itself. An INIT has no association to name yet.
+    fn verification_tag(&self, port: u16) -> u32 {
+        match self.scan_type {
+            ScanType::SctpCookieEcho => self.tag(port),
+            _ => 0,
+        }
+    }
+
+    fn probe_chunk(&self, port: u16) -> Vec<u8> {
+        match self.scan_type {
+            ScanType::SctpCookieEcho => {
+                // The cookie only has to be there, it's never going to check out
+                chunk(CHUNK_COOKIE_ECHO, 0, &[0; 4])
+            }
+            _ => {
+                let mut init = Vec::with_capacity(16);
+                init.extend_from_slice(&self.tag(port).to_be_bytes());
+                // Receiver window, outbound and inbound streams, initial TSN
+                init.extend_from_slice(&32768u32.to_be_bytes());
+                init.extend_from_slice(&10u16.to_be_bytes());
+                init.extend_from_slice(&2048u16.to_be_bytes());
+                init.extend_from_slice(&self.tag(port).to_be_bytes());
+                chunk(CHUNK_INIT, 0, &init)
+            }
+        }
+    }
+
+    fn packet(&self, port: u16, verification_tag: u32, chunk: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(SCTP_HEADER_LEN + chunk.len());
+        packet.extend_from_slice(&self.source_port.to_be_bytes());
+        packet.extend_from_slice(&port.to_be_bytes());
+        packet.extend_from_slice(&verification_tag.to_be_bytes());
+        packet.extend_from_slice(&[0; 4]);
+        packet.extend_from_slice(chunk);
+        // Unlike the rest of the header, the checksum goes out least significant byte first
+        let checksum = crc32c(&packet);
+        packet[8..12].copy_from_slice(&checksum.to_le_bytes());
+
+        packet
+    }
+}
+
+impl Prober for SctpProbe {
+    fn probe(&self, port: u16) -> Vec<u8> {
+        self.packet(port, self.verification_tag(port), &self.probe_chunk(port))
+    }
+
+    fn classify(&self, reply: &Reply) -> Option<Answer> {
+        let packet = reply.payload;
+        let field = |i: usize| {
+            packet.get(i..i + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let port = u16::from_be_bytes([*packet.first()?, *packet.get(1)?]);
+        let destination = u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]);
+        if destination != self.source_port || field(4)? != self.tag(port) {
+            return None;
+        }
+
+        let (state, reason, teardown) = match *packet.get(SCTP_HEADER_LEN)? {
+            // Abort the half-open association, addressed with the peer's initiate
+            // tag that follows the chunk header
+            CHUNK_INIT_ACK => (
+                PortState::Open,
+                Reason::InitAck,
+                Some(self.packet(port, field(16)?, &chunk(CHUNK_ABORT, 0, &[]))),
+            ),
+            CHUNK_ABORT => (PortState::Closed, Reason::Abort, None),
+            _ => return None,
+        };
+        Some(Answer { port, state, reason, teardown })
+    }
+
+    fn classify_unreachable(&self, unreachable: &Unreachable) -> Option<Answer> {
+        let (source_port, port) = unreachable.ports()?;
+        let tag = u32::from_be_bytes(unreachable.header.get(4..8)?.try_into().ok()?);
+        if source_port != self.source_port || tag != self.verification_tag(port) {
+            return None;
+        }
+
+        Some(Answer {
+            port,
+            state: PortState::Filtered,
+            reason: Reason::IcmpUnreach,
+            teardown: None,
+        })
+    }
+
+    // Listening endpoints silently drop a COOKIE ECHO they have no cookie for.
+    fn unanswered_state(&self) -> PortState {
+        match self.scan_type {
+            ScanType::SctpCookieEcho => PortState::OpenFiltered,
+            _ => PortState::Filtered,
+        }
+    }
+}
+
+// An SCTP chunk: type, flags and length, then the value padded to 4 bytes.
+fn chunk(kind: u8, flags: u8, value: &[u8]) -> Vec<u8> {
+    let mut chunk = vec![kind, flags];
+    chunk.extend_from_slice(&(4 + value.len() as u16).to_be_bytes());
+    chunk.extend_from_slice(value);
+    chunk.resize(chunk.len().next_multiple_of(4), 0);
+    chunk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet::packet::ip::IpNextHeaderProtocols;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const TARGET: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    // What the target would send back to a probe of `port`.
+    fn reply_packet(probe: &SctpProbe, port: u16, tag: u32, chunk: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&port.to_be_bytes());
+        packet.extend_from_slice(&probe.source_port.to_be_bytes());
+        packet.extend_from_slice(&tag.to_be_bytes());
+        packet.extend_from_slice(&[0; 4]);
+        packet.extend_from_slice(chunk);
+        let checksum = crc32c(&packet);
+        packet[8..12].copy_from_slice(&checksum.to_le_bytes());
+        packet
+    }
+
+    fn classify(probe: &SctpProbe, packet: &[u8]) -> Option<Answer> {
+        probe.classify(&Reply {
+            source: TARGET,
+            hop_limit: Some(64),
+            identification: None,
+            dont_fragment: None,
+            payload: packet,
+        })
+    }
+
+    fn checksum_ok(packet: &[u8]) -> bool {
+        let mut zeroed = packet.to_vec();
+        zeroed[8..12].fill(0);
+        crc32c(&zeroed).to_le_bytes() == packet[8..12]
+    }
+
+    #[test]
+    fn crc32c_matches_rfc_3720_test_vectors() {
+        let incrementing: Vec<u8> = (0..32).collect();
+        let decrementing: Vec<u8> = (0..32).rev().collect();
+        assert_eq!(crc32c(&[0; 32]), 0x8a91_36aa);
+        assert_eq!(crc32c(&[0xff; 32]), 0x62a8_ab43);
+        assert_eq!(crc32c(&incrementing), 0x46dd_794e);
+        assert_eq!(crc32c(&decrementing), 0x113f_db5c);
+        // The RFC lists them as sent, least significant byte first
+        assert_eq!(crc32c(&[0; 32]).to_le_bytes(), [0xaa, 0x36, 0x91, 0x8a]);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn init_probes_are_well_formed() {
+        let probe = SctpProbe::new(ScanType::SctpInit);
+        let packet = probe.probe(38412);
+        assert_eq!(packet.len(), SCTP_HEADER_LEN + 20);
+        assert_eq!(packet[2..4], 38412u16.to_be_bytes());
+        // No association yet, so no verification tag
+        assert_eq!(packet[4..8], [0; 4]);
+        assert_eq!(packet[12], CHUNK_INIT);
+        assert_eq!(packet[14..16], 20u16.to_be_bytes());
+        assert_eq!(packet[16..20], probe.tag(38412).to_be_bytes());
+        assert!(checksum_ok(&packet));
+    }
+
+    #[test]
+    fn init_ack_means_open_and_is_aborted() {
+        let probe = SctpProbe::new(ScanType::SctpInit);
+        let peer_tag = 0xdead_beefu32;
+        let mut init_ack = peer_tag.to_be_bytes().to_vec();
+        init_ack.extend_from_slice(&[0; 12]);
+        let packet = reply_packet(&probe, 2905, probe.tag(2905), &chunk(CHUNK_INIT_ACK, 0, &init_ack));
+
+        let answer = classify(&probe, &packet).unwrap();
+        assert_eq!((answer.port, answer.state, answer.reason), (2905, PortState::Open, Reason::InitAck));
+        // The ABORT is addressed with the tag the target chose
+        let teardown = answer.teardown.unwrap();
+        assert_eq!(teardown[4..8], peer_tag.to_be_bytes());
+        assert_eq!(teardown[12], CHUNK_ABORT);
+        assert!(checksum_ok(&teardown));
+    }
+
+    #[test]
+    fn abort_means_closed() {
+        let probe = SctpProbe::new(ScanType::SctpInit);
+        let packet = reply_packet(&probe, 3868, probe.tag(3868), &chunk(CHUNK_ABORT, 0, &[]));
+
+        let answer = classify(&probe, &packet).unwrap();
+        assert_eq!((answer.port, answer.state, answer.reason), (3868, PortState::Closed, Reason::Abort));
+        assert!(answer.teardown.is_none());
+    }
+
+    #[test]
+    fn replies_to_someone_else_are_ignored() {
+        let probe = SctpProbe::new(ScanType::SctpInit);
+        let abort = chunk(CHUNK_ABORT, 0, &[]);
+        assert!(classify(&probe, &reply_packet(&probe, 3868, probe.tag(3868) ^ 2, &abort)).is_none());
+        assert!(classify(&probe, &reply_packet(&probe, 3868, probe.tag(3869), &abort)).is_none());
+
+        let mut elsewhere = reply_packet(&probe, 3868, probe.tag(3868), &abort);
+        elsewhere[2..4].copy_from_slice(&probe.source_port.wrapping_add(1).to_be_bytes());
+        assert!(classify(&probe, &elsewhere).is_none());
+        assert!(classify(&probe, &elsewhere[..6]).is_none());
+    }
+
+    #[test]
+    fn cookie_echo_probes_only_hear_from_closed_ports() {
+        let probe = SctpProbe::new(ScanType::SctpCookieEcho);
+        let packet = probe.probe(9900);
+        assert_eq!(packet[4..8], probe.tag(9900).to_be_bytes());
+        assert_eq!(packet[12], CHUNK_COOKIE_ECHO);
+        assert!(checksum_ok(&packet));
+
+        let abort = reply_packet(&probe, 9900, probe.tag(9900), &chunk(CHUNK_ABORT, 0, &[]));
+        assert_eq!(classify(&probe, &abort).unwrap().state, PortState::Closed);
+        assert_eq!(probe.unanswered_state(), PortState::OpenFiltered);
+        assert_eq!(SctpProbe::new(ScanType::SctpInit).unanswered_state(), PortState::Filtered);
+    }
+
+    #[test]
+    fn unreachables_quoting_a_probe_mean_filtered() {
+        let probe = SctpProbe::new(ScanType::SctpCookieEcho);
+        let quoted = probe.probe(9900);
+        let unreachable = Unreachable {
+            port_unreachable: false,
+            destination: TARGET,
+            protocol: IpNextHeaderProtocols::Sctp,
+            header: &quoted[..8],
+        };
+        let answer = probe.classify_unreachable(&unreachable).unwrap();
+        assert_eq!((answer.port, answer.state, answer.reason), (9900, PortState::Filtered, Reason::IcmpUnreach));
+
+        let other = SctpProbe::new(ScanType::SctpCookieEcho).probe(9900);
+        let unreachable = Unreachable { header: &other[..8], ..unreachable };
+        assert!(probe.classify_unreachable(&unreachable).is_none());
+    }
+}
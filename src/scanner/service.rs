@@ -0,0 +1,179 @@
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::ratelimit;
+
+use super::connect::{SYN_LEN_V4, SYN_LEN_V6};
+use super::probes::{Identified, ServiceMatch, ServiceProbes};
+use super::progress::{CancelToken, ProgressMeter};
+use super::result::{PortResult, Transport};
+
+// Service and version detection for open ports. We connect and wait for a banner,
+// then send protocol probes until a response matches one of their signatures.
+
+// How long to wait for the first byte of a response, unless the probe says otherwise
+const RESPONSE_WAIT: Duration = Duration::from_secs(2);
+// Once data starts arriving, how long to wait for the rest of it
+const TRAILING_WAIT: Duration = Duration::from_millis(250);
+const MAX_RESPONSE: usize = 16384;
+const UDP_ATTEMPTS: usize = 2;
+const MAX_WORKERS: usize = 16;
+
+pub struct ServiceDetector {
+    probes: ServiceProbes,
+}
+
+impl ServiceDetector {
+    // The bundled probes, plus those from a probe file or directory of them.
+    pub fn new(extra: Option<&Path>) -> io::Result<Self> {
+        let mut probes = ServiceProbes::bundled();
+        if let Some(path) = extra {
+            probes.load(path)?;
+        }
+        Ok(Self { probes })
+    }
+
+    // Identify the service on every open port, reporting each port once it's done
+    // and whether it matched; those that did have their service, product and
+    // version filled in. Cancelling lets the ports already being probed finish
+    // and skips the rest.
+    pub(super) fn detect_all<F>(
+        &self,
+        ports: Vec<PortResult>,
+        connect_timeout: Duration,
+        cancel: &CancelToken,
+        progress: &ProgressMeter,
+        report: F,
+    ) where
+        F: Fn(PortResult, bool) + Sync,
+    {
+        let workers = ports.len().min(MAX_WORKERS);
+        let queue = Mutex::new(ports);
+
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| loop {
+                    if cancel.is_cancelled() {
+                        break;
+                    }
+                    let Some(mut result) = queue.lock().unwrap().pop() else {
+                        break;
+                    };
+                    let address = SocketAddr::new(result.host, result.port);
+                    let found = self.detect(address, result.protocol, connect_timeout);
+                    let identified = found.is_some();
+                    if let Some(found) = found {
+                        result.service = found.service;
+                        result.product = found.product;
+                        result.version = found.version;
+                        result.info = found.info;
+                        progress.received(1);
+                    }
+                    report(result, identified);
+                    progress.done(1);
+                });
+            }
+        });
+    }
+
+    pub fn detect(
+        &self,
+        address: SocketAddr,
+        protocol: Transport,
+        connect_timeout: Duration,
+    ) -> Option<ServiceMatch> {
+        if self.probes.excluded(protocol, address.port()) {
+            return None;
+        }
+
+        let mut soft: Option<ServiceMatch> = None;
+        for probe in self.probes.sequence(protocol, address.port()) {
+            // After a softmatch, only probes that can confirm the service are worth sending
+            if let Some(soft) = &soft
+                && !self.probes.can_identify(probe, &soft.service)
+            {
+                continue;
+            }
+
+            let wait = probe.wait.unwrap_or(RESPONSE_WAIT);
+            let response = match protocol {
+                Transport::Tcp => tcp_exchange(address, &probe.payload, connect_timeout, wait),
+                Transport::Udp => udp_exchange(address, &probe.payload, wait),
+                // Probe files can't declare SCTP probes, so there are none to send
+                Transport::Sctp => break,
+            };
+            let response = match response {
+                Ok(response) => response,
+                // The port has closed since it was scanned, no point going on
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => break,
+                Err(_) => continue,
+            };
+
+            let service = soft.as_ref().map(|soft| soft.service.as_str());
+            match self.probes.identify(probe, &response, service) {
+                Some(Identified::Hard(found)) => return Some(found),
+                Some(Identified::Soft(found)) => soft = Some(found),
+                None => {}
+            }
+        }
+        soft
+    }
+}
+
+// Connect, send the payload if there is one, and collect whatever comes back.
+fn tcp_exchange(
+    address: SocketAddr,
+    payload: &[u8],
+    connect_timeout: Duration,
+    wait: Duration,
+) -> io::Result<Vec<u8>> {
+    ratelimit::acquire(if address.is_ipv4() { SYN_LEN_V4 } else { SYN_LEN_V6 });
+    let mut stream = TcpStream::connect_timeout(&address, connect_timeout)?;
+    if !payload.is_empty() {
+        ratelimit::acquire(payload.len());
+        stream.write_all(payload)?;
+    }
+
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 4096];
+    stream.set_read_timeout(Some(wait))?;
+    while response.len() < MAX_RESPONSE {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(length) => {
+                response.extend_from_slice(&buffer[..length]);
+                stream.set_read_timeout(Some(TRAILING_WAIT))?;
+            }
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => break,
+            // Some services hang up right after their answer
+            Err(_) if !response.is_empty() => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(response)
+}
+
+fn udp_exchange(address: SocketAddr, payload: &[u8], wait: Duration) -> io::Result<Vec<u8>> {
+    let socket = match address {
+        SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+        SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+    };
+    socket.connect(address)?;
+    socket.set_read_timeout(Some(wait))?;
+
+    let mut buffer = [0u8; 4096];
+    for _ in 0..UDP_ATTEMPTS {
+        ratelimit::acquire(payload.len());
+        socket.send(payload)?;
+        match socket.recv(&mut buffer) {
+            Ok(length) => return Ok(buffer[..length].to_vec()),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Vec::new())
+}
@@ -0,0 +1,143 @@
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::tcp::{MutableTcpPacket, TcpFlags, TcpOption, TcpPacket};
+use pnet::packet::Packet;
+
+use super::engine::{Answer, Prober};
+use super::raw::{Reply, Route, Unreachable};
+use super::result::{PortState, Reason};
+use super::ScanType;
+
+const TCP_HEADER_LEN: usize = 24;
+
+// The SYN scan and its stealthier variants, which differ only in the flags they
+// send and how they read the replies.
+pub(super) struct TcpProbe {
+    route: Route,
+    source_port: u16,
+    secret: u32,
+    scan_type: ScanType,
+}
+
+impl TcpProbe {
+    pub fn new(route: Route, scan_type: ScanType) -> Self {
+        Self {
+            route,
+            source_port: rand::random::<u16>() % 16384 + 49152,
+            secret: rand::random(),
+            scan_type,
+        }
+    }
+
+    // Flags each raw TCP scan sets on its probes.
+    fn flags(&self) -> u16 {
+        match self.scan_type {
+            ScanType::TcpFin => TcpFlags::FIN,
+            ScanType::TcpNull => 0,
+            ScanType::TcpXmas => TcpFlags::FIN | TcpFlags::PSH | TcpFlags::URG,
+            ScanType::TcpAck | ScanType::TcpWindow => TcpFlags::ACK,
+            ScanType::TcpMaimon => TcpFlags::FIN | TcpFlags::ACK,
+            _ => TcpFlags::SYN,
+        }
+    }
+
+    // Per-port initial sequence number, so replies can be checked without keeping state
+    fn sequence(&self, port: u16) -> u32 {
+        self.secret ^ (port as u32).wrapping_mul(0x9e37_79b1)
+    }
+
+    fn packet(&self, port: u16, flags: u16, sequence: u32) -> Vec<u8> {
+        let mut tcp_buffer = [0u8; TCP_HEADER_LEN];
+        let mut tcp_packet = MutableTcpPacket::new(&mut tcp_buffer).unwrap();
+        tcp_packet.set_source(self.source_port);
+        tcp_packet.set_destination(port);
+        tcp_packet.set_sequence(sequence);
+        // The reset to an ACK takes its sequence number from our acknowledgement
+        if flags & TcpFlags::ACK != 0 {
+            tcp_packet.set_acknowledgement(self.sequence(port));
+        }
+        tcp_packet.set_data_offset((TCP_HEADER_LEN / 4) as u8);
+        tcp_packet.set_flags(flags);
+        if flags & TcpFlags::SYN != 0 {
+            tcp_packet.set_window(1024);
+            tcp_packet.set_options(&[TcpOption::mss(1460)]);
+        }
+        let checksum = self.route.checksum(IpNextHeaderProtocols::Tcp, tcp_packet.packet(), 8);
+        tcp_packet.set_checksum(checksum);
+
+        tcp_buffer.to_vec()
+    }
+}
+
+impl Prober for TcpProbe {
+    fn probe(&self, port: u16) -> Vec<u8> {
+        self.packet(port, self.flags(), self.sequence(port))
+    }
+
+    fn classify(&self, reply: &Reply) -> Option<Answer> {
+        let tcp = TcpPacket::new(reply.payload)?;
+        let port = tcp.get_source();
+        if tcp.get_destination() != self.source_port {
+            return None;
+        }
+
+        // Replies to an ACK echo it as their sequence number, anything else is
+        // acknowledged, with SYN and FIN each counting for one
+        let probe_flags = self.flags();
+        let ours = if probe_flags & TcpFlags::ACK != 0 {
+            tcp.get_sequence() == self.sequence(port)
+        } else {
+            let length = (probe_flags & (TcpFlags::SYN | TcpFlags::FIN) != 0) as u32;
+            tcp.get_acknowledgement() == self.sequence(port).wrapping_add(length)
+        };
+        if !ours {
+            return None;
+        }
+
+        let flags = tcp.get_flags();
+        if flags & (TcpFlags::SYN | TcpFlags::ACK) == TcpFlags::SYN | TcpFlags::ACK {
+            Some(Answer {
+                port,
+                state: PortState::Open,
+                reason: Reason::SynAck,
+                // Reset the half-open connection
+                teardown: Some(self.packet(port, TcpFlags::RST, tcp.get_acknowledgement())),
+            })
+        } else if flags & TcpFlags::RST != 0 {
+            let state = match self.scan_type {
+                ScanType::TcpAck => PortState::Unfiltered,
+                // Some stacks advertise a window on resets from open ports only
+                ScanType::TcpWindow if tcp.get_window() > 0 => PortState::Open,
+                _ => PortState::Closed,
+            };
+            Some(Answer { port, state, reason: Reason::Rst, teardown: None })
+        } else {
+            None
+        }
+    }
+
+    fn classify_unreachable(&self, unreachable: &Unreachable) -> Option<Answer> {
+        let (source_port, port) = unreachable.ports()?;
+        let sequence = u32::from_be_bytes(unreachable.header.get(4..8)?.try_into().ok()?);
+        if source_port != self.source_port || sequence != self.sequence(port) {
+            return None;
+        }
+
+        Some(Answer {
+            port,
+            state: PortState::Filtered,
+            reason: Reason::IcmpUnreach,
+            teardown: None,
+        })
+    }
+
+    // Open ports ignore the probes of the FIN, NULL, Xmas and Maimon scans, while
+    // anything reachable answers an ACK, so there silence means a firewall ate it.
+    fn unanswered_state(&self) -> PortState {
+        match self.scan_type {
+            ScanType::TcpFin | ScanType::TcpNull | ScanType::TcpXmas | ScanType::TcpMaimon => {
+                PortState::OpenFiltered
+            }
+            _ => PortState::Filtered,
+        }
+    }
+}
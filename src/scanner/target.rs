@@ -0,0 +1,378 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+
+use super::dns::{AddressChoice, Resolver};
+use crate::utils::ipv4_to_u32;
+
+// Refuse to expand anything bigger than a /12 into individual hosts
+pub const MAX_TARGETS: usize = 1 << 20;
+
+#[derive(Debug)]
+pub enum TargetError {
+    Invalid(String),
+    Resolve(String, io::Error),
+    DnsServer(String),
+    File(PathBuf, io::Error),
+    TooMany(usize),
+    Empty,
+}
+
+impl fmt::Display for TargetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TargetError::Invalid(spec) => write!(f, "Invalid target \"{}\"", spec),
+            TargetError::Resolve(host, e) => write!(f, "Failed to resolve \"{}\": {}", host, e),
+            TargetError::DnsServer(server) => write!(f, "Invalid DNS server \"{}\"", server),
+            TargetError::File(path, e) => {
+                write!(f, "Failed to read target file {}: {}", path.display(), e)
+            }
+            TargetError::TooMany(count) => write!(
+                f,
+                "Target specification expands to {} or more hosts (limit {})",
+                count, MAX_TARGETS
+            ),
+            TargetError::Empty => write!(f, "No targets left to scan"),
+        }
+    }
+}
+
+impl std::error::Error for TargetError {}
+
+// A single host to scan, with the name it was given as or resolved back to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub address: IpAddr,
+    pub hostname: Option<String>,
+}
+
+// One entry of a target specification.
+#[derive(Debug, Clone, PartialEq)]
+enum TargetPattern {
+    // Inclusive range of IPv4 addresses, from CIDR blocks and start-end ranges
+    Range(u32, u32),
+    // The same for IPv6 CIDR blocks
+    Range6(u128, u128),
+    // Per-octet ranges in the nmap style, e.g. 10.0.1-3.5-40
+    Octets([(u8, u8); 4]),
+    Address(IpAddr),
+    Hostname(String),
+}
+
+impl TargetPattern {
+    fn parse(spec: &str) -> Result<Self, TargetError> {
+        let invalid = || TargetError::Invalid(spec.to_string());
+
+        if let Ok(address) = spec.parse::<IpAddr>() {
+            return Ok(TargetPattern::Address(address));
+        }
+
+        if let Some((network, prefix)) = spec.split_once('/')
+            && let Ok(network) = network.parse::<Ipv6Addr>()
+        {
+            let prefix = prefix.parse::<u32>().ok().filter(|p| *p <= 128).ok_or_else(invalid)?;
+            let mask = if prefix == 0 { 0 } else { u128::MAX << (128 - prefix) };
+            let start = u128::from(network) & mask;
+            return Ok(TargetPattern::Range6(start, start | !mask));
+        }
+
+        if let Some((network, prefix)) = spec.split_once('/') {
+            let network = network.parse::<Ipv4Addr>().map_err(|_| invalid())?;
+            let prefix = prefix.parse::<u32>().ok().filter(|p| *p <= 32).ok_or_else(invalid)?;
+            let mask = if prefix == 0 { 0 } else { u32::MAX << (32 - prefix) };
+            let start = ipv4_to_u32(network) & mask;
+            return Ok(TargetPattern::Range(start, start | !mask));
+        }
+
+        if let Some((start, end)) = spec.split_once('-')
+            && let (Ok(start), Ok(end)) = (start.parse::<Ipv4Addr>(), end.parse::<Ipv4Addr>())
+        {
+            let (start, end) = (ipv4_to_u32(start), ipv4_to_u32(end));
+            if start > end {
+                return Err(invalid());
+            }
+            return Ok(TargetPattern::Range(start, end));
+        }
+
+        let octets: Vec<&str> = spec.split('.').collect();
+        let looks_numeric = spec.chars().all(|c| c.is_ascii_digit() || c == '.' || c == '-');
+        if octets.len() == 4 && looks_numeric {
+            let mut ranges = [(0u8, 0u8); 4];
+            for (range, octet) in ranges.iter_mut().zip(&octets) {
+                *range = parse_octet_range(octet).ok_or_else(invalid)?;
+            }
+            return Ok(TargetPattern::Octets(ranges));
+        }
+        if looks_numeric {
+            return Err(invalid());
+        }
+
+        if is_hostname(spec) {
+            return Ok(TargetPattern::Hostname(spec.to_string()));
+        }
+
+        Err(invalid())
+    }
+
+    // Rough host count, used to refuse huge specifications before expanding them.
+    fn size(&self) -> usize {
+        match self {
+            TargetPattern::Range(start, end) => (end - start) as usize + 1,
+            TargetPattern::Range6(start, end) => {
+                usize::try_from(end - start).unwrap_or(usize::MAX).saturating_add(1)
+            }
+            TargetPattern::Octets(ranges) => ranges
+                .iter()
+                .map(|(start, end)| (end - start) as usize + 1)
+                .product(),
+            TargetPattern::Address(_) | TargetPattern::Hostname(_) => 1,
+        }
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self, address) {
+            (TargetPattern::Range(start, end), IpAddr::V4(address)) => {
+                (*start..=*end).contains(&ipv4_to_u32(address))
+            }
+            (TargetPattern::Range6(start, end), IpAddr::V6(address)) => {
+                (*start..=*end).contains(&u128::from(address))
+            }
+            (TargetPattern::Octets(ranges), IpAddr::V4(address)) => ranges
+                .iter()
+                .zip(address.octets())
+                .all(|((start, end), octet)| (*start..=*end).contains(&octet)),
+            (TargetPattern::Address(pattern), address) => *pattern == address,
+            _ => false,
+        }
+    }
+
+    fn expand(
+        &self,
+        resolver: &Resolver,
+        choice: AddressChoice,
+        targets: &mut Vec<Target>,
+    ) -> Result<(), TargetError> {
+        let address = |address: IpAddr| Target { address, hostname: None };
+        match self {
+            TargetPattern::Range(start, end) => {
+                targets.extend((*start..=*end).map(|a| address(IpAddr::V4(Ipv4Addr::from(a)))));
+            }
+            TargetPattern::Range6(start, end) => {
+                targets.extend((*start..=*end).map(|a| address(IpAddr::V6(Ipv6Addr::from(a)))));
+            }
+            TargetPattern::Octets([a, b, c, d]) => {
+                for a in a.0..=a.1 {
+                    for b in b.0..=b.1 {
+                        for c in c.0..=c.1 {
+                            for d in d.0..=d.1 {
+                                targets.push(address(IpAddr::V4(Ipv4Addr::new(a, b, c, d))));
+                            }
+                        }
+                    }
+                }
+            }
+            TargetPattern::Address(pattern) => targets.push(address(*pattern)),
+            TargetPattern::Hostname(host) => {
+                let addresses = choice.select(resolve(resolver, host)?);
+                if addresses.is_empty() {
+                    let e = io::Error::new(io::ErrorKind::NotFound, "no address of the chosen family");
+                    return Err(TargetError::Resolve(host.clone(), e));
+                }
+                targets.extend(addresses.into_iter().map(|address| Target {
+                    address,
+                    hostname: Some(host.clone()),
+                }));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A parsed target specification: what to scan, minus what to leave alone.
+#[derive(Debug, Clone, Default)]
+pub struct TargetSpec {
+    include: Vec<TargetPattern>,
+    exclude: Vec<TargetPattern>,
+}
+
+impl TargetSpec {
+    // Accepts comma or whitespace separated addresses, CIDR blocks, ranges and hostnames.
+    pub fn parse(targets: &str, exclude: &str) -> Result<Self, TargetError> {
+        Ok(Self {
+            include: parse_list(targets)?,
+            exclude: parse_list(exclude)?,
+        })
+    }
+
+    // Add the targets listed in a file, one or more per line. '#' starts a comment.
+    pub fn add_file(&mut self, path: &Path) -> Result<(), TargetError> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| TargetError::File(path.to_path_buf(), e))?;
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            self.include.extend(parse_list(line)?);
+        }
+        Ok(())
+    }
+
+    // Expand into the individual hosts to scan, in order and without duplicates.
+    // Hostnames are resolved with `resolver` and narrowed down by `choice`.
+    pub fn targets(
+        &self,
+        resolver: &Resolver,
+        choice: AddressChoice,
+    ) -> Result<Vec<Target>, TargetError> {
+        let size = self.include.iter().map(TargetPattern::size).fold(0, usize::saturating_add);
+        if size > MAX_TARGETS {
+            return Err(TargetError::TooMany(size));
+        }
+
+        let mut expanded = Vec::with_capacity(size);
+        for pattern in &self.include {
+            pattern.expand(resolver, choice, &mut expanded)?;
+        }
+
+        // Excluding a hostname excludes every address it has
+        let mut exclude = Vec::new();
+        for pattern in &self.exclude {
+            match pattern {
+                TargetPattern::Hostname(host) => exclude.extend(
+                    resolve(resolver, host)?.into_iter().map(TargetPattern::Address),
+                ),
+                pattern => exclude.push(pattern.clone()),
+            }
+        }
+
+        let mut seen = HashSet::new();
+        expanded.retain(|target| {
+            seen.insert(target.address)
+                && !exclude.iter().any(|pattern| pattern.contains(target.address))
+        });
+
+        if expanded.is_empty() {
+            return Err(TargetError::Empty);
+        }
+        Ok(expanded)
+    }
+}
+
+fn parse_list(list: &str) -> Result<Vec<TargetPattern>, TargetError> {
+    list.split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|spec| !spec.is_empty())
+        .map(TargetPattern::parse)
+        .collect()
+}
+
+fn parse_octet_range(octet: &str) -> Option<(u8, u8)> {
+    let (start, end) = match octet.split_once('-') {
+        Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+        None => {
+            let value = octet.parse().ok()?;
+            (value, value)
+        }
+    };
+    (start <= end).then_some((start, end))
+}
+
+fn is_hostname(spec: &str) -> bool {
+    spec.len() <= 253
+        && spec.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+}
+
+fn resolve(resolver: &Resolver, host: &str) -> Result<Vec<IpAddr>, TargetError> {
+    resolver
+        .lookup(host)
+        .map_err(|e| TargetError::Resolve(host.to_string(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Expand a numeric spec; nothing here needs the resolver.
+    fn expand(targets: &str, exclude: &str) -> Result<Vec<IpAddr>, TargetError> {
+        let spec = TargetSpec::parse(targets, exclude)?;
+        let targets = spec.targets(&Resolver::System, AddressChoice::FirstIpv4)?;
+        Ok(targets.into_iter().map(|target| target.address).collect())
+    }
+
+    fn addresses(addresses: &[&str]) -> Vec<IpAddr> {
+        addresses.iter().map(|address| address.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn cidr_blocks_cover_the_whole_network() {
+        let expected = addresses(&["192.0.2.4", "192.0.2.5", "192.0.2.6", "192.0.2.7"]);
+        assert_eq!(expand("192.0.2.5/30", "").unwrap(), expected);
+
+        let expected = addresses(&["2001:db8::", "2001:db8::1", "2001:db8::2", "2001:db8::3"]);
+        assert_eq!(expand("2001:db8::2/126", "").unwrap(), expected);
+    }
+
+    #[test]
+    fn slash_32_is_a_single_host_and_slash_0_everything() {
+        assert_eq!(expand("192.0.2.9/32", "").unwrap(), addresses(&["192.0.2.9"]));
+        assert_eq!(expand("2001:db8::9/128", "").unwrap(), addresses(&["2001:db8::9"]));
+        assert_eq!(
+            TargetPattern::parse("192.0.2.9/0").unwrap(),
+            TargetPattern::Range(0, u32::MAX)
+        );
+        assert!(matches!(expand("0.0.0.0/0", ""), Err(TargetError::TooMany(count)) if count == 1 << 32));
+    }
+
+    #[test]
+    fn ranges_and_octet_patterns() {
+        let expected = addresses(&["192.0.2.254", "192.0.2.255", "192.0.3.0", "192.0.3.1"]);
+        assert_eq!(expand("192.0.2.254-192.0.3.1", "").unwrap(), expected);
+
+        let expected = addresses(&["10.0.1.5", "10.0.1.6", "10.0.2.5", "10.0.2.6"]);
+        assert_eq!(expand("10.0.1-2.5-6", "").unwrap(), expected);
+    }
+
+    #[test]
+    fn reversed_ranges_are_invalid() {
+        for spec in ["192.0.2.10-192.0.2.1", "10.0.0.9-3", "10.5-1.0.1"] {
+            assert!(matches!(expand(spec, ""), Err(TargetError::Invalid(s)) if s == spec), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn malformed_specs_are_invalid() {
+        for spec in ["192.0.2.0/33", "2001:db8::/129", "192.0.2.300", "10.0.0", "-host.test"] {
+            assert!(matches!(expand(spec, ""), Err(TargetError::Invalid(_))), "{}", spec);
+        }
+        assert_eq!(
+            TargetPattern::parse("host.example.test").unwrap(),
+            TargetPattern::Hostname("host.example.test".to_string())
+        );
+    }
+
+    #[test]
+    fn lists_drop_duplicates_and_exclusions_in_order() {
+        let targets = expand("192.0.2.0/29, 192.0.2.1 198.51.100.7", "192.0.2.2-192.0.2.5,192.0.2.0").unwrap();
+        assert_eq!(targets, addresses(&["192.0.2.1", "192.0.2.6", "192.0.2.7", "198.51.100.7"]));
+    }
+
+    #[test]
+    fn excluding_everything_leaves_nothing_to_scan() {
+        assert!(matches!(expand("192.0.2.0/30", "192.0.2.0/24"), Err(TargetError::Empty)));
+    }
+
+    #[test]
+    fn specs_over_the_limit_are_refused_before_expanding() {
+        // A /12 is exactly the limit, one more host is too many
+        assert!(matches!(
+            expand("10.0.0.0/12, 192.0.2.1", ""),
+            Err(TargetError::TooMany(count)) if count == MAX_TARGETS + 1
+        ));
+        assert!(matches!(expand("2001:db8::/64", ""), Err(TargetError::TooMany(_))));
+    }
+}
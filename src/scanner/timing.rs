@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Pacing and retransmission for the raw-packet scans. Each host gets its own
+// round-trip estimate, which decides how long to wait for replies, and its own
+// scan delay, which grows whenever retransmissions reveal that probes are being
+// dropped. A congestion window, kept the way TCP keeps one, limits how many
+// probes may await an answer from the host at once.
+
+const INITIAL_WINDOW: f64 = 10.0;
+const INITIAL_THRESHOLD: f64 = 75.0;
+
+// Named presets for how fast to scan and how patient to be, after nmap's -T0 to -T5.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum TimingTemplate {
+    Paranoid,
+    Sneaky,
+    Polite,
+    Normal,
+    Aggressive,
+    Insane,
+}
+
+impl TimingTemplate {
+    pub const ALL: [TimingTemplate; 6] = [
+        TimingTemplate::Paranoid,
+        TimingTemplate::Sneaky,
+        TimingTemplate::Polite,
+        TimingTemplate::Normal,
+        TimingTemplate::Aggressive,
+        TimingTemplate::Insane,
+    ];
+
+    // Pause between probes to the same host. The faster templates are only held
+    // back by the probe window until drops push this up.
+    fn scan_delay(self) -> Duration {
+        match self {
+            TimingTemplate::Paranoid => Duration::from_secs(300),
+            TimingTemplate::Sneaky => Duration::from_secs(15),
+            TimingTemplate::Polite => Duration::from_millis(400),
+            _ => Duration::ZERO,
+        }
+    }
+
+    // How far drops may push the scan delay
+    fn max_scan_delay(self) -> Duration {
+        match self {
+            TimingTemplate::Aggressive => Duration::from_millis(10),
+            TimingTemplate::Insane => Duration::from_millis(5),
+            _ => self.scan_delay().max(Duration::from_secs(1)),
+        }
+    }
+
+    // Reply timeout before any round trip has been measured, and the bounds on it after
+    fn rtt_timeouts(self) -> (Duration, Duration, Duration) {
+        match self {
+            TimingTemplate::Aggressive => (
+                Duration::from_millis(500),
+                Duration::from_millis(100),
+                Duration::from_millis(1250),
+            ),
+            TimingTemplate::Insane => (
+                Duration::from_millis(250),
+                Duration::from_millis(50),
+                Duration::from_millis(300),
+            ),
+            _ => (
+                Duration::from_secs(1),
+                Duration::from_millis(100),
+                Duration::from_secs(10),
+            ),
+        }
+    }
+
+    pub fn max_retries(self) -> u32 {
+        match self {
+            TimingTemplate::Aggressive => 6,
+            TimingTemplate::Insane => 2,
+            _ => 10,
+        }
+    }
+
+    // The slow templates are about not being noticed, so they send one probe at a time
+    pub(super) fn serial(self) -> bool {
+        matches!(
+            self,
+            TimingTemplate::Paranoid | TimingTemplate::Sneaky | TimingTemplate::Polite
+        )
+    }
+}
+
+impl fmt::Display for TimingTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            TimingTemplate::Paranoid => "paranoid",
+            TimingTemplate::Sneaky => "sneaky",
+            TimingTemplate::Polite => "polite",
+            TimingTemplate::Normal => "normal",
+            TimingTemplate::Aggressive => "aggressive",
+            TimingTemplate::Insane => "insane",
+        })
+    }
+}
+
+// Timing state for the scan of one host.
+pub(super) struct Timing {
+    template: TimingTemplate,
+    max_retries: u32,
+    // Smoothed round-trip time and its variation, as TCP keeps them (RFC 6298)
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    delay: Duration,
+    // Highest retransmission that got an answer; going more than one past it
+    // only slows the scan down
+    best_try: u32,
+    window: f64,
+    // Window size past which it grows linearly rather than by one per answer
+    threshold: f64,
+    last_drop: Option<Instant>,
+}
+
+impl Timing {
+    // `latency` is the round trip host discovery measured, if it got one.
+    pub fn new(
+        template: TimingTemplate,
+        max_retries: Option<u32>,
+        latency: Option<Duration>,
+    ) -> Self {
+        let mut timing = Self {
+            template,
+            max_retries: max_retries.unwrap_or(template.max_retries()),
+            srtt: None,
+            rttvar: Duration::ZERO,
+            delay: template.scan_delay(),
+            best_try: 0,
+            window: INITIAL_WINDOW,
+            threshold: INITIAL_THRESHOLD,
+            last_drop: None,
+        };
+        if let Some(latency) = latency {
+            timing.sample(latency);
+        }
+        timing
+    }
+
+    fn sample(&mut self, rtt: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                let difference = srtt.abs_diff(rtt);
+                self.rttvar = (self.rttvar * 3 + difference) / 4;
+                self.srtt = Some((srtt * 7 + rtt) / 8);
+            }
+        }
+    }
+
+    // How long to wait for the reply to a probe before resending it or giving up.
+    pub fn timeout(&self) -> Duration {
+        let (initial, min, max) = self.template.rtt_timeouts();
+        match self.srtt {
+            Some(srtt) => (srtt + self.rttvar * 4).clamp(min, max),
+            None => initial,
+        }
+    }
+
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    // How many probes may be awaiting an answer from the host.
+    pub fn window(&self) -> usize {
+        self.window as usize
+    }
+
+    // A probe was answered `rtt` after its latest copy went out, `tryno` being
+    // the number of retransmissions it took.
+    pub fn answered(&mut self, tryno: u32, rtt: Duration) {
+        if tryno == 0 {
+            self.sample(rtt);
+            self.grow();
+        } else {
+            // It's unclear which copy the reply belongs to, so there's no RTT
+            // sample, but the earlier copies were clearly lost: slow down
+            self.best_try = self.best_try.max(tryno);
+
+            // Losses show up in bunches, so back off once per round trip at most
+            let now = Instant::now();
+            if self.last_drop.is_none_or(|last| now - last > self.timeout()) {
+                self.delay = (self.delay * 2)
+                    .max(Duration::from_millis(1))
+                    .min(self.template.max_scan_delay());
+                self.threshold = (self.window / 2.0).max(2.0);
+                self.window = self.threshold;
+                self.last_drop = Some(now);
+            }
+        }
+    }
+
+    // A probe went unanswered. Filtered ports do just that, so only answers to
+    // retransmissions count as evidence of drops, and the window grows as if
+    // this had been answered.
+    pub fn timed_out(&mut self) {
+        self.grow();
+    }
+
+    fn grow(&mut self) {
+        self.window += if self.window < self.threshold { 1.0 } else { 1.0 / self.window };
+    }
+
+    // Whether a silent port is worth resending to, `tryno` being the retransmission
+    // that would be.
+    pub fn retry_allowed(&self, tryno: u32) -> bool {
+        tryno <= self.max_retries && tryno <= self.best_try + 1
+    }
+}
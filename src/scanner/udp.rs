@@ -0,0 +1,75 @@
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::udp::{MutableUdpPacket, UdpPacket};
+use pnet::packet::Packet;
+
+use super::engine::{Answer, Prober};
+use super::payloads;
+use super::raw::{Reply, Route, Unreachable};
+use super::result::{PortState, Reason};
+
+const UDP_HEADER_LEN: usize = 8;
+
+pub(super) struct UdpProbe {
+    route: Route,
+    source_port: u16,
+}
+
+impl UdpProbe {
+    pub fn new(route: Route) -> Self {
+        Self {
+            route,
+            source_port: rand::random::<u16>() % 16384 + 49152,
+        }
+    }
+}
+
+impl Prober for UdpProbe {
+    fn probe(&self, port: u16) -> Vec<u8> {
+        let payload = payloads::udp_payload(port);
+        let mut udp_buffer = vec![0u8; UDP_HEADER_LEN + payload.len()];
+        let mut udp_packet = MutableUdpPacket::new(&mut udp_buffer).unwrap();
+        udp_packet.set_source(self.source_port);
+        udp_packet.set_destination(port);
+        udp_packet.set_length((UDP_HEADER_LEN + payload.len()) as u16);
+        udp_packet.set_payload(payload);
+        let checksum = self.route.checksum(IpNextHeaderProtocols::Udp, udp_packet.packet(), 3);
+        udp_packet.set_checksum(checksum);
+
+        udp_buffer
+    }
+
+    // Any UDP answer from the probed port means something is listening.
+    fn classify(&self, reply: &Reply) -> Option<Answer> {
+        let udp = UdpPacket::new(reply.payload)?;
+        if udp.get_destination() != self.source_port {
+            return None;
+        }
+        Some(Answer {
+            port: udp.get_source(),
+            state: PortState::Open,
+            reason: Reason::UdpResponse,
+            teardown: None,
+        })
+    }
+
+    // Port unreachable means closed; the other unreachable codes are what
+    // filtering devices send back.
+    fn classify_unreachable(&self, unreachable: &Unreachable) -> Option<Answer> {
+        let (source_port, port) = unreachable.ports()?;
+        if source_port != self.source_port {
+            return None;
+        }
+
+        let state = if unreachable.port_unreachable {
+            PortState::Closed
+        } else {
+            PortState::Filtered
+        };
+        Some(Answer { port, state, reason: Reason::IcmpUnreach, teardown: None })
+    }
+
+    // Silence is ambiguous for UDP: the service may just ignore our probe
+    fn unanswered_state(&self) -> PortState {
+        PortState::OpenFiltered
+    }
+}
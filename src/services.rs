@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::OnceLock;
+
+use crate::scanner::Transport;
+
+// Service names for TCP, UDP and SCTP ports, and a ranking of how often each
+// port is found open. A port's name comes from the system's /etc/services if
+// it's listed there, then from the hand-picked subset of the IANA registry in
+// src/iana-services, then from the nmap-style names in src/port-services. That
+// file also ranks the ports for the top-N lists; its frequencies only set the
+// order and aren't survey figures. Names and aliases from all three can be
+// used to look ports up.
+
+const SYSTEM: &str = "/etc/services";
+
+// A hand-picked subset of the IANA registry, in the /etc/services format
+const IANA: &str = include_str!("iana-services");
+
+// Port names and a made-up ranking, in the nmap-services format
+const FREQUENCIES: &str = include_str!("port-services");
+
+static SERVICES: OnceLock<Services> = OnceLock::new();
+
+#[derive(Default)]
+struct Services {
+    names: HashMap<(Transport, u16), String>,
+    // Every name and alias, in lower case, with the ports that go by it
+    ports: HashMap<String, Vec<(Transport, u16)>>,
+    frequencies: HashMap<(Transport, u16), f64>,
+}
+
+impl Services {
+    fn load() -> Self {
+        let mut services = Services::default();
+        match fs::read_to_string(SYSTEM) {
+            Ok(contents) => services.parse(&contents, false),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("Error reading {}: {}", SYSTEM, e),
+        }
+        services.parse(IANA, false);
+        services.parse(FREQUENCIES, true);
+        services
+    }
+
+    // "name port/protocol" followed by aliases in /etc/services, or by the open
+    // frequency in nmap-services. Names already known for a port are kept.
+    fn parse(&mut self, contents: &str, frequencies: bool) {
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let (Some(name), Some(port)) = (fields.next(), fields.next()) else { continue };
+            let Some(key) = port_and_protocol(port) else { continue };
+
+            let mut names = Vec::new();
+            if frequencies {
+                if let Some(frequency) = fields.next().and_then(|field| field.parse().ok()) {
+                    self.frequencies.insert(key, frequency);
+                }
+            } else {
+                names.extend(fields);
+            }
+            // nmap-services calls ports it has no name for "unknown"
+            if name != "unknown" {
+                self.names.entry(key).or_insert_with(|| name.to_string());
+                names.push(name);
+            }
+            for name in names {
+                let ports = self.ports.entry(name.to_ascii_lowercase()).or_default();
+                if !ports.contains(&key) {
+                    ports.push(key);
+                }
+            }
+        }
+    }
+}
+
+fn services() -> &'static Services {
+    SERVICES.get_or_init(Services::load)
+}
+
+fn port_and_protocol(field: &str) -> Option<(Transport, u16)> {
+    let (port, protocol) = field.split_once('/')?;
+    let protocol = match protocol {
+        "tcp" => Transport::Tcp,
+        "udp" => Transport::Udp,
+        "sctp" => Transport::Sctp,
+        _ => return None,
+    };
+    Some((protocol, port.parse().ok()?))
+}
+
+pub fn name(port: u16, protocol: Transport) -> Option<&'static str> {
+    services().names.get(&(protocol, port)).map(String::as_str)
+}
+
+// Every port a service name or alias is registered on, for any protocol.
+pub fn ports(name: &str) -> &'static [(Transport, u16)] {
+    services()
+        .ports
+        .get(&name.to_ascii_lowercase())
+        .map_or(&[], Vec::as_slice)
+}
+
+// The port a name stands for on one protocol, the most commonly open one if
+// there are several.
+pub fn port(name: &str, protocol: Transport) -> Option<u16> {
+    ports(name)
+        .iter()
+        .filter(|&&(transport, _)| transport == protocol)
+        .max_by(|a, b| frequency(a.1, protocol).total_cmp(&frequency(b.1, protocol)).then(b.1.cmp(&a.1)))
+        .map(|&(_, port)| port)
+}
+
+// Where the port ranks, from 0 to 1. Only the order means anything.
+pub fn frequency(port: u16, protocol: Transport) -> f64 {
+    services().frequencies.get(&(protocol, port)).copied().unwrap_or(0.0)
+}
+
+// The `count` ports most often found open, most common first.
+pub fn top(protocol: Transport, count: usize) -> Vec<u16> {
+    let mut ranked: Vec<(u16, f64)> = services()
+        .frequencies
+        .iter()
+        .filter(|&(&(transport, _), _)| transport == protocol)
+        .map(|(&(_, port), &frequency)| (port, frequency))
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+    ranked.into_iter().take(count).map(|(port, _)| port).collect()
+}
+
+// e.g. "443 (https)", or just the number for ports without a name
+pub fn describe(port: u16, protocol: Transport) -> String {
+    match name(port, protocol) {
+        Some(name) => format!("{} ({})", port, name),
+        None => port.to_string(),
+    }
+}
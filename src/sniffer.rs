@@ -1,20 +1,31 @@
+use pnet::packet::arp::{ArpOperations, ArpPacket};
 use pnet::packet::ethernet::{EthernetPacket, EtherTypes};
-use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::packet::ip::{IpNextHeaderProtocol, IpNextHeaderProtocols};
 use pnet::packet::ipv4::Ipv4Packet;
 use pnet::packet::ipv6::Ipv6Packet;
+use pnet::packet::ipv6::{ExtensionPacket, FragmentPacket};
+use pnet::packet::icmpv6::{Icmpv6Packet, Icmpv6Types};
 use pnet::packet::tcp::TcpPacket;
 use pnet::packet::udp::UdpPacket;
 use pnet::packet::icmp::{IcmpPacket, IcmpTypes};
 use pnet::packet::Packet;
+use std::net::Ipv6Addr;
 use std::time::SystemTime;
 use crossbeam_channel::Sender;
-use std::fmt;
+
+use crate::oui;
+use crate::scanner::Transport;
+use crate::services;
+use crate::utils::crc32c;
 
 #[derive(Debug)]
 pub struct PacketInfo {
     pub timestamp: String,
     pub source: String,
     pub destination: String,
+    // MAC addresses with the vendor, where the registry knows it
+    pub source_mac: String,
+    pub destination_mac: String,
     pub protocol: String,
     pub length: usize,
     pub info: String,
@@ -26,6 +37,8 @@ impl PacketInfo {
             timestamp: String::new(),
             source: String::new(),
             destination: String::new(),
+            source_mac: String::new(),
+            destination_mac: String::new(),
             protocol: String::new(),
             length: 0,
             info: String::new(),
@@ -35,20 +48,16 @@ impl PacketInfo {
 
 pub struct PacketSniffer {
     interface: pnet_datalink::NetworkInterface,
-    filter: String,
 }
 
 impl PacketSniffer {
-    pub fn new(interface_name: &str, filter: &str) -> Self {
+    pub fn new(interface_name: &str) -> Self {
         let interface = pnet_datalink::interfaces()
             .into_iter()
             .find(|iface| iface.name == interface_name)
             .expect("Interface not found");
         
-        Self {
-            interface,
-            filter: filter.to_string(),
-        }
+        Self { interface }
     }
     
     pub fn start(&mut self, tx: Sender<PacketInfo>) {
@@ -61,7 +70,7 @@ impl PacketSniffer {
         loop {
             match rx.next() {
                 Ok(packet) => {
-                    if let Some(packet_info) = self.process_packet(&packet) {
+                    if let Some(packet_info) = self.process_packet(packet) {
                         tx.send(packet_info).unwrap();
                     }
                 }
@@ -79,6 +88,8 @@ impl PacketSniffer {
         packet_info.length = packet.len();
         
         let ethernet = EthernetPacket::new(packet)?;
+        packet_info.source_mac = oui::describe(ethernet.get_source());
+        packet_info.destination_mac = oui::describe(ethernet.get_destination());
         
         match ethernet.get_ethertype() {
             EtherTypes::Ipv4 => {
@@ -86,58 +97,89 @@ impl PacketSniffer {
                 packet_info.source = ipv4.get_source().to_string();
                 packet_info.destination = ipv4.get_destination().to_string();
                 
-                match ipv4.get_next_level_protocol() {
-                    IpNextHeaderProtocols::Tcp => {
-                        packet_info.protocol = "TCP".to_string();
-                        if let Some(tcp) = TcpPacket::new(ipv4.payload()) {
-                            packet_info.info = format!(
-                                "{} → {} [{}] Seq={} Ack={} Win={} Len={}",
-                                tcp.get_source(),
-                                tcp.get_destination(),
-                                self.tcp_flags_to_str(tcp.get_flags()),
-                                tcp.get_sequence(),
-                                tcp.get_acknowledgement(),
-                                tcp.get_window(),
-                                tcp.payload().len()
-                            );
-                        }
-                    }
-                    IpNextHeaderProtocols::Udp => {
-                        packet_info.protocol = "UDP".to_string();
-                        if let Some(udp) = UdpPacket::new(ipv4.payload()) {
-                            packet_info.info = format!(
-                                "{} → {} Len={}",
-                                udp.get_source(),
-                                udp.get_destination(),
-                                udp.get_length()
-                            );
-                        }
-                    }
-                    IpNextHeaderProtocols::Icmp => {
-                        packet_info.protocol = "ICMP".to_string();
-                        if let Some(icmp) = IcmpPacket::new(ipv4.payload()) {
-                            let icmp_type = match icmp.get_icmp_type() {
-                                IcmpTypes::EchoReply => "Echo Reply",
-                                IcmpTypes::EchoRequest => "Echo Request",
-                                IcmpTypes::DestinationUnreachable => "Destination Unreachable",
-                                _ => "Other ICMP",
-                            };
-                            packet_info.info = icmp_type.to_string();
-                        }
-                    }
-                    _ => {
-                        packet_info.protocol = format!("IPv4 Protocol {}", ipv4.get_next_level_protocol());
-                    }
-                }
+                self.process_transport(
+                    "IPv4",
+                    ipv4.get_next_level_protocol(),
+                    ipv4.payload(),
+                    &mut packet_info,
+                );
             }
             EtherTypes::Ipv6 => {
                 let ipv6 = Ipv6Packet::new(ethernet.payload())?;
                 packet_info.source = ipv6.get_source().to_string();
                 packet_info.destination = ipv6.get_destination().to_string();
-                packet_info.protocol = "IPv6".to_string();
+                
+                // Walk the extension header chain to reach the upper-layer header
+                let mut next_header = ipv6.get_next_header();
+                let mut payload = ipv6.payload();
+                let mut extensions = Vec::new();
+                loop {
+                    match next_header {
+                        IpNextHeaderProtocols::Hopopt
+                        | IpNextHeaderProtocols::Ipv6Route
+                        | IpNextHeaderProtocols::Ipv6Opts => {
+                            let Some(extension) = ExtensionPacket::new(payload) else { break };
+                            let length = (extension.get_hdr_ext_len() as usize + 1) * 8;
+                            extensions.push(match next_header {
+                                IpNextHeaderProtocols::Hopopt => "Hop-by-Hop",
+                                IpNextHeaderProtocols::Ipv6Route => "Routing",
+                                _ => "Destination Options",
+                            });
+                            next_header = extension.get_next_header();
+                            payload = payload.get(length..).unwrap_or_default();
+                        }
+                        IpNextHeaderProtocols::Ipv6Frag => {
+                            let Some(fragment) = FragmentPacket::new(payload) else { break };
+                            next_header = fragment.get_next_header();
+                            // The low three bits are flags, the rest is the offset in bytes
+                            let offset = fragment.get_fragment_offset() & !0x7;
+                            payload = payload.get(8..).unwrap_or_default();
+                            // Only the first fragment carries the upper-layer header
+                            if offset != 0 {
+                                packet_info.protocol = "IPv6".to_string();
+                                packet_info.info = format!(
+                                    "Fragment offset={} id={:#x} next={}",
+                                    offset,
+                                    fragment.get_id(),
+                                    next_header
+                                );
+                                return Some(packet_info);
+                            }
+                            extensions.push("Fragment");
+                        }
+                        IpNextHeaderProtocols::Ah => {
+                            let Some(&length) = payload.get(1) else { break };
+                            next_header = IpNextHeaderProtocol(payload[0]);
+                            payload = payload.get((length as usize + 2) * 4..).unwrap_or_default();
+                            extensions.push("Authentication");
+                        }
+                        _ => break,
+                    }
+                }
+                
+                self.process_transport("IPv6", next_header, payload, &mut packet_info);
+                if !extensions.is_empty() {
+                    packet_info.info = format!("{} [{}]", packet_info.info, extensions.join(", "));
+                }
             }
             EtherTypes::Arp => {
                 packet_info.protocol = "ARP".to_string();
+                let arp = ArpPacket::new(ethernet.payload())?;
+                packet_info.source = arp.get_sender_proto_addr().to_string();
+                packet_info.destination = arp.get_target_proto_addr().to_string();
+                packet_info.info = match arp.get_operation() {
+                    ArpOperations::Request => format!(
+                        "Who has {}? Tell {}",
+                        arp.get_target_proto_addr(),
+                        arp.get_sender_proto_addr()
+                    ),
+                    ArpOperations::Reply => format!(
+                        "{} is at {}",
+                        arp.get_sender_proto_addr(),
+                        oui::describe(arp.get_sender_hw_addr())
+                    ),
+                    operation => format!("Operation {}", operation.0),
+                };
             }
             _ => {
                 packet_info.protocol = format!("EtherType {}", ethernet.get_ethertype());
@@ -147,6 +189,197 @@ impl PacketSniffer {
         Some(packet_info)
     }
     
+    // Fill in the protocol and info columns from an IPv4 or IPv6 payload.
+    fn process_transport(
+        &self,
+        ip_version: &str,
+        protocol: IpNextHeaderProtocol,
+        payload: &[u8],
+        packet_info: &mut PacketInfo,
+    ) {
+        match protocol {
+            IpNextHeaderProtocols::Tcp => {
+                packet_info.protocol = "TCP".to_string();
+                if let Some(tcp) = TcpPacket::new(payload) {
+                    packet_info.info = format!(
+                        "{} → {} [{}] Seq={} Ack={} Win={} Len={}",
+                        services::describe(tcp.get_source(), Transport::Tcp),
+                        services::describe(tcp.get_destination(), Transport::Tcp),
+                        self.tcp_flags_to_str(tcp.get_flags()),
+                        tcp.get_sequence(),
+                        tcp.get_acknowledgement(),
+                        tcp.get_window(),
+                        tcp.payload().len()
+                    );
+                }
+            }
+            IpNextHeaderProtocols::Udp => {
+                packet_info.protocol = "UDP".to_string();
+                if let Some(udp) = UdpPacket::new(payload) {
+                    packet_info.info = format!(
+                        "{} → {} Len={}",
+                        services::describe(udp.get_source(), Transport::Udp),
+                        services::describe(udp.get_destination(), Transport::Udp),
+                        udp.get_length()
+                    );
+                }
+            }
+            IpNextHeaderProtocols::Sctp => {
+                packet_info.protocol = "SCTP".to_string();
+                if let Some(info) = self.sctp_info(payload) {
+                    packet_info.info = info;
+                }
+            }
+            IpNextHeaderProtocols::Icmp => {
+                packet_info.protocol = "ICMP".to_string();
+                if let Some(icmp) = IcmpPacket::new(payload) {
+                    let icmp_type = match icmp.get_icmp_type() {
+                        IcmpTypes::EchoReply => "Echo Reply",
+                        IcmpTypes::EchoRequest => "Echo Request",
+                        IcmpTypes::DestinationUnreachable => "Destination Unreachable",
+                        _ => "Other ICMP",
+                    };
+                    packet_info.info = icmp_type.to_string();
+                }
+            }
+            IpNextHeaderProtocols::Icmpv6 => {
+                packet_info.protocol = "ICMPv6".to_string();
+                if let Some(icmp) = Icmpv6Packet::new(payload) {
+                    packet_info.info = self.icmpv6_info(&icmp);
+                }
+            }
+            IpNextHeaderProtocols::Ipv6NoNxt => {
+                packet_info.protocol = ip_version.to_string();
+                packet_info.info = "No Next Header".to_string();
+            }
+            _ => {
+                packet_info.protocol = format!("{} Protocol {}", ip_version, protocol);
+            }
+        }
+    }
+    
+    fn icmpv6_info(&self, icmp: &Icmpv6Packet) -> String {
+        let code = icmp.get_icmpv6_code().0;
+        // Echo identifier/sequence, or the target address of neighbor discovery
+        let body = icmp.payload();
+        let echo = || {
+            let field = |i: usize| body.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+            format!("id={} seq={}", field(0).unwrap_or(0), field(2).unwrap_or(0))
+        };
+        let target = || {
+            body.get(4..20)
+                .and_then(|b| <[u8; 16]>::try_from(b).ok())
+                .map(|b| Ipv6Addr::from(b).to_string())
+                .unwrap_or_default()
+        };
+        
+        match icmp.get_icmpv6_type() {
+            Icmpv6Types::DestinationUnreachable => {
+                let reason = match code {
+                    0 => "No Route",
+                    1 => "Administratively Prohibited",
+                    2 => "Beyond Scope of Source",
+                    3 => "Address Unreachable",
+                    4 => "Port Unreachable",
+                    5 => "Source Address Failed Policy",
+                    6 => "Reject Route",
+                    _ => "Unknown Code",
+                };
+                format!("Destination Unreachable ({})", reason)
+            }
+            Icmpv6Types::PacketTooBig => {
+                let mtu = body.get(..4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+                format!("Packet Too Big MTU={}", mtu.unwrap_or(0))
+            }
+            Icmpv6Types::TimeExceeded => match code {
+                0 => "Time Exceeded (Hop Limit)".to_string(),
+                _ => "Time Exceeded (Fragment Reassembly)".to_string(),
+            },
+            Icmpv6Types::ParameterProblem => "Parameter Problem".to_string(),
+            Icmpv6Types::EchoRequest => format!("Echo Request {}", echo()),
+            Icmpv6Types::EchoReply => format!("Echo Reply {}", echo()),
+            Icmpv6Types::RouterSolicit => "Router Solicitation".to_string(),
+            Icmpv6Types::RouterAdvert => "Router Advertisement".to_string(),
+            Icmpv6Types::NeighborSolicit => format!("Neighbor Solicitation for {}", target()),
+            Icmpv6Types::NeighborAdvert => format!("Neighbor Advertisement {}", target()),
+            Icmpv6Types::Redirect => "Redirect".to_string(),
+            icmp_type => match icmp_type.0 {
+                130 => "Multicast Listener Query".to_string(),
+                131 | 143 => "Multicast Listener Report".to_string(),
+                132 => "Multicast Listener Done".to_string(),
+                _ => format!("ICMPv6 Type {} Code {}", icmp_type.0, code),
+            },
+        }
+    }
+    
+    // Ports, verification tag and a summary of every chunk in the packet.
+    fn sctp_info(&self, packet: &[u8]) -> Option<String> {
+        let word = |i: usize| {
+            packet.get(i..i + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        };
+        let short = |i: usize| packet.get(i..i + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+        let header = packet.get(..12)?;
+        
+        // The checksum covers the whole packet with its own field zeroed
+        let mut zeroed = packet.to_vec();
+        zeroed[8..12].fill(0);
+        let checksum_ok = crc32c(&zeroed).to_le_bytes() == header[8..12];
+        
+        let mut chunks = Vec::new();
+        let mut offset = 12;
+        while let Some(&[kind, flags, high, low]) = packet.get(offset..offset + 4) {
+            let length = u16::from_be_bytes([high, low]) as usize;
+            let value = offset + 4;
+            chunks.push(match kind {
+                0 => format!(
+                    "DATA TSN={} SID={} Len={}",
+                    word(value).unwrap_or(0),
+                    short(value + 4).unwrap_or(0),
+                    length.saturating_sub(16)
+                ),
+                1 | 2 => format!(
+                    "{} Tag={:#010x} TSN={}",
+                    if kind == 1 { "INIT" } else { "INIT_ACK" },
+                    word(value).unwrap_or(0),
+                    word(value + 12).unwrap_or(0)
+                ),
+                3 => format!("SACK Cum={}", word(value).unwrap_or(0)),
+                4 => "HEARTBEAT".to_string(),
+                5 => "HEARTBEAT_ACK".to_string(),
+                // The T bit says the tag was reflected rather than the peer's own
+                6 => format!("ABORT{}", if flags & 1 != 0 { " (T)" } else { "" }),
+                7 => format!("SHUTDOWN Cum={}", word(value).unwrap_or(0)),
+                8 => "SHUTDOWN_ACK".to_string(),
+                9 => "ERROR".to_string(),
+                10 => "COOKIE_ECHO".to_string(),
+                11 => "COOKIE_ACK".to_string(),
+                14 => "SHUTDOWN_COMPLETE".to_string(),
+                15 => "AUTH".to_string(),
+                64 => "I_DATA".to_string(),
+                128 => "ASCONF_ACK".to_string(),
+                130 => "RE_CONFIG".to_string(),
+                132 => "PAD".to_string(),
+                192 => "FORWARD_TSN".to_string(),
+                193 => "ASCONF".to_string(),
+                _ => format!("Chunk {}", kind),
+            });
+            // Lengths exclude the padding to a multiple of 4
+            if length < 4 {
+                break;
+            }
+            offset += length.next_multiple_of(4);
+        }
+        
+        Some(format!(
+            "{} → {} Tag={:#010x} [{}]{}",
+            services::describe(short(0)?, Transport::Sctp),
+            services::describe(short(2)?, Transport::Sctp),
+            word(4)?,
+            chunks.join(", "),
+            if checksum_ok { "" } else { " [bad checksum]" }
+        ))
+    }
+    
     fn tcp_flags_to_str(&self, flags: u16) -> String {
         let mut flag_str = String::new();
         if flags & 0x01 != 0 { flag_str.push('F'); } // FIN
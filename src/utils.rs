@@ -1,27 +1,5 @@
 use std::net::Ipv4Addr;
 
-pub fn calculate_checksum(data: &[u8]) -> u16 {
-    let mut sum = 0u32;
-    let mut i = 0;
-    let len = data.len();
-    
-    while i < len {
-        let word = if i + 1 < len {
-            ((data[i] as u16) << 8) | data[i + 1] as u16
-        } else {
-            (data[i] as u16) << 8
-        };
-        sum += word as u32;
-        i += 2;
-    }
-    
-    while sum >> 16 != 0 {
-        sum = (sum & 0xffff) + (sum >> 16);
-    }
-    
-    !(sum as u16)
-}
-
 pub fn ipv4_to_u32(ip: Ipv4Addr) -> u32 {
     let octets = ip.octets();
     ((octets[0] as u32) << 24) |
@@ -29,3 +7,28 @@ pub fn ipv4_to_u32(ip: Ipv4Addr) -> u32 {
     ((octets[2] as u32) << 8)  |
     (octets[3] as u32)
 }
+
+// CRC32c (Castagnoli), the checksum SCTP uses in place of the Internet checksum.
+pub fn crc32c(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
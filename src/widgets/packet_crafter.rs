@@ -1,6 +1,12 @@
 use eframe::egui;
 use crate::crafter::PacketCrafter as NativeCrafter;
-use std::net::Ipv4Addr;
+use crate::oui;
+use crate::scanner::Transport;
+use crate::services;
+use std::net::IpAddr;
+use std::thread;
+use std::time::Duration;
+use crossbeam_channel::{unbounded, Receiver, TryRecvError};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Protocol {
@@ -13,13 +19,16 @@ pub enum Protocol {
 pub struct PacketCrafter {
     source_ip: String,
     dest_ip: String,
-    source_port: u16,
-    dest_port: u16,
+    // Numbers or service names, e.g. "80" or "http"
+    source_port: String,
+    dest_port: String,
     protocol: Protocol,
     payload: String,
     count: u32,
     delay: u32,
     results: Vec<String>,
+    // Progress and errors from the thread doing the sending
+    receiver: Option<Receiver<String>>,
     crafting: bool,
 }
 
@@ -28,13 +37,14 @@ impl Default for PacketCrafter {
         Self {
             source_ip: "192.168.1.100".to_string(),
             dest_ip: "192.168.1.1".to_string(),
-            source_port: 54321,
-            dest_port: 80,
+            source_port: "54321".to_string(),
+            dest_port: "80".to_string(),
             protocol: Protocol::Tcp,
             payload: "DeepNet Packet".to_string(),
             count: 5,
             delay: 100,
             results: Vec::new(),
+            receiver: None,
             crafting: false,
         }
     }
@@ -57,11 +67,11 @@ impl PacketCrafter {
                 ui.end_row();
                 
                 ui.label("Source Port:");
-                ui.add(egui::DragValue::new(&mut self.source_port).clamp_range(1..=65535));
+                ui.add(egui::TextEdit::singleline(&mut self.source_port).hint_text("54321"));
                 ui.end_row();
                 
                 ui.label("Destination Port:");
-                ui.add(egui::DragValue::new(&mut self.dest_port).clamp_range(1..=65535));
+                ui.add(egui::TextEdit::singleline(&mut self.dest_port).hint_text("80 or http"));
                 ui.end_row();
                 
                 ui.label("Protocol:");
@@ -94,6 +104,8 @@ impl PacketCrafter {
             self.start_crafting();
         }
         
+        self.poll_results();
+        
         ui.separator();
         
         egui::ScrollArea::vertical().show(ui, |ui| {
@@ -107,37 +119,97 @@ impl PacketCrafter {
         self.crafting = true;
         self.results.clear();
         
-        let source_ip = self.source_ip.parse::<Ipv4Addr>().unwrap_or_else(|_| {
-            Ipv4Addr::new(192, 168, 1, 100)
-        });
+        let (source_ip, dest_ip) = match (
+            self.source_ip.trim().parse::<IpAddr>(),
+            self.dest_ip.trim().parse::<IpAddr>(),
+        ) {
+            (Ok(source_ip), Ok(dest_ip)) => (source_ip, dest_ip),
+            (Err(_), _) => return self.fail(format!("Invalid source IP \"{}\"", self.source_ip)),
+            (_, Err(_)) => return self.fail(format!("Invalid destination IP \"{}\"", self.dest_ip)),
+        };
         
-        let dest_ip = self.dest_ip.parse::<Ipv4Addr>().unwrap_or_else(|_| {
-            Ipv4Addr::new(192, 168, 1, 1)
-        });
+        let Some(source_port) = self.port(&self.source_port) else {
+            return self.fail(format!("Invalid source port \"{}\"", self.source_port));
+        };
+        let Some(dest_port) = self.port(&self.dest_port) else {
+            return self.fail(format!("Invalid destination port \"{}\"", self.dest_port));
+        };
         
         let mut crafter = NativeCrafter::new(
             source_ip,
             dest_ip,
-            self.source_port,
-            self.dest_port,
+            source_port,
+            dest_port,
             self.protocol.clone(),
             self.payload.clone(),
             self.count,
-            self.delay,
         );
         
-        crafter.craft_and_send();
+        let (tx, rx) = unbounded();
+        self.receiver = Some(rx);
+        let destination = format!("{}:{}", self.dest_ip.trim(), services::describe(dest_port, self.transport()));
+        let protocol = self.protocol.clone();
+        let (count, delay) = (self.count, Duration::from_millis(self.delay as u64));
         
-        for i in 0..self.count {
-            self.results.push(format!(
-                "Sent {} packet to {}:{} - Protocol: {:?}",
-                i + 1,
-                self.dest_ip,
-                self.dest_port,
-                self.protocol
-            ));
+        thread::spawn(move || {
+            let interface = match crafter.pick_interface() {
+                Ok(interface) => interface,
+                Err(e) => {
+                    let _ = tx.send(format!("Error: {}", e));
+                    return;
+                }
+            };
+            let _ = tx.send(match interface.mac {
+                Some(mac) => format!("Sending from {} ({})", interface.name, oui::describe(mac)),
+                None => format!("Sending from {}", interface.name),
+            });
+            
+            let result = crafter.craft_and_send(|sent| {
+                let _ = tx.send(format!("Sent {} packet to {} - Protocol: {:?}", sent, destination, protocol));
+                if sent < count {
+                    thread::sleep(delay);
+                }
+            });
+            if let Err(e) = result {
+                let _ = tx.send(format!("Error: {}", e));
+            }
+        });
+    }
+    
+    fn poll_results(&mut self) {
+        let Some(receiver) = &self.receiver else { return };
+        loop {
+            match receiver.try_recv() {
+                Ok(result) => self.results.push(result),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.receiver = None;
+                    self.crafting = false;
+                    break;
+                }
+            }
         }
-        
+    }
+    
+    // A port number, or a service name looked up for the chosen protocol
+    fn port(&self, input: &str) -> Option<u16> {
+        let input = input.trim();
+        match input.parse::<u16>() {
+            Ok(port) => Some(port).filter(|&port| port > 0),
+            Err(_) => services::port(input, self.transport()),
+        }
+    }
+    
+    // Names are looked up as TCP ports unless the packets are UDP
+    fn transport(&self) -> Transport {
+        match self.protocol {
+            Protocol::Udp => Transport::Udp,
+            _ => Transport::Tcp,
+        }
+    }
+    
+    fn fail(&mut self, message: String) {
+        self.results.push(message);
         self.crafting = false;
     }
 }
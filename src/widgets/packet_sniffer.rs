@@ -7,7 +7,6 @@ use crossbeam_channel::{bounded, Receiver};
 pub struct PacketSniffer {
     interface: String,
     interfaces: Vec<String>,
-    filter: String,
     results: Arc<Mutex<Vec<PacketInfo>>>,
     receiver: Option<Receiver<PacketInfo>>,
     sniffing: bool,
@@ -27,7 +26,6 @@ impl Default for PacketSniffer {
         Self {
             interface,
             interfaces,
-            filter: "".to_string(),
             results: Arc::new(Mutex::new(Vec::new())),
             receiver: None,
             sniffing: false,
@@ -53,10 +51,6 @@ impl PacketSniffer {
                         }
                     });
                 ui.end_row();
-                
-                ui.label("Filter (BPF):");
-                ui.text_edit_singleline(&mut self.filter);
-                ui.end_row();
             });
         
         ui.separator();
@@ -79,12 +73,13 @@ impl PacketSniffer {
             let results = self.results.lock().unwrap();
             
             egui::Grid::new("packet_grid")
-                .num_columns(6)
+                .num_columns(7)
                 .striped(true)
                 .show(ui, |ui| {
                     ui.strong("Time");
                     ui.strong("Source");
                     ui.strong("Destination");
+                    ui.strong("Ethernet");
                     ui.strong("Protocol");
                     ui.strong("Length");
                     ui.strong("Info");
@@ -94,6 +89,7 @@ impl PacketSniffer {
                         ui.label(&packet.timestamp);
                         ui.label(&packet.source);
                         ui.label(&packet.destination);
+                        ui.label(format!("{} → {}", packet.source_mac, packet.destination_mac));
                         ui.label(&packet.protocol);
                         ui.label(packet.length.to_string());
                         ui.label(&packet.info);
@@ -112,11 +108,9 @@ impl PacketSniffer {
         self.receiver = Some(rx);
         
         let interface = self.interface.clone();
-        let filter = self.filter.clone();
-        let results = self.results.clone();
         
         thread::spawn(move || {
-            let mut sniffer = NativeSniffer::new(&interface, &filter);
+            let mut sniffer = NativeSniffer::new(&interface);
             sniffer.start(tx);
         });
     }
@@ -1,18 +1,89 @@
 use eframe::egui;
-use crate::scanner::{PortScanner as NativeScanner, ScanType};
-use std::sync::{Arc, Mutex};
-use std::thread;
-use crossbeam_channel::{bounded, Receiver};
+use crate::scanner::{
+    AddressChoice, Alerts, CancelToken, Checkpoint, DiffFormat, DiscoveryConfig, ExportFormat, HostResult, PortResult,
+    PortScanner as NativeScanner, PortState, Progress, ScanConfig, ScanDiff, ScanError, ScanEvent,
+    ScanReport, ScanType, TimingTemplate,
+};
+use crate::ratelimit;
+use std::cmp::Ordering;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime};
+use crossbeam_channel::{unbounded, Receiver, TryRecvError};
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum SortColumn {
+    Port,
+    Protocol,
+    State,
+    Service,
+    Product,
+    Version,
+    Reason,
+    Ttl,
+    Rtt,
+}
 
 pub struct PortScanner {
     target: String,
-    port_range: (u16, u16),
+    target_file: String,
+    exclude: String,
+    dns_server: String,
+    address_choice: AddressChoice,
+    reverse_dns: bool,
+    skip_discovery: bool,
+    arp_ping: bool,
+    echo_ping: bool,
+    timestamp_ping: bool,
+    syn_ping_ports: String,
+    ack_ping_ports: String,
+    ports: String,
+    randomize_ports: bool,
     scan_type: ScanType,
-    threads: usize,
-    results: Arc<Mutex<Vec<(u16, String)>>>,
-    progress: f32,
+    max_in_flight: usize,
+    max_hosts: usize,
+    timing: TimingTemplate,
+    override_retries: bool,
+    max_retries: u32,
+    service_detection: bool,
+    service_probes: String,
+    os_detection: bool,
+    connect_timeout_ms: u64,
+    max_connections: usize,
+    checkpoint: String,
+    repeat: bool,
+    repeat_hours: f64,
+    alert_log: bool,
+    alert_desktop: bool,
+    webhook: String,
+    // When the next run of a repeating scan is due, the configuration saved
+    // from its first run for every run to repeat, and the last run's results
+    // to compare it with
+    next_run: Option<Instant>,
+    repeat_config: Option<ScanConfig>,
+    baseline: Option<ScanReport>,
+    report: ScanReport,
+    export_format: ExportFormat,
+    export_path: String,
+    diff_before: String,
+    diff_after: String,
+    diff: Option<ScanDiff>,
+    // The diff as text, kept rather than rendered again every frame
+    diff_text: String,
+    diff_format: DiffFormat,
+    diff_path: String,
+    selected_host: Option<IpAddr>,
+    show_down: bool,
+    sort_column: SortColumn,
+    sort_ascending: bool,
+    state_filter: Option<PortState>,
+    text_filter: String,
+    progress: Option<Progress>,
     status: String,
-    receiver: Option<Receiver<(u16, String)>>,
+    receiver: Option<Receiver<ScanEvent>>,
+    handle: Option<JoinHandle<Result<(), ScanError>>>,
+    cancel: CancelToken,
     scanning: bool,
 }
 
@@ -20,19 +91,79 @@ impl Default for PortScanner {
     fn default() -> Self {
         Self {
             target: "127.0.0.1".to_string(),
-            port_range: (1, 1024),
+            target_file: String::new(),
+            exclude: String::new(),
+            dns_server: String::new(),
+            address_choice: AddressChoice::FirstIpv4,
+            reverse_dns: false,
+            skip_discovery: false,
+            arp_ping: true,
+            echo_ping: true,
+            timestamp_ping: true,
+            syn_ping_ports: "443".to_string(),
+            ack_ping_ports: "80".to_string(),
+            ports: "1-1024".to_string(),
+            randomize_ports: false,
             scan_type: ScanType::TcpSyn,
-            threads: 100,
-            results: Arc::new(Mutex::new(Vec::new())),
-            progress: 0.0,
+            max_in_flight: 1000,
+            max_hosts: 64,
+            timing: TimingTemplate::Normal,
+            override_retries: false,
+            max_retries: TimingTemplate::Normal.max_retries(),
+            service_detection: false,
+            service_probes: String::new(),
+            os_detection: false,
+            connect_timeout_ms: 1000,
+            max_connections: 256,
+            checkpoint: String::new(),
+            repeat: false,
+            repeat_hours: 24.0,
+            alert_log: true,
+            alert_desktop: false,
+            webhook: String::new(),
+            next_run: None,
+            repeat_config: None,
+            baseline: None,
+            report: ScanReport::new(&ScanConfig::default()),
+            export_format: ExportFormat::Xml,
+            export_path: String::new(),
+            diff_before: String::new(),
+            diff_after: String::new(),
+            diff: None,
+            diff_text: String::new(),
+            diff_format: DiffFormat::Text,
+            diff_path: String::new(),
+            selected_host: None,
+            show_down: false,
+            sort_column: SortColumn::Port,
+            sort_ascending: true,
+            state_filter: Some(PortState::Open),
+            text_filter: String::new(),
+            progress: None,
             status: "Ready".to_string(),
             receiver: None,
+            handle: None,
+            cancel: CancelToken::new(),
             scanning: false,
         }
     }
 }
 
 impl PortScanner {
+    // Called every frame, whichever tab is showing, so that results keep coming
+    // in and repeating scans start on time.
+    pub fn background(&mut self) {
+        self.poll_results();
+        if let Some(next_run) = self.next_run
+            && !self.scanning
+            && Instant::now() >= next_run
+            && let Some(config) = self.repeat_config.clone()
+        {
+            self.next_run = None;
+            self.launch(&config.clone(), move || NativeScanner::new(&config));
+        }
+    }
+    
     pub fn ui(&mut self, ui: &mut egui::Ui) {
         ui.heading("Port Scanner");
         
@@ -41,14 +172,69 @@ impl PortScanner {
             .spacing([20.0, 10.0])
             .show(ui, |ui| {
                 ui.label("Target:");
-                ui.text_edit_singleline(&mut self.target);
+                ui.text_edit_singleline(&mut self.target)
+                    .on_hover_text("Addresses, CIDR blocks, ranges or hostnames, e.g. 192.168.1.0/24, 10.0.0.1-20, example.com");
+                ui.end_row();
+                
+                ui.label("Target File:");
+                ui.text_edit_singleline(&mut self.target_file);
+                ui.end_row();
+                
+                ui.label("Exclude:");
+                ui.text_edit_singleline(&mut self.exclude);
+                ui.end_row();
+                
+                ui.label("DNS Server:");
+                ui.add(egui::TextEdit::singleline(&mut self.dns_server).hint_text("system resolver"));
+                ui.end_row();
+                
+                ui.label("Addresses:");
+                egui::ComboBox::from_id_source("address_choice")
+                    .selected_text(self.address_choice.to_string())
+                    .show_ui(ui, |ui| {
+                        for choice in [
+                            AddressChoice::FirstIpv4,
+                            AddressChoice::FirstIpv6,
+                            AddressChoice::AllIpv4,
+                            AddressChoice::AllIpv6,
+                            AddressChoice::All,
+                        ] {
+                            ui.selectable_value(&mut self.address_choice, choice, choice.to_string());
+                        }
+                    });
                 ui.end_row();
                 
-                ui.label("Port Range:");
+                ui.label("Reverse DNS:");
+                ui.checkbox(&mut self.reverse_dns, "Look up hostnames");
+                ui.end_row();
+                
+                ui.label("Host Discovery:");
                 ui.horizontal(|ui| {
-                    ui.add(egui::DragValue::new(&mut self.port_range.0).clamp_range(1..=65535));
-                    ui.label("to");
-                    ui.add(egui::DragValue::new(&mut self.port_range.1).clamp_range(1..=65535));
+                    ui.checkbox(&mut self.skip_discovery, "Skip (treat all hosts as up)");
+                    ui.add_enabled_ui(!self.skip_discovery, |ui| {
+                        ui.checkbox(&mut self.arp_ping, "ARP");
+                        ui.checkbox(&mut self.echo_ping, "ICMP Echo");
+                        ui.checkbox(&mut self.timestamp_ping, "ICMP Timestamp");
+                    });
+                });
+                ui.end_row();
+                
+                ui.label("Ping Ports:");
+                ui.add_enabled_ui(!self.skip_discovery, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("SYN");
+                        ui.add(egui::TextEdit::singleline(&mut self.syn_ping_ports).desired_width(100.0));
+                        ui.label("ACK");
+                        ui.add(egui::TextEdit::singleline(&mut self.ack_ping_ports).desired_width(100.0));
+                    });
+                });
+                ui.end_row();
+                
+                ui.label("Ports:");
+                ui.horizontal(|ui| {
+                    ui.add(egui::TextEdit::singleline(&mut self.ports).hint_text("22,80,8000-8100,U:53,top 100,http"))
+                        .on_hover_text("Ports, ranges, \"top N\" or service names; T:, U: and S: limit what follows to TCP, UDP or SCTP");
+                    ui.checkbox(&mut self.randomize_ports, "Randomize order");
                 });
                 ui.end_row();
                 
@@ -59,79 +245,677 @@ impl PortScanner {
                         ui.selectable_value(&mut self.scan_type, ScanType::TcpSyn, "TCP SYN");
                         ui.selectable_value(&mut self.scan_type, ScanType::TcpConnect, "TCP Connect");
                         ui.selectable_value(&mut self.scan_type, ScanType::Udp, "UDP");
+                        ui.selectable_value(&mut self.scan_type, ScanType::TcpFin, "TCP FIN");
+                        ui.selectable_value(&mut self.scan_type, ScanType::TcpNull, "TCP NULL");
+                        ui.selectable_value(&mut self.scan_type, ScanType::TcpXmas, "TCP Xmas");
+                        ui.selectable_value(&mut self.scan_type, ScanType::TcpAck, "TCP ACK");
+                        ui.selectable_value(&mut self.scan_type, ScanType::TcpWindow, "TCP Window");
+                        ui.selectable_value(&mut self.scan_type, ScanType::TcpMaimon, "TCP Maimon");
+                        ui.selectable_value(&mut self.scan_type, ScanType::SctpInit, "SCTP INIT");
+                        ui.selectable_value(&mut self.scan_type, ScanType::SctpCookieEcho, "SCTP COOKIE-ECHO");
                     });
                 ui.end_row();
                 
-                ui.label("Threads:");
-                ui.add(egui::DragValue::new(&mut self.threads).clamp_range(1..=1000));
+                ui.label("Service Detection:");
+                ui.checkbox(&mut self.service_detection, "Probe open ports for service and version");
+                ui.end_row();
+                
+                ui.label("OS Detection:");
+                ui.checkbox(&mut self.os_detection, "Fingerprint live hosts (needs root)");
                 ui.end_row();
+                
+                if self.service_detection {
+                    ui.label("Probe Files:");
+                    ui.add(egui::TextEdit::singleline(&mut self.service_probes).hint_text("bundled only"))
+                        .on_hover_text("An nmap-service-probes file, or a directory of them, used alongside the bundled probes");
+                    ui.end_row();
+                }
+                
+                if self.scan_type == ScanType::TcpConnect {
+                    ui.label("Connect Timeout (ms):");
+                    ui.add(egui::DragValue::new(&mut self.connect_timeout_ms).clamp_range(10..=30000));
+                    ui.end_row();
+                    
+                    ui.label("Max Connections:");
+                    ui.add(egui::DragValue::new(&mut self.max_connections).clamp_range(1..=4096));
+                    ui.end_row();
+                } else {
+                    ui.label("Timing:");
+                    egui::ComboBox::from_id_source("timing")
+                        .selected_text(self.timing.to_string())
+                        .show_ui(ui, |ui| {
+                            for template in TimingTemplate::ALL {
+                                ui.selectable_value(&mut self.timing, template, template.to_string());
+                            }
+                        });
+                    ui.end_row();
+                    
+                    ui.label("Max Retries:");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.override_retries, "Override");
+                        if !self.override_retries {
+                            self.max_retries = self.timing.max_retries();
+                        }
+                        ui.add_enabled(
+                            self.override_retries,
+                            egui::DragValue::new(&mut self.max_retries).clamp_range(0..=50),
+                        );
+                    });
+                    ui.end_row();
+                    
+                    ui.label("Probe Window:");
+                    ui.add(egui::DragValue::new(&mut self.max_in_flight).clamp_range(1..=100000))
+                        .on_hover_text("How many probes may await an answer at once");
+                    ui.end_row();
+                    
+                    ui.label("Parallel Hosts:");
+                    ui.add(egui::DragValue::new(&mut self.max_hosts).clamp_range(1..=4096));
+                    ui.end_row();
+                }
             });
         
         ui.separator();
         
         if ui.button("Start Scan").clicked() && !self.scanning {
+            // A fresh start of a repeating scan has nothing to compare with yet,
+            // and repeats whatever is on the form now
+            self.baseline = None;
+            self.next_run = None;
+            self.repeat_config = None;
             self.start_scan();
         }
         
-        ui.add_enabled_ui(self.scanning, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Checkpoint File:");
+            ui.add(egui::TextEdit::singleline(&mut self.checkpoint).hint_text("none"))
+                .on_hover_text("Saved as the scan goes, so it can be resumed after a crash or a stop");
+            let can_resume = !self.scanning && !self.checkpoint.trim().is_empty();
+            if ui
+                .add_enabled(can_resume, egui::Button::new("Resume Scan"))
+                .on_hover_text("Carry on with the scan saved in the checkpoint, skipping the work it records as done")
+                .clicked()
+            {
+                self.resume_scan();
+            }
+        });
+        
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.repeat, "Repeat every")
+                .on_hover_text("Run the scan again and again, comparing each run with the one before");
+            ui.add(egui::DragValue::new(&mut self.repeat_hours).clamp_range(0.1..=720.0).speed(0.1));
+            ui.label("hours, alerting on changes by");
+            ui.checkbox(&mut self.alert_log, "Log");
+            ui.checkbox(&mut self.alert_desktop, "Desktop");
+            ui.add(egui::TextEdit::singleline(&mut self.webhook).hint_text("webhook http://..."));
+        });
+        if !self.repeat {
+            self.next_run = None;
+            self.repeat_config = None;
+        }
+        
+        ui.add_enabled_ui(self.scanning && !self.cancel.is_cancelled(), |ui| {
             if ui.button("Stop Scan").clicked() {
                 self.stop_scan();
             }
         });
         
         ui.label(&self.status);
-        ui.add(egui::ProgressBar::new(self.progress).show_percentage());
+        match &self.progress {
+            Some(progress) => {
+                ui.add(egui::ProgressBar::new(progress.fraction()).text(progress.to_string()));
+            }
+            None => {
+                ui.add(egui::ProgressBar::new(0.0));
+            }
+        }
+        
+        ui.horizontal(|ui| {
+            ui.label("Export:");
+            egui::ComboBox::from_id_source("export_format")
+                .selected_text(self.export_format.to_string())
+                .show_ui(ui, |ui| {
+                    for format in ExportFormat::ALL {
+                        ui.selectable_value(&mut self.export_format, format, format.to_string());
+                    }
+                });
+            ui.add(
+                egui::TextEdit::singleline(&mut self.export_path)
+                    .hint_text(format!("scan.{}", self.export_format.extension())),
+            );
+            let has_results = !self.report.hosts.is_empty();
+            if ui
+                .add_enabled(has_results, egui::Button::new("Export"))
+                .on_hover_text("Write every result so far to the file, whatever the filters show")
+                .clicked()
+            {
+                self.export_results();
+            }
+        });
+        
+        egui::CollapsingHeader::new("Compare Scans")
+            .id_source("compare")
+            .show(ui, |ui| {
+                let mut compare = false;
+                let mut export = false;
+                ui.horizontal(|ui| {
+                    ui.label("Before:");
+                    ui.add(egui::TextEdit::singleline(&mut self.diff_before).hint_text("scan.json"))
+                        .on_hover_text("Results of the earlier scan, exported as JSON");
+                    ui.label("After:");
+                    ui.add(egui::TextEdit::singleline(&mut self.diff_after).hint_text("current results"))
+                        .on_hover_text("Results of the later scan, exported as JSON; leave empty to use the results above");
+                    compare = ui
+                        .add_enabled(!self.diff_before.trim().is_empty(), egui::Button::new("Compare"))
+                        .clicked();
+                });
+                
+                if self.diff.is_some() {
+                    ui.horizontal(|ui| {
+                        ui.label("Export Diff:");
+                        egui::ComboBox::from_id_source("diff_format")
+                            .selected_text(self.diff_format.to_string())
+                            .show_ui(ui, |ui| {
+                                for format in DiffFormat::ALL {
+                                    ui.selectable_value(&mut self.diff_format, format, format.to_string());
+                                }
+                            });
+                        ui.add(
+                            egui::TextEdit::singleline(&mut self.diff_path)
+                                .hint_text(format!("scan-diff.{}", self.diff_format.extension())),
+                        );
+                        export = ui.button("Export").clicked();
+                    });
+                    
+                    egui::ScrollArea::vertical()
+                        .id_source("diff")
+                        .max_height(300.0)
+                        .show(ui, |ui| {
+                            for line in self.diff_text.lines() {
+                                // Red for what the first scan had, green for what the second has
+                                let color = match line.chars().next() {
+                                    Some('-') => egui::Color32::from_rgb(230, 90, 90),
+                                    Some('+') => egui::Color32::from_rgb(90, 200, 90),
+                                    _ => ui.visuals().text_color(),
+                                };
+                                ui.label(egui::RichText::new(line).monospace().color(color));
+                            }
+                            if self.diff.as_ref().is_some_and(ScanDiff::is_empty) {
+                                ui.label("No changes");
+                            }
+                        });
+                }
+                
+                if compare {
+                    self.compare_scans();
+                }
+                if export {
+                    self.export_diff();
+                }
+            });
         
         ui.separator();
         
-        egui::ScrollArea::vertical().show(ui, |ui| {
-            let results = self.results.lock().unwrap();
+        ui.horizontal(|ui| {
+            ui.label(format!("Hosts up: {} of {}", self.report.hosts_up(), self.report.hosts.len()));
+            ui.checkbox(&mut self.show_down, "Show down hosts");
+            ui.separator();
+            ui.label("Show:");
+            egui::ComboBox::from_id_source("state_filter")
+                .selected_text(match self.state_filter {
+                    Some(state) => state.to_string(),
+                    None => "all".to_string(),
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.state_filter, None, "all");
+                    for state in [
+                        PortState::Open,
+                        PortState::Closed,
+                        PortState::Filtered,
+                        PortState::Unfiltered,
+                        PortState::OpenFiltered,
+                    ] {
+                        ui.selectable_value(&mut self.state_filter, Some(state), state.to_string());
+                    }
+                });
+            
+            ui.label("Filter:");
+            ui.text_edit_singleline(&mut self.text_filter)
+                .on_hover_text("Matches addresses and hostnames, or port numbers, services and products");
+        });
+        
+        egui::SidePanel::left("hosts_list")
+            .resizable(true)
+            .default_width(260.0)
+            .show_inside(ui, |ui| self.hosts_list(ui));
+        egui::CentralPanel::default().show_inside(ui, |ui| self.host_details(ui));
+    }
+    
+    // The hosts by address, for picking the one to show in detail.
+    fn hosts_list(&mut self, ui: &mut egui::Ui) {
+        let mut hosts: Vec<&HostResult> = self
+            .report
+            .hosts
+            .iter()
+            .filter(|host| self.show_down || host.status.up)
+            .filter(|host| self.host_matches(host) || host.ports.iter().any(|result| self.port_matches(result)))
+            .collect();
+        hosts.sort_by_key(|host| host.status.address);
+        
+        // Keep the selection on a host that's listed
+        if !hosts.iter().any(|host| Some(host.status.address) == self.selected_host) {
+            self.selected_host = hosts.first().map(|host| host.status.address);
+        }
+        
+        let mut clicked = None;
+        egui::ScrollArea::vertical().id_source("hosts").show(ui, |ui| {
+            for host in hosts {
+                let status = &host.status;
+                let mut text = status.address.to_string();
+                if let Some(hostname) = &status.hostname {
+                    text += &format!(" ({})", hostname);
+                }
+                if status.up {
+                    text += &format!("\n{} open", host.open_ports());
+                    if let Some(os) = &status.os {
+                        text += &format!(", {}", os.family);
+                    }
+                } else {
+                    text += &format!("\ndown, {}", status.reason);
+                }
+                let selected = self.selected_host == Some(status.address);
+                if ui.selectable_label(selected, text).clicked() {
+                    clicked = Some(status.address);
+                }
+            }
+        });
+        if clicked.is_some() {
+            self.selected_host = clicked;
+        }
+    }
+    
+    // Everything known about the selected host, and its port table.
+    fn host_details(&mut self, ui: &mut egui::Ui) {
+        let Some(host) = self.selected_host.and_then(|address| self.report.host(address)) else {
+            ui.label("No hosts to show");
+            return;
+        };
+        let status = &host.status;
+        
+        egui::Grid::new("host_details")
+            .num_columns(2)
+            .spacing([20.0, 4.0])
+            .show(ui, |ui| {
+                ui.strong("Address:");
+                ui.label(status.address.to_string());
+                ui.end_row();
+                
+                if let Some(mac) = status.mac {
+                    ui.strong("MAC Address:");
+                    ui.label(match &status.vendor {
+                        Some(vendor) => format!("{} ({})", mac, vendor),
+                        None => mac.to_string(),
+                    });
+                    ui.end_row();
+                }
+                
+                ui.strong("Hostname:");
+                ui.label(status.hostname.as_deref().unwrap_or("-"));
+                ui.end_row();
+                
+                ui.strong("Status:");
+                ui.label(format!("{}, {}", if status.up { "up" } else { "down" }, status.reason));
+                ui.end_row();
+                
+                ui.strong("Latency:");
+                ui.label(
+                    status
+                        .latency
+                        .map(|latency| format!("{:.2} ms", latency.as_secs_f64() * 1000.0))
+                        .unwrap_or("-".to_string()),
+                );
+                ui.end_row();
+                
+                ui.strong("OS:");
+                ui.label(match &status.os {
+                    Some(os) => format!("{} ({}, {}%)", os.name, os.family, os.confidence),
+                    None => "-".to_string(),
+                });
+                ui.end_row();
+            });
+        
+        ui.separator();
+        
+        // A host picked by its address or name shows all its ports
+        let host_matched = self.host_matches(host);
+        let mut rows: Vec<&PortResult> = host
+            .ports
+            .iter()
+            .filter(|result| self.state_filter.is_none_or(|state| result.state == state))
+            .filter(|result| host_matched || self.port_matches(result))
+            .collect();
+        rows.sort_by(|a, b| {
+            let ordering = compare(self.sort_column, a, b)
+                .then(a.protocol.cmp(&b.protocol))
+                .then(a.port.cmp(&b.port));
+            if self.sort_ascending { ordering } else { ordering.reverse() }
+        });
+        
+        let mut clicked = None;
+        egui::ScrollArea::vertical().id_source("ports").show(ui, |ui| {
             egui::Grid::new("results_grid")
-                .num_columns(3)
+                .num_columns(9)
                 .striped(true)
                 .show(ui, |ui| {
-                    ui.strong("Port");
-                    ui.strong("Protocol");
-                    ui.strong("Status");
+                    for (column, title) in [
+                        (SortColumn::Port, "Port"),
+                        (SortColumn::Protocol, "Protocol"),
+                        (SortColumn::State, "State"),
+                        (SortColumn::Service, "Service"),
+                        (SortColumn::Product, "Product"),
+                        (SortColumn::Version, "Version"),
+                        (SortColumn::Reason, "Reason"),
+                        (SortColumn::Ttl, "TTL"),
+                        (SortColumn::Rtt, "RTT"),
+                    ] {
+                        let sorted = self.sort_column == column;
+                        let title = match (sorted, self.sort_ascending) {
+                            (true, true) => format!("{} ⬆", title),
+                            (true, false) => format!("{} ⬇", title),
+                            _ => title.to_string(),
+                        };
+                        if ui.selectable_label(sorted, egui::RichText::new(title).strong()).clicked() {
+                            clicked = Some(column);
+                        }
+                    }
                     ui.end_row();
                     
-                    for (port, status) in results.iter() {
-                        ui.label(port.to_string());
-                        ui.label(if *port < 1024 { "Well-known" } else { "Registered" });
-                        ui.label(status);
+                    for result in rows {
+                        ui.label(result.port.to_string());
+                        ui.label(result.protocol.to_string());
+                        ui.label(result.state.to_string());
+                        ui.label(&result.service);
+                        ui.label(result.product.as_deref().unwrap_or_default());
+                        ui.label(match (&result.version, &result.info) {
+                            (Some(version), Some(info)) => format!("{} ({})", version, info),
+                            (Some(version), None) => version.clone(),
+                            (None, Some(info)) => format!("({})", info),
+                            (None, None) => String::new(),
+                        });
+                        ui.label(result.reason.to_string());
+                        ui.label(result.ttl.map(|ttl| ttl.to_string()).unwrap_or_default());
+                        ui.label(
+                            result
+                                .rtt
+                                .map(|rtt| format!("{:.2} ms", rtt.as_secs_f64() * 1000.0))
+                                .unwrap_or_default(),
+                        );
                         ui.end_row();
                     }
                 });
         });
+        
+        // Clicking the sorted column again flips the direction
+        if let Some(column) = clicked {
+            if self.sort_column == column {
+                self.sort_ascending = !self.sort_ascending;
+            } else {
+                self.sort_column = column;
+                self.sort_ascending = true;
+            }
+        }
+    }
+    
+    fn host_matches(&self, host: &HostResult) -> bool {
+        self.text_filter.is_empty()
+            || host.status.address.to_string().contains(&self.text_filter)
+            || host.status.hostname.as_ref().is_some_and(|name| name.contains(&self.text_filter))
+    }
+    
+    fn port_matches(&self, result: &PortResult) -> bool {
+        self.text_filter.is_empty()
+            || result.port.to_string().contains(&self.text_filter)
+            || result.service.contains(&self.text_filter)
+            || result.product.as_ref().is_some_and(|product| product.contains(&self.text_filter))
     }
     
     fn start_scan(&mut self) {
+        let (Some(syn_ports), Some(ack_ports)) =
+            (parse_ports(&self.syn_ping_ports), parse_ports(&self.ack_ping_ports))
+        else {
+            self.status = "Invalid ping ports".to_string();
+            return;
+        };
+        
+        let target_file = self.target_file.trim();
+        let service_probes = self.service_probes.trim();
+        let config = ScanConfig {
+            target: self.target.clone(),
+            target_file: (!target_file.is_empty()).then(|| PathBuf::from(target_file)),
+            exclude: self.exclude.clone(),
+            dns_server: self.dns_server.clone(),
+            address_choice: self.address_choice,
+            reverse_dns: self.reverse_dns,
+            discovery: DiscoveryConfig {
+                skip: self.skip_discovery,
+                arp: self.arp_ping,
+                icmp_echo: self.echo_ping,
+                icmp_timestamp: self.timestamp_ping,
+                syn_ports,
+                ack_ports,
+            },
+            ports: self.ports.clone(),
+            randomize_ports: self.randomize_ports,
+            scan_type: self.scan_type,
+            max_in_flight: self.max_in_flight,
+            max_hosts: self.max_hosts,
+            timing: self.timing,
+            max_retries: self.override_retries.then_some(self.max_retries),
+            service_detection: self.service_detection,
+            service_probes: (!service_probes.is_empty()).then(|| PathBuf::from(service_probes)),
+            os_detection: self.os_detection,
+            connect_timeout: Duration::from_millis(self.connect_timeout_ms),
+            max_connections: self.max_connections,
+            checkpoint: checkpoint_path(&self.checkpoint),
+            rate_limit: ratelimit::limit(),
+        };
+        
+        self.launch(&config.clone(), move || NativeScanner::new(&config));
+    }
+    
+    // The checkpoint's own settings are used, not the ones on the form.
+    fn resume_scan(&mut self) {
+        let Some(path) = checkpoint_path(&self.checkpoint) else {
+            return;
+        };
+        let checkpoint = match Checkpoint::load(&path) {
+            Ok(checkpoint) => checkpoint,
+            Err(e) => {
+                self.status = format!("Scan failed: {}", e);
+                return;
+            }
+        };
+        let config = checkpoint.config.clone();
+        self.baseline = None;
+        self.next_run = None;
+        self.repeat_config = None;
+        self.launch(&config, move || NativeScanner::from_checkpoint(&path, checkpoint));
+    }
+    
+    fn launch<F>(&mut self, config: &ScanConfig, scanner: F)
+    where
+        F: FnOnce() -> Result<NativeScanner, ScanError> + Send + 'static,
+    {
         self.scanning = true;
         self.status = "Scanning...".to_string();
-        self.progress = 0.0;
-        self.results.lock().unwrap().clear();
+        if self.repeat {
+            // Editing the form between runs doesn't change what later runs scan,
+            // so every run compares like with like. Every run starts afresh
+            // rather than resuming the last.
+            if self.repeat_config.is_none() {
+                self.repeat_config = Some(ScanConfig { checkpoint: None, ..config.clone() });
+            }
+            let interval = Duration::from_secs_f64(self.repeat_hours * 3600.0);
+            self.next_run = Some(Instant::now() + interval);
+        }
+        self.progress = None;
+        self.report = ScanReport::new(config);
         
-        let (tx, rx) = bounded(100);
+        // Unbounded, so the scan never waits on the UI while another tab is showing
+        let (tx, rx) = unbounded();
         self.receiver = Some(rx);
+        self.cancel = CancelToken::new();
+        let cancel = self.cancel.clone();
         
-        let target = self.target.clone();
-        let port_range = self.port_range;
-        let scan_type = self.scan_type;
-        let threads = self.threads;
-        let results = self.results.clone();
-        let status = self.status.clone();
+        self.handle = Some(thread::spawn(move || {
+            // Bad target specs and checkpoints come back through the handle and
+            // show up in the status
+            let mut scanner = scanner()?;
+            scanner.scan(tx, &cancel)
+        }));
+    }
+    
+    fn poll_results(&mut self) {
+        let Some(receiver) = &self.receiver else {
+            return;
+        };
         
-        thread::spawn(move || {
-            let mut scanner = NativeScanner::new(&target, port_range, scan_type, threads);
-            scanner.scan(tx);
-            
-            let mut results = results.lock().unwrap();
-            *results = scanner.get_results();
-        });
+        let mut finished = false;
+        loop {
+            match receiver.try_recv() {
+                Ok(ScanEvent::Progress(progress)) => self.progress = Some(progress),
+                Ok(event) => self.report.record(&event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    finished = true;
+                    break;
+                }
+            }
+        }
+        
+        if !finished {
+            return;
+        }
+        
+        // The scan thread dropped its sender, so it has finished
+        self.receiver = None;
+        if let Some(handle) = self.handle.take() {
+            match handle.join() {
+                Ok(Ok(())) => {
+                    self.report.finished = Some(SystemTime::now());
+                    self.status = "Scan complete".to_string();
+                    if self.repeat {
+                        self.compare_with_last_run();
+                    }
+                }
+                Ok(Err(ScanError::Cancelled)) => self.status = "Scan stopped".to_string(),
+                Ok(Err(e)) => self.status = format!("Scan failed: {}", e),
+                Err(_) => self.status = "Scan failed".to_string(),
+            }
+        }
+        self.scanning = false;
     }
     
+    // The scan thread notices within a probe or two and reports back as it ends.
+    // Stopping a repeating scan stops the repeats too.
     fn stop_scan(&mut self) {
-        self.scanning = false;
-        self.status = "Scan stopped".to_string();
+        self.cancel.cancel();
+        self.next_run = None;
+        self.status = "Stopping...".to_string();
     }
+    
+    // Alert on what changed since the last run of a repeating scan, showing the
+    // changes in the comparison view.
+    fn compare_with_last_run(&mut self) {
+        if let Some(baseline) = &self.baseline {
+            let diff = ScanDiff::new(baseline, &self.report);
+            if diff.exposure_changed() {
+                let webhook = self.webhook.trim();
+                let alerts = Alerts {
+                    log: self.alert_log,
+                    desktop: self.alert_desktop,
+                    webhook: (!webhook.is_empty()).then(|| webhook.to_string()),
+                };
+                alerts.fire(&diff);
+            }
+            self.status = format!("Scan complete, {}", diff.summary());
+            self.diff_text = diff.to_string();
+            self.diff = Some(diff);
+        }
+        self.baseline = Some(self.report.clone());
+    }
+    
+    fn compare_scans(&mut self) {
+        let load = |path: &str| {
+            ScanReport::load(Path::new(path)).map_err(|e| format!("Error reading {}: {}", path, e))
+        };
+        let before = load(self.diff_before.trim());
+        let after = match self.diff_after.trim() {
+            "" => Ok(None),
+            path => load(path).map(Some),
+        };
+        match (before, after) {
+            (Ok(before), Ok(after)) => {
+                let diff = ScanDiff::new(&before, after.as_ref().unwrap_or(&self.report));
+                self.status = format!("{} hosts changed", diff.hosts.len());
+                self.diff_text = diff.to_string();
+                self.diff = Some(diff);
+            }
+            (Err(e), _) | (_, Err(e)) => self.status = e,
+        }
+    }
+    
+    fn export_diff(&mut self) {
+        let Some(diff) = &self.diff else {
+            return;
+        };
+        let path = match self.diff_path.trim() {
+            "" => format!("scan-diff.{}", self.diff_format.extension()),
+            path => path.to_string(),
+        };
+        self.status = match diff.save(Path::new(&path), self.diff_format) {
+            Ok(()) => format!("Exported diff to {}", path),
+            Err(e) => format!("Export failed: {}", e),
+        };
+    }
+    
+    // Everything found so far, even while the scan is still going.
+    fn export_results(&mut self) {
+        let path = match self.export_path.trim() {
+            "" => format!("scan.{}", self.export_format.extension()),
+            path => path.to_string(),
+        };
+        self.status = match self.report.save(Path::new(&path), self.export_format) {
+            Ok(()) => format!("Exported {} to {}", self.export_format, path),
+            Err(e) => format!("Export failed: {}", e),
+        };
+    }
+}
+
+fn compare(column: SortColumn, a: &PortResult, b: &PortResult) -> Ordering {
+    match column {
+        SortColumn::Port => a.port.cmp(&b.port),
+        SortColumn::Protocol => a.protocol.cmp(&b.protocol),
+        SortColumn::State => a.state.cmp(&b.state),
+        SortColumn::Service => a.service.cmp(&b.service),
+        SortColumn::Product => a.product.cmp(&b.product),
+        SortColumn::Version => a.version.cmp(&b.version),
+        SortColumn::Reason => a.reason.cmp(&b.reason),
+        SortColumn::Ttl => a.ttl.cmp(&b.ttl),
+        SortColumn::Rtt => a.rtt.cmp(&b.rtt),
+    }
+}
+
+fn checkpoint_path(path: &str) -> Option<PathBuf> {
+    let path = path.trim();
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+// Comma separated list of ports, e.g. "80,443".
+fn parse_ports(ports: &str) -> Option<Vec<u16>> {
+    ports
+        .split(',')
+        .map(str::trim)
+        .filter(|port| !port.is_empty())
+        .map(|port| port.parse().ok().filter(|port| *port != 0))
+        .collect()
 }